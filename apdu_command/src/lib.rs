@@ -0,0 +1,374 @@
+//! `#[derive(ApduCommand)]` generates the decoding of an APDU header into an
+//! instruction enum.
+//!
+//! ```ignore
+//! use ledger_device_sdk::ApduCommand;
+//!
+//! #[derive(ApduCommand)]
+//! #[apdu(cla = 0xe0)]
+//! enum Instruction {
+//!     #[apdu(ins = 0x03)]
+//!     GetVersion,
+//!     #[apdu(ins = 0x05, p1 = 0..=1, p2 = 0)]
+//!     GetPubkey,
+//!     #[apdu(ins = 0x06, p1 = 0, p2 = 0x80)]
+//!     SignTxFirst,
+//!     #[apdu(ins = 0x06, p1 = 1..=255)]
+//!     SignTxNext,
+//! }
+//! ```
+//!
+//! The generated `TryFrom<ApduHeader>` implementation returns
+//! `StatusWords::BadCla` if the enum declares a CLA and the header does not
+//! match it, `StatusWords::BadIns` if no variant has the header INS, and
+//! `StatusWords::BadP1P2` if the INS is known but none of the corresponding
+//! variants accepts P1 and P2. Variants are tried in declaration order.
+//!
+//! `ins` and `cla` accept any expression of type `u8`; `p1` and `p2` accept any
+//! pattern and default to `_`. A `SUPPORTED_INS` constant listing the supported
+//! instructions is also generated.
+//!
+//! The generated code refers to `ApduHeader` and `StatusWords` through
+//! `::ledger_device_sdk::io`. Crates using the SDK under another name, or
+//! through a re-export, give its path with `#[apdu(crate = path)]` on the
+//! enum:
+//!
+//! ```ignore
+//! #[derive(ApduCommand)]
+//! #[apdu(crate = ::my_sdk, cla = 0xe0)]
+//! enum Instruction {
+//!     #[apdu(ins = 0x03)]
+//!     GetVersion,
+//! }
+//! ```
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{ToTokens, quote};
+use syn::ext::IdentExt;
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::{
+    Attribute, Data, DeriveInput, Expr, ExprLit, Fields, Ident, Lit, Pat, Path, Token,
+    parse_macro_input,
+};
+
+/// A single `key = value` argument of an `#[apdu(...)]` attribute.
+enum ApduArg {
+    Crate(Path),
+    Cla(Expr),
+    Ins(Expr),
+    P1(TokenStream2),
+    P2(TokenStream2),
+}
+
+/// Parses a pattern, possibly made of several `|`-separated alternatives.
+fn parse_pattern(input: ParseStream) -> syn::Result<TokenStream2> {
+    let cases = Punctuated::<Pat, Token![|]>::parse_separated_nonempty(input)?;
+    Ok(cases.into_token_stream())
+}
+
+impl Parse for ApduArg {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        // `crate` is a keyword, which `Ident::parse` rejects
+        let key = Ident::parse_any(input)?;
+        input.parse::<Token![=]>()?;
+        match key.to_string().as_str() {
+            "crate" => Ok(ApduArg::Crate(input.parse()?)),
+            "cla" => Ok(ApduArg::Cla(input.parse()?)),
+            "ins" => Ok(ApduArg::Ins(input.parse()?)),
+            "p1" => Ok(ApduArg::P1(parse_pattern(input)?)),
+            "p2" => Ok(ApduArg::P2(parse_pattern(input)?)),
+            _ => Err(syn::Error::new(
+                key.span(),
+                "expected one of `crate`, `cla`, `ins`, `p1`, `p2`",
+            )),
+        }
+    }
+}
+
+fn parse_apdu_args(attrs: &[Attribute]) -> syn::Result<Vec<ApduArg>> {
+    let mut args = Vec::new();
+    for attr in attrs.iter().filter(|a| a.path.is_ident("apdu")) {
+        let parsed = attr.parse_args_with(Punctuated::<ApduArg, Token![,]>::parse_terminated)?;
+        args.extend(parsed);
+    }
+    Ok(args)
+}
+
+struct Variant {
+    ident: Ident,
+    ins: Expr,
+    p1: TokenStream2,
+    p2: TokenStream2,
+}
+
+fn parse_variant(variant: &syn::Variant) -> syn::Result<Variant> {
+    if !matches!(variant.fields, Fields::Unit) {
+        return Err(syn::Error::new_spanned(
+            variant,
+            "ApduCommand can only be derived for unit variants",
+        ));
+    }
+    let mut ins = None;
+    let mut p1 = quote!(_);
+    let mut p2 = quote!(_);
+    for arg in parse_apdu_args(&variant.attrs)? {
+        match arg {
+            ApduArg::Ins(e) => ins = Some(e),
+            ApduArg::P1(p) => p1 = p,
+            ApduArg::P2(p) => p2 = p,
+            ApduArg::Cla(e) => {
+                return Err(syn::Error::new_spanned(
+                    e,
+                    "`cla` must be set on the enum, not on a variant",
+                ));
+            }
+            ApduArg::Crate(p) => {
+                return Err(syn::Error::new_spanned(
+                    p,
+                    "`crate` must be set on the enum, not on a variant",
+                ));
+            }
+        }
+    }
+    let ins = ins.ok_or_else(|| {
+        syn::Error::new_spanned(&variant.ident, "missing `#[apdu(ins = ...)]` attribute")
+    })?;
+    Ok(Variant {
+        ident: variant.ident.clone(),
+        ins,
+        p1,
+        p2,
+    })
+}
+
+/// Identifies the instruction of an `ins` expression: integer literals by their
+/// value, so that `0x06` and `6` are the same instruction, and other
+/// expressions (e.g. constants) by their tokens.
+#[derive(PartialEq)]
+enum InsKey {
+    Value(u8),
+    Tokens(String),
+}
+
+impl InsKey {
+    fn new(ins: &Expr) -> Self {
+        if let Expr::Lit(ExprLit {
+            lit: Lit::Int(lit), ..
+        }) = ins
+            && let Ok(value) = lit.base10_parse()
+        {
+            return InsKey::Value(value);
+        }
+        InsKey::Tokens(ins.to_token_stream().to_string())
+    }
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let data = match &input.data {
+        Data::Enum(data) => data,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "ApduCommand can only be derived for enums",
+            ));
+        }
+    };
+
+    let mut cla = None;
+    let mut sdk = quote!(::ledger_device_sdk);
+    for arg in parse_apdu_args(&input.attrs)? {
+        match arg {
+            ApduArg::Crate(p) => sdk = p.into_token_stream(),
+            ApduArg::Cla(e) => cla = Some(e),
+            ApduArg::Ins(e) => {
+                return Err(syn::Error::new_spanned(e, "`ins` must be set on variants"));
+            }
+            ApduArg::P1(p) | ApduArg::P2(p) => {
+                return Err(syn::Error::new_spanned(
+                    p,
+                    "`p1` and `p2` must be set on variants",
+                ));
+            }
+        }
+    }
+
+    let variants = data
+        .variants
+        .iter()
+        .map(parse_variant)
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    // Several variants may share the same INS (e.g. with different P1/P2), only
+    // list it once in the table of supported instructions.
+    let mut keys = Vec::new();
+    let mut supported_ins: Vec<&Expr> = Vec::new();
+    for v in &variants {
+        let key = InsKey::new(&v.ins);
+        if !keys.contains(&key) {
+            keys.push(key);
+            supported_ins.push(&v.ins);
+        }
+    }
+
+    let sdk = quote!(#sdk::io);
+    let cla_check = cla.map(|cla| {
+        quote! {
+            if header.cla != (#cla) {
+                return Err(#sdk::StatusWords::BadCla);
+            }
+        }
+    });
+    let arms = variants.iter().map(|v| {
+        let Variant { ident, ins, p1, p2 } = v;
+        quote! {
+            if header.ins == (#ins) {
+                if matches!(header.p1, #p1) && matches!(header.p2, #p2) {
+                    return Ok(#name::#ident);
+                }
+                known_ins = true;
+            }
+        }
+    });
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::core::convert::TryFrom<#sdk::ApduHeader> for #name #ty_generics #where_clause {
+            type Error = #sdk::StatusWords;
+
+            #[allow(unused_mut, unused_assignments)]
+            fn try_from(header: #sdk::ApduHeader) -> Result<Self, Self::Error> {
+                #cla_check
+                let mut known_ins = false;
+                #(#arms)*
+                if known_ins {
+                    Err(#sdk::StatusWords::BadP1P2)
+                } else {
+                    Err(#sdk::StatusWords::BadIns)
+                }
+            }
+        }
+
+        impl #impl_generics #name #ty_generics #where_clause {
+            /// Instructions supported by this enum, in declaration order.
+            pub const SUPPORTED_INS: &'static [u8] = &[#(#supported_ins),*];
+        }
+    })
+}
+
+#[proc_macro_derive(ApduCommand, attributes(apdu))]
+pub fn derive_apdu_command(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use syn::parse_quote;
+
+    fn expand_err(input: DeriveInput) -> String {
+        expand(input).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn not_an_enum() {
+        let err = expand_err(parse_quote! {
+            struct Ins;
+        });
+        assert_eq!(err, "ApduCommand can only be derived for enums");
+    }
+
+    #[test]
+    fn variant_with_fields() {
+        let err = expand_err(parse_quote! {
+            enum Ins {
+                #[apdu(ins = 0x03)]
+                GetVersion(u8),
+            }
+        });
+        assert_eq!(err, "ApduCommand can only be derived for unit variants");
+    }
+
+    #[test]
+    fn missing_ins() {
+        let err = expand_err(parse_quote! {
+            enum Ins {
+                #[apdu(p1 = 0)]
+                GetVersion,
+            }
+        });
+        assert_eq!(err, "missing `#[apdu(ins = ...)]` attribute");
+    }
+
+    #[test]
+    fn misplaced_arguments() {
+        let err = expand_err(parse_quote! {
+            enum Ins {
+                #[apdu(ins = 0x03, cla = 0xe0)]
+                GetVersion,
+            }
+        });
+        assert_eq!(err, "`cla` must be set on the enum, not on a variant");
+
+        let err = expand_err(parse_quote! {
+            enum Ins {
+                #[apdu(crate = ::my_sdk, ins = 0x03)]
+                GetVersion,
+            }
+        });
+        assert_eq!(err, "`crate` must be set on the enum, not on a variant");
+
+        let err = expand_err(parse_quote! {
+            #[apdu(ins = 0x03)]
+            enum Ins {
+                #[apdu(ins = 0x03)]
+                GetVersion,
+            }
+        });
+        assert_eq!(err, "`ins` must be set on variants");
+
+        let err = expand_err(parse_quote! {
+            #[apdu(p2 = 0)]
+            enum Ins {
+                #[apdu(ins = 0x03)]
+                GetVersion,
+            }
+        });
+        assert_eq!(err, "`p1` and `p2` must be set on variants");
+    }
+
+    #[test]
+    fn invalid_arguments() {
+        let err = expand_err(parse_quote! {
+            enum Ins {
+                #[apdu(ins = 0x03, p3 = 0)]
+                GetVersion,
+            }
+        });
+        assert_eq!(err, "expected one of `crate`, `cla`, `ins`, `p1`, `p2`");
+
+        let err = expand_err(parse_quote! {
+            #[apdu(crate = 0xe0)]
+            enum Ins {
+                #[apdu(ins = 0x03)]
+                GetVersion,
+            }
+        });
+        assert_eq!(err, "expected identifier");
+
+        let err = expand_err(parse_quote! {
+            enum Ins {
+                #[apdu(ins)]
+                GetVersion,
+            }
+        });
+        assert_eq!(err, "expected `=`");
+    }
+}
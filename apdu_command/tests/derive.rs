@@ -0,0 +1,108 @@
+//! Decoding of APDU headers by the generated code, against a mock of the
+//! `io` module of the SDK.
+
+use apdu_command::ApduCommand;
+
+mod sdk {
+    pub mod io {
+        pub struct ApduHeader {
+            pub cla: u8,
+            pub ins: u8,
+            pub p1: u8,
+            pub p2: u8,
+        }
+
+        #[derive(Debug, PartialEq)]
+        pub enum StatusWords {
+            BadCla,
+            BadIns,
+            BadP1P2,
+        }
+    }
+}
+
+use sdk::io::{ApduHeader, StatusWords};
+
+const GET_VERSION: u8 = 0x03;
+
+#[derive(ApduCommand, Debug, PartialEq)]
+#[apdu(crate = crate::sdk, cla = 0xe0)]
+enum Instruction {
+    #[apdu(ins = GET_VERSION)]
+    GetVersion,
+    #[apdu(ins = 0x05, p1 = 0..=1, p2 = 0)]
+    GetPubkey,
+    #[apdu(ins = 0x06, p1 = 0, p2 = 0x80)]
+    SignTxFirst,
+    #[apdu(ins = 0x06, p1 = 1..=255)]
+    SignTxNext,
+    #[apdu(ins = 0x06)]
+    SignTxAny,
+}
+
+#[derive(ApduCommand, Debug, PartialEq)]
+#[apdu(crate = crate::sdk)]
+enum AnyCla {
+    #[apdu(ins = 0x01, p2 = 1 | 3)]
+    Odd,
+}
+
+#[derive(ApduCommand, Debug, PartialEq)]
+#[apdu(crate = crate::sdk)]
+enum SameIns {
+    #[apdu(ins = 0x06, p1 = 0)]
+    Hex,
+    #[apdu(ins = 6, p1 = 1)]
+    Decimal,
+    #[apdu(ins = 0b110)]
+    Binary,
+}
+
+fn decode<T: TryFrom<ApduHeader>>(cla: u8, ins: u8, p1: u8, p2: u8) -> Result<T, T::Error> {
+    T::try_from(ApduHeader { cla, ins, p1, p2 })
+}
+
+#[test]
+fn decode_variants() {
+    assert_eq!(decode(0xe0, 0x03, 0xff, 0xff), Ok(Instruction::GetVersion));
+    assert_eq!(decode(0xe0, 0x05, 1, 0), Ok(Instruction::GetPubkey));
+    assert_eq!(decode(0xe0, 0x06, 0, 0x80), Ok(Instruction::SignTxFirst));
+    assert_eq!(decode(0xe0, 0x06, 2, 0x80), Ok(Instruction::SignTxNext));
+    // Variants are tried in declaration order
+    assert_eq!(decode(0xe0, 0x06, 0, 0), Ok(Instruction::SignTxAny));
+    assert_eq!(decode(0x00, 0x01, 0, 3), Ok(AnyCla::Odd));
+    assert_eq!(decode(0x00, 0x06, 1, 0), Ok(SameIns::Decimal));
+    assert_eq!(decode(0x00, 0x06, 2, 0), Ok(SameIns::Binary));
+}
+
+#[test]
+fn decode_errors() {
+    assert_eq!(
+        decode::<Instruction>(0xe1, 0x03, 0, 0),
+        Err(StatusWords::BadCla)
+    );
+    assert_eq!(
+        decode::<Instruction>(0xe0, 0x04, 0, 0),
+        Err(StatusWords::BadIns)
+    );
+    assert_eq!(
+        decode::<Instruction>(0xe0, 0x05, 2, 0),
+        Err(StatusWords::BadP1P2)
+    );
+    assert_eq!(
+        decode::<Instruction>(0xe0, 0x05, 0, 1),
+        Err(StatusWords::BadP1P2)
+    );
+    assert_eq!(
+        decode::<AnyCla>(0x00, 0x01, 0, 2),
+        Err(StatusWords::BadP1P2)
+    );
+}
+
+#[test]
+fn supported_ins() {
+    assert_eq!(Instruction::SUPPORTED_INS, &[0x03, 0x05, 0x06]);
+    assert_eq!(AnyCla::SUPPORTED_INS, &[0x01]);
+    // Listed once, whatever the notation of the literals
+    assert_eq!(SameIns::SUPPORTED_INS, &[0x06]);
+}
@@ -1,4 +1,5 @@
-fn generate_install_parameters() {
+/// Returns the directory of the application being built.
+fn root_dir() -> std::path::PathBuf {
     // Find the root package directory by looking at OUT_DIR
     // OUT_DIR is something like: /path/to/app/target/nanosplus/debug/build/ledger_device_sdk-xxx/out
     // We need to extract /path/to/app from this
@@ -6,20 +7,24 @@ fn generate_install_parameters() {
     let out_path = std::path::PathBuf::from(&out_dir);
 
     // Navigate up from OUT_DIR to find the root: out -> build-hash -> build -> debug/release -> target-name -> target -> ROOT
-    let root_dir = out_path
+    out_path
         .parent() // Remove /out
         .and_then(|p| p.parent()) // Remove /ledger_device_sdk-xxx
         .and_then(|p| p.parent()) // Remove /build
         .and_then(|p| p.parent()) // Remove /debug or /release
         .and_then(|p| p.parent()) // Remove /nanosplus (target name)
         .and_then(|p| p.parent()) // Remove /target
-        .expect("Could not find root directory from OUT_DIR");
+        .expect("Could not find root directory from OUT_DIR")
+        .to_path_buf()
+}
 
+fn generate_install_parameters() {
+    let root_dir = root_dir();
     println!("cargo:warning=Root directory: {}", root_dir.display());
 
     // Now run cargo metadata from the root directory
     let output = std::process::Command::new("cargo")
-        .current_dir(root_dir)
+        .current_dir(&root_dir)
         .args(&["metadata", "--format-version", "1", "--no-deps"])
         .output()
         .expect("Failed to execute cargo metadata");
@@ -197,7 +202,362 @@ fn generate_install_parameters() {
     .unwrap();
 }
 
+/// Layout (size, alignment) of the types which can be stored in `.nvm_data`.
+/// The storage types must be kept in sync with the `NVM_SIZE` constants of
+/// `src/nvm.rs`.
+struct NvmLayouts<'a> {
+    /// Alignment of `AtomicStorage`.
+    page_size: usize,
+    /// Constants declared in the file of the static.
+    consts: &'a std::collections::HashMap<String, usize>,
+}
+
+fn round_up(size: usize, align: usize) -> usize {
+    size.div_ceil(align) * align
+}
+
+/// Splits `s` at the `sep` characters which are not nested in brackets.
+fn split_top_level(s: &str, sep: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0i32;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '<' | '[' | '(' => depth += 1,
+            '>' | ']' | ')' => depth -= 1,
+            c if c == sep && depth == 0 => {
+                parts.push(s[start..i].trim());
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    parts.push(s[start..].trim());
+    parts
+}
+
+impl NvmLayouts<'_> {
+    fn const_value(&self, expr: &str) -> Option<usize> {
+        let expr = expr
+            .trim()
+            .trim_start_matches('{')
+            .trim_end_matches('}')
+            .trim();
+        let literal = expr
+            .trim_end_matches("usize")
+            .trim_end_matches("u16")
+            .replace('_', "");
+        literal
+            .parse()
+            .ok()
+            .or_else(|| self.consts.get(expr.rsplit("::").next()?).copied())
+    }
+
+    fn aligned(&self, value: (usize, usize)) -> (usize, usize) {
+        (round_up(value.0, 64), 64)
+    }
+
+    fn safe(&self, value: (usize, usize)) -> (usize, usize) {
+        (64 + self.aligned(value).0, 64)
+    }
+
+    fn atomic(&self, value: (usize, usize)) -> (usize, usize) {
+        (
+            round_up(2 * self.safe(value).0, self.page_size),
+            self.page_size,
+        )
+    }
+
+    fn repr_c(&self, fields: &[(usize, usize)]) -> (usize, usize) {
+        let mut offset = 0;
+        let mut align = 1;
+        for field in fields {
+            offset = round_up(offset, field.1) + field.0;
+            align = align.max(field.1);
+        }
+        (round_up(offset, align), align)
+    }
+
+    /// Returns the size and alignment of a type, or None if it is not known.
+    fn layout(&self, ty: &str) -> Option<(usize, usize)> {
+        let ty = ty.trim();
+        if let Some(array) = ty.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+            let parts = split_top_level(array, ';');
+            let elem = self.layout(parts.first()?)?;
+            return Some((elem.0 * self.const_value(parts.get(1)?)?, elem.1));
+        }
+        let (path, args) = match ty.find('<') {
+            Some(i) => (
+                &ty[..i],
+                split_top_level(ty[i + 1..].strip_suffix('>')?, ','),
+            ),
+            None => (ty, Vec::new()),
+        };
+        let arg = |i: usize| args.get(i).and_then(|a| self.layout(a));
+        let const_arg = |i: usize| args.get(i).and_then(|a| self.const_value(a));
+        let layout = match path.rsplit("::").next()?.trim() {
+            "u8" | "i8" | "bool" => (1, 1),
+            "u16" | "i16" => (2, 2),
+            "u32" | "i32" | "f32" | "char" | "usize" | "isize" => (4, 4),
+            "u64" | "i64" | "f64" => (8, 8),
+            "u128" | "i128" => (16, 8),
+            "NVMData" => arg(0)?,
+            "AlignedStorage" => self.aligned(arg(0)?),
+            "SafeStorage" => self.safe(arg(0)?),
+            "AtomicStorage" => self.atomic(arg(0)?),
+            "Collection" => {
+                let n = const_arg(1)?;
+                let order = self.atomic((round_up(4 + 2 * n, 4), 4));
+                let slots = n * self.aligned(arg(0)?).0;
+                (round_up(order.0 + slots, self.page_size), self.page_size)
+            }
+            "SettingsStorage" => self.atomic((2 * const_arg(0)?, 2)),
+            // 8-byte header, followed by the value stored as bytes
+            "Versioned" => (round_up(8 + const_arg(0)?, 8), 8),
+            "VersionedStorage" if args.len() > 3 => arg(3)?,
+            "VersionedStorage" => self.atomic((round_up(8 + const_arg(2)?, 8), 8)),
+            "SecureStorage" => {
+                let storage = self.atomic(self.repr_c(&[(1, 1), (4, 4), arg(0)?, (32, 1)]));
+                // Followed by the `encrypted` flag
+                (round_up(storage.0 + 1, storage.1), storage.1)
+            }
+            "KvStore" => {
+                let (entries, blocks) = (const_arg(0)?, const_arg(1)?);
+                // 38-byte entries, and a wear counter per block
+                let directory = self.atomic((38 * entries + 2 * blocks, 2));
+                let data = 64 * blocks;
+                (round_up(directory.0 + data, self.page_size), self.page_size)
+            }
+            _ => return None,
+        };
+        Some(layout)
+    }
+}
+
+/// Returns the `.rs` files under `dir`.
+fn rust_files(dir: &std::path::Path, files: &mut Vec<std::path::PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            rust_files(&path, files);
+        } else if path.extension().is_some_and(|e| e == "rs") {
+            files.push(path);
+        }
+    }
+}
+
+/// Returns the constants of integer value declared in `source`.
+fn integer_consts(source: &str) -> std::collections::HashMap<String, usize> {
+    source
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            let decl = line
+                .strip_prefix("pub ")
+                .unwrap_or(line)
+                .strip_prefix("const ")?;
+            let (name, rest) = decl.split_once(':')?;
+            let value = rest.split_once('=')?.1.trim().trim_end_matches(';');
+            let value = value
+                .trim_end_matches("usize")
+                .replace('_', "")
+                .parse()
+                .ok()?;
+            Some((name.trim().to_string(), value))
+        })
+        .collect()
+}
+
+/// Returns the name and type of the statics placed in `.nvm_data` in `source`.
+fn nvm_statics(source: &str) -> Vec<(String, String)> {
+    let mut statics = Vec::new();
+    let mut rest = source;
+    while let Some(i) = rest.find("\".nvm_data\"") {
+        let line_start = rest[..i].rfind('\n').map_or(0, |n| n + 1);
+        let is_comment = rest[line_start..i].trim_start().starts_with("//");
+        rest = &rest[i + 1..];
+        if is_comment {
+            continue;
+        }
+        let Some(j) = rest.find("static ") else {
+            break;
+        };
+        let decl = rest[j + "static ".len()..].trim_start();
+        let decl = decl.strip_prefix("mut ").unwrap_or(decl);
+        let Some((name, ty)) = decl.split_once(':') else {
+            continue;
+        };
+        let ty = split_top_level(ty, '=')[0];
+        statics.push((
+            name.trim().to_string(),
+            ty.split_whitespace().collect::<Vec<_>>().join(" "),
+        ));
+    }
+    statics
+}
+
+/// Returns the maximum size of the `.nvm_data` section set with the
+/// `NVM_DATA_LIMIT` environment variable, either as a number of bytes, or as
+/// a comma-separated list of target:bytes pairs (e.g. "nanox: 4096, stax: 8192").
+fn nvm_data_limit(target_os: &str) -> Option<usize> {
+    let raw = std::env::var("NVM_DATA_LIMIT").ok()?;
+    let trimmed = raw.trim();
+    trimmed.parse().ok().or_else(|| {
+        trimmed.split(',').find_map(|entry| {
+            let (k, v) = entry.split_once(':')?;
+            (k.trim() == target_os).then(|| v.trim().parse().ok())?
+        })
+    })
+}
+
+/// Storage types whose layout computed by [`NvmLayouts`] is compared with
+/// their `NVM_SIZE` by the tests of `src/nvm.rs`.
+const NVM_LAYOUT_SAMPLES: &[&str] = &[
+    "AlignedStorage<[u8; 100]>",
+    "SafeStorage<[u8; 100]>",
+    "AtomicStorage<u8>",
+    "AtomicStorage<[u8; 1000]>",
+    "Collection<u32, 10>",
+    "Collection<[u8; 100], 40>",
+    "VersionedStorage<[u32; 2], 2, 16>",
+    "VersionedStorage<[u32; 2], 2, 100, SafeStorage<Versioned<100>>>",
+    "SecureStorage<[u8; 100]>",
+    "KvStore<8, 4>",
+    "crate::settings::SettingsStorage<3>",
+];
+
+/// Writes `nvm_layouts.rs` to `OUT_DIR`: an array of the sample types along
+/// with their `NVM_SIZE` and the size computed by [`NvmLayouts`].
+fn generate_nvm_layouts(page_size: usize) {
+    let consts = std::collections::HashMap::new();
+    let layouts = NvmLayouts {
+        page_size,
+        consts: &consts,
+    };
+    let mut samples = String::from("[\n");
+    for ty in NVM_LAYOUT_SAMPLES {
+        let (size, _) = layouts.layout(ty).unwrap();
+        samples.push_str(&format!("    (\"{ty}\", <{ty}>::NVM_SIZE, {size}),\n"));
+    }
+    samples.push(']');
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    std::fs::write(
+        std::path::Path::new(&out_dir).join("nvm_layouts.rs"),
+        samples,
+    )
+    .unwrap();
+}
+
+/// Lists the statics of the application placed in `.nvm_data`, along with
+/// their size, and fails if the `.nvm_data` section exceeds `NVM_DATA_LIMIT`.
+///
+/// Only the sources of the application are scanned: the statics declared by
+/// its dependencies are not counted, so the reported total is a lower bound
+/// when they use `.nvm_data` too. The size of the statics of other types than
+/// the storages of the SDK is unknown: they are always reported, and the build
+/// fails if `NVM_DATA_LIMIT` is set, as the limit cannot be checked.
+///
+/// Only the total is displayed, unless the `NVM_DATA_REPORT` environment
+/// variable is set. Cargo only displays the warnings of the build scripts of
+/// path and git dependencies. When the SDK comes from a registry, the full
+/// report is still written to `nvm_usage.txt`, next to the application binary
+/// (`target/<target>/<profile>/`), and it is displayed when the build fails
+/// because of `NVM_DATA_LIMIT`.
+fn report_nvm_usage() {
+    println!("cargo:rerun-if-env-changed=NVM_DATA_LIMIT");
+    println!("cargo:rerun-if-env-changed=NVM_DATA_REPORT");
+    let target_os = std::env::var("CARGO_CFG_TARGET_OS").unwrap_or_default();
+    generate_nvm_layouts(if target_os == "nanox" { 256 } else { 512 });
+    let page_size = match target_os.as_str() {
+        "nanox" => 256,
+        "nanosplus" | "stax" | "flex" | "apex_p" => 512,
+        _ => return,
+    };
+
+    let src_dir = root_dir().join("src");
+    if !src_dir.is_dir() {
+        return;
+    }
+    println!("cargo:rerun-if-changed={}", src_dir.display());
+    let mut files = Vec::new();
+    rust_files(&src_dir, &mut files);
+    files.sort();
+
+    let verbose = std::env::var_os("NVM_DATA_REPORT").is_some_and(|v| !v.is_empty());
+    let mut report = String::new();
+    let mut log = |line: String, warn: bool| {
+        if warn || verbose {
+            println!("cargo:warning={line}");
+        }
+        report.push_str(&line);
+        report.push('\n');
+    };
+    let mut total = 0;
+    let mut unknown = 0;
+    for file in files {
+        let Ok(source) = std::fs::read_to_string(&file) else {
+            continue;
+        };
+        let consts = integer_consts(&source);
+        let layouts = NvmLayouts {
+            page_size,
+            consts: &consts,
+        };
+        let file = file.strip_prefix(&src_dir).unwrap_or(&file).display();
+        for (name, ty) in nvm_statics(&source) {
+            match layouts.layout(&ty) {
+                Some((size, align)) => {
+                    total = round_up(total, align) + size;
+                    log(
+                        format!("NVM data: {name} ({file}): {ty}: {size} bytes"),
+                        false,
+                    );
+                }
+                None => {
+                    unknown += 1;
+                    log(
+                        format!("NVM data: {name} ({file}): {ty}: unknown size, not counted"),
+                        true,
+                    );
+                }
+            }
+        }
+    }
+
+    // The linker appends a 4-byte word, and rounds the section to a page
+    let section = round_up(total + 4, page_size);
+    log(
+        format!(
+            "NVM data: {}{total} bytes used, {section} bytes ({} pages) of .nvm_data",
+            if unknown > 0 { "at least " } else { "" },
+            section / page_size
+        ),
+        true,
+    );
+
+    // OUT_DIR is target/<target>/<profile>/build/ledger_device_sdk-xxx/out
+    let out_dir = std::path::PathBuf::from(std::env::var("OUT_DIR").unwrap());
+    if let Some(profile_dir) = out_dir.ancestors().nth(3) {
+        let _ = std::fs::write(profile_dir.join("nvm_usage.txt"), &report);
+    }
+
+    if let Some(limit) = nvm_data_limit(&target_os) {
+        assert!(
+            unknown == 0,
+            "NVM_DATA_LIMIT cannot be checked: the size of {unknown} .nvm_data statics is unknown\n{report}"
+        );
+        assert!(
+            section <= limit,
+            "The .nvm_data section takes {section} bytes, more than NVM_DATA_LIMIT ({limit} bytes) for target {target_os}\n{report}"
+        );
+    }
+}
+
 fn main() {
     println!("cargo:rerun-if-changed=Cargo.toml");
     generate_install_parameters();
+    report_nvm_usage();
 }
@@ -1,20 +1,16 @@
 use ledger_secure_sdk_sys::{const_cstr, infos::str_to_bytes};
 
-const_cstr!(
-    ELF_APP_NAME,
-    "ledger.app_name",
-    option_env!("APP_NAME").unwrap_or("SDK Rust App")
-);
-const_cstr!(
-    ELF_APP_VERSION,
-    "ledger.app_version",
-    option_env!("APP_VERSION").unwrap_or("0.0.0")
-);
-const_cstr!(
-    ELF_APP_FLAGS,
-    "ledger.app_flags",
-    option_env!("APP_FLAGS").unwrap_or("0")
-);
+/// Application metadata, as filled by the build script from the `package.metadata.ledger`
+/// section of the application manifest.
+pub(crate) const APP_NAME: &str = option_env!("APP_NAME").unwrap_or("SDK Rust App");
+pub(crate) const APP_VERSION: &str = option_env!("APP_VERSION").unwrap_or("0.0.0");
+pub(crate) const APP_FLAGS: &str = option_env!("APP_FLAGS").unwrap_or("0");
+/// Version of this SDK.
+pub(crate) const SDK_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+const_cstr!(ELF_APP_NAME, "ledger.app_name", APP_NAME);
+const_cstr!(ELF_APP_VERSION, "ledger.app_version", APP_VERSION);
+const_cstr!(ELF_APP_FLAGS, "ledger.app_flags", APP_FLAGS);
 
 #[used]
 #[unsafe(no_mangle)]
@@ -0,0 +1,296 @@
+//! Symmetric encryption: AES (CBC, CTR and GCM modes) and ChaCha20-Poly1305.
+//!
+//! Keys are held in typed objects which are erased from memory when dropped.
+//! Authenticated encryption algorithms implement the [`AeadInit`] trait, which
+//! provides both streaming operations and one-shot `seal`/`open` methods:
+//!
+//! ```
+//! use ledger_device_sdk::cipher::{AeadInit, TAG_LEN, aes::{AesGcm, AesKey}};
+//!
+//! let key = AesKey::new(&raw_key)?;
+//! let mut tag = [0u8; TAG_LEN];
+//! AesGcm::new(&key)?.seal(&nonce, &aad, &plaintext, &mut ciphertext, &mut tag)?;
+//! AesGcm::new(&key)?.open(&nonce, &aad, &ciphertext, &mut plaintext, &tag)?;
+//! ```
+
+use crate::ecc::CxError;
+use crate::hmac::{HMACError, HMACInit, mac_eq, sha2::Sha2_256};
+use zeroize::Zeroize;
+
+pub mod aes;
+pub mod chacha20_poly1305;
+
+/// Size of the authentication tags of the AEAD algorithms.
+pub const TAG_LEN: usize = 16;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum CipherError {
+    /// The key, nonce or IV has an invalid size or value.
+    InvalidParameter,
+    /// The input length is not supported by the mode, or the output buffer is
+    /// too small.
+    InvalidLength,
+    /// The authentication tag does not match the data.
+    AuthenticationFailed,
+    InternalError,
+}
+
+impl From<CxError> for CipherError {
+    fn from(e: CxError) -> CipherError {
+        match e {
+            CxError::InvalidParameter
+            | CxError::InvalidParameterSize
+            | CxError::InvalidParameterValue => CipherError::InvalidParameter,
+            _ => CipherError::InternalError,
+        }
+    }
+}
+
+impl From<u32> for CipherError {
+    fn from(x: u32) -> CipherError {
+        CxError::from(x).into()
+    }
+}
+
+impl From<CipherError> for u32 {
+    fn from(e: CipherError) -> u32 {
+        e as u32
+    }
+}
+
+/// Direction of a cipher operation.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Operation {
+    Encrypt,
+    Decrypt,
+}
+
+/// Erases a C context, which may contain keys or key-dependent data.
+fn zeroize_ctx<T>(ctx: &mut T) {
+    // Safety: the C contexts are plain data
+    unsafe { core::slice::from_raw_parts_mut(ctx as *mut T as *mut u8, core::mem::size_of::<T>()) }
+        .zeroize();
+}
+
+/// XORs `data` with the keystream `HMAC-SHA256(key, counter || block index)`,
+/// block indexes being big-endian `u32` starting at `first_block`.
+///
+/// This is the stream cipher of the secure session of `io_new` and of the
+/// secure NVM storage. Fails if the block index overflows.
+pub(crate) fn apply_hmac_keystream(
+    key: &[u8],
+    counter: u32,
+    first_block: u32,
+    data: &mut [u8],
+) -> Result<(), HMACError> {
+    let mut keystream = [0u8; 32];
+    let res = data
+        .chunks_mut(keystream.len())
+        .enumerate()
+        .try_for_each(|(i, chunk)| {
+            let block = u32::try_from(i)
+                .ok()
+                .and_then(|i| first_block.checked_add(i))
+                .ok_or(HMACError::InvalidParameter)?;
+            let mut input = [0u8; 8];
+            input[..4].copy_from_slice(&counter.to_be_bytes());
+            input[4..].copy_from_slice(&block.to_be_bytes());
+            Sha2_256::new(key).hmac(&input, &mut keystream)?;
+            for (b, k) in chunk.iter_mut().zip(keystream.iter()) {
+                *b ^= k;
+            }
+            Ok(())
+        });
+    keystream.zeroize();
+    res
+}
+
+/// Defines the behavior of a rust AEAD (authenticated encryption with
+/// associated data) object.
+/// The implementation for a given algorithm is done using a rust macro
+/// to avoid code duplication since only the C structures and functions
+/// imported from the C SDK change.
+pub trait AeadInit: Sized {
+    type Key;
+
+    /// Creates the AEAD object, and sets its key.
+    fn new(key: &Self::Key) -> Result<Self, CipherError>;
+
+    /// Starts the encryption or decryption of a message with the given nonce.
+    fn start(&mut self, operation: Operation, nonce: &[u8]) -> Result<(), CipherError>;
+
+    /// Authenticates additional data, which is not encrypted.
+    /// This method should be called before any call to `update`.
+    fn update_aad(&mut self, aad: &[u8]) -> Result<(), CipherError>;
+
+    /// Encrypts or decrypts `input` into `output`, which must have the same length.
+    /// This method may be called as many times needed.
+    fn update(&mut self, input: &[u8], output: &mut [u8]) -> Result<(), CipherError>;
+
+    /// Computes the authentication tag of the message.
+    fn finish(&mut self, tag: &mut [u8; TAG_LEN]) -> Result<(), CipherError>;
+
+    /// Computes the authentication tag of the message, and compares it with `tag`
+    /// in constant time.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`CipherError::AuthenticationFailed`] if the tags differ.
+    fn check_tag(&mut self, tag: &[u8; TAG_LEN]) -> Result<(), CipherError> {
+        let mut expected = [0u8; TAG_LEN];
+        self.finish(&mut expected)?;
+        if !mac_eq(&expected, tag) {
+            return Err(CipherError::AuthenticationFailed);
+        }
+        Ok(())
+    }
+
+    /// Encrypts `plaintext` into `ciphertext`, which must have the same
+    /// length, and computes the authentication tag of the ciphertext and of
+    /// `aad`.
+    fn seal(
+        &mut self,
+        nonce: &[u8],
+        aad: &[u8],
+        plaintext: &[u8],
+        ciphertext: &mut [u8],
+        tag: &mut [u8; TAG_LEN],
+    ) -> Result<(), CipherError> {
+        self.start(Operation::Encrypt, nonce)?;
+        self.update_aad(aad)?;
+        self.update(plaintext, ciphertext)?;
+        self.finish(tag)
+    }
+
+    /// Checks the authentication tag of `ciphertext` and `aad`, and decrypts
+    /// `ciphertext` into `plaintext`, which must have the same length.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`CipherError::AuthenticationFailed`] if the tag is not
+    /// valid. `plaintext` is then erased.
+    fn open(
+        &mut self,
+        nonce: &[u8],
+        aad: &[u8],
+        ciphertext: &[u8],
+        plaintext: &mut [u8],
+        tag: &[u8; TAG_LEN],
+    ) -> Result<(), CipherError> {
+        self.start(Operation::Decrypt, nonce)?;
+        self.update_aad(aad)?;
+        self.update(ciphertext, plaintext)?;
+        self.check_tag(tag).inspect_err(|_| plaintext.zeroize())
+    }
+}
+
+/// This macro can be used to implement the AeadInit trait for a given
+/// algorithm by providing the structure name, the key type, the C context
+/// name, and the C functions of the algorithm.
+macro_rules! impl_aead {
+    (
+        $typename:ident,
+        $keytype:ty,
+        $ctxname:ident,
+        $initfname:ident,
+        $setkeyfname:ident,
+        $startfname:ident,
+        $aadfname:ident,
+        $updatefname:ident,
+        $finishfname:ident
+    ) => {
+        #[derive(Default)]
+        pub struct $typename {
+            ctx: $ctxname,
+        }
+
+        impl AeadInit for $typename {
+            type Key = $keytype;
+
+            fn new(key: &$keytype) -> Result<Self, CipherError> {
+                let mut aead: $typename = Default::default();
+                let raw = key.as_bytes();
+                unsafe { $initfname(&mut aead.ctx) };
+                let err = unsafe { $setkeyfname(&mut aead.ctx, raw.as_ptr(), raw.len()) };
+                if err != CX_OK {
+                    return Err(err.into());
+                }
+                Ok(aead)
+            }
+
+            fn start(&mut self, operation: Operation, nonce: &[u8]) -> Result<(), CipherError> {
+                let mode = match operation {
+                    Operation::Encrypt => CX_ENCRYPT,
+                    Operation::Decrypt => CX_DECRYPT,
+                };
+                let err = unsafe { $startfname(&mut self.ctx, mode, nonce.as_ptr(), nonce.len()) };
+                if err != CX_OK {
+                    return Err(err.into());
+                }
+                Ok(())
+            }
+
+            fn update_aad(&mut self, aad: &[u8]) -> Result<(), CipherError> {
+                let err = unsafe { $aadfname(&mut self.ctx, aad.as_ptr(), aad.len()) };
+                if err != CX_OK {
+                    return Err(err.into());
+                }
+                Ok(())
+            }
+
+            fn update(&mut self, input: &[u8], output: &mut [u8]) -> Result<(), CipherError> {
+                if input.len() != output.len() {
+                    return Err(CipherError::InvalidLength);
+                }
+                let err = unsafe {
+                    $updatefname(
+                        &mut self.ctx,
+                        input.as_ptr(),
+                        output.as_mut_ptr(),
+                        input.len(),
+                    )
+                };
+                if err != CX_OK {
+                    return Err(err.into());
+                }
+                Ok(())
+            }
+
+            fn finish(&mut self, tag: &mut [u8; TAG_LEN]) -> Result<(), CipherError> {
+                let err = unsafe { $finishfname(&mut self.ctx, tag.as_mut_ptr(), tag.len()) };
+                if err != CX_OK {
+                    return Err(err.into());
+                }
+                Ok(())
+            }
+        }
+
+        /// Cleanup the context, which holds the key, when dropping this structure.
+        impl Drop for $typename {
+            fn drop(&mut self) {
+                $crate::cipher::zeroize_ctx(&mut self.ctx);
+            }
+        }
+    };
+}
+pub(crate) use impl_aead;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_eq_err as assert_eq;
+    use crate::testing::TestType;
+    use testmacro::test_item as test;
+
+    #[test]
+    fn cipher_error_mapping() {
+        assert_eq!(
+            CipherError::from(CxError::InvalidParameterSize),
+            CipherError::InvalidParameter
+        );
+        assert_eq!(
+            CipherError::from(CxError::NotInvertible),
+            CipherError::InternalError
+        );
+    }
+}
@@ -0,0 +1,277 @@
+//! AES in CBC, CTR and GCM modes.
+use super::{AeadInit, CipherError, Operation, TAG_LEN, impl_aead};
+use ledger_secure_sdk_sys::{
+    CX_DECRYPT, CX_ENCRYPT, CX_OK, cx_aes_dec_block, cx_aes_enc_block, cx_aes_gcm_context_t,
+    cx_aes_gcm_finish, cx_aes_gcm_init, cx_aes_gcm_set_key, cx_aes_gcm_start, cx_aes_gcm_update,
+    cx_aes_gcm_update_aad, cx_aes_init_key_no_throw, cx_aes_key_t,
+};
+use zeroize::Zeroize;
+
+/// Size of an AES block, and of the IVs of the CBC and CTR modes.
+pub const AES_BLOCK_LEN: usize = 16;
+
+/// AES key of 128, 192 or 256 bits.
+pub struct AesKey {
+    key: cx_aes_key_t,
+}
+
+impl AesKey {
+    /// # Errors
+    ///
+    /// Fails with [`CipherError::InvalidParameter`] if `key` is not 16, 24 or
+    /// 32 bytes long.
+    pub fn new(key: &[u8]) -> Result<AesKey, CipherError> {
+        if !matches!(key.len(), 16 | 24 | 32) {
+            return Err(CipherError::InvalidParameter);
+        }
+        let mut aes_key = AesKey {
+            key: cx_aes_key_t::default(),
+        };
+        let err = unsafe { cx_aes_init_key_no_throw(key.as_ptr(), key.len(), &mut aes_key.key) };
+        if err != CX_OK {
+            return Err(err.into());
+        }
+        Ok(aes_key)
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        &self.key.keys[..self.key.size]
+    }
+
+    fn encrypt_block(&self, block: &mut [u8; AES_BLOCK_LEN]) -> Result<(), CipherError> {
+        let input = *block;
+        let err = unsafe { cx_aes_enc_block(&self.key, input.as_ptr(), block.as_mut_ptr()) };
+        if err != CX_OK {
+            return Err(err.into());
+        }
+        Ok(())
+    }
+
+    fn decrypt_block(&self, block: &mut [u8; AES_BLOCK_LEN]) -> Result<(), CipherError> {
+        let input = *block;
+        let err = unsafe { cx_aes_dec_block(&self.key, input.as_ptr(), block.as_mut_ptr()) };
+        if err != CX_OK {
+            return Err(err.into());
+        }
+        Ok(())
+    }
+}
+
+/// Cleanup the key from memory when dropping this structure.
+impl Drop for AesKey {
+    #[inline(never)]
+    fn drop(&mut self) {
+        self.key.keys.zeroize();
+        self.key.size = 0;
+    }
+}
+
+/// Streaming AES-CBC encryption or decryption, without padding.
+pub struct AesCbc<'a> {
+    key: &'a AesKey,
+    operation: Operation,
+    /// Previous ciphertext block.
+    chain: [u8; AES_BLOCK_LEN],
+}
+
+impl<'a> AesCbc<'a> {
+    pub fn new(key: &'a AesKey, operation: Operation, iv: &[u8; AES_BLOCK_LEN]) -> AesCbc<'a> {
+        AesCbc {
+            key,
+            operation,
+            chain: *iv,
+        }
+    }
+
+    /// Encrypts or decrypts `data` in place. This method may be called as many
+    /// times needed.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`CipherError::InvalidLength`] if the length of `data` is
+    /// not a multiple of [`AES_BLOCK_LEN`].
+    pub fn update(&mut self, data: &mut [u8]) -> Result<(), CipherError> {
+        if data.len() % AES_BLOCK_LEN != 0 {
+            return Err(CipherError::InvalidLength);
+        }
+        let mut block = [0u8; AES_BLOCK_LEN];
+        for chunk in data.chunks_exact_mut(AES_BLOCK_LEN) {
+            block.copy_from_slice(chunk);
+            match self.operation {
+                Operation::Encrypt => {
+                    block
+                        .iter_mut()
+                        .zip(self.chain.iter())
+                        .for_each(|(b, c)| *b ^= c);
+                    self.key.encrypt_block(&mut block)?;
+                    self.chain = block;
+                }
+                Operation::Decrypt => {
+                    let ciphertext = block;
+                    self.key.decrypt_block(&mut block)?;
+                    block
+                        .iter_mut()
+                        .zip(self.chain.iter())
+                        .for_each(|(b, c)| *b ^= c);
+                    self.chain = ciphertext;
+                }
+            }
+            chunk.copy_from_slice(&block);
+        }
+        block.zeroize();
+        Ok(())
+    }
+}
+
+/// Streaming AES-CTR encryption and decryption, which are the same operation.
+///
+/// The counter is the whole 128-bit block, incremented as a big-endian integer.
+pub struct AesCtr<'a> {
+    key: &'a AesKey,
+    counter: [u8; AES_BLOCK_LEN],
+    keystream: [u8; AES_BLOCK_LEN],
+    /// Number of bytes of `keystream` already used.
+    used: usize,
+}
+
+impl<'a> AesCtr<'a> {
+    pub fn new(key: &'a AesKey, iv: &[u8; AES_BLOCK_LEN]) -> AesCtr<'a> {
+        AesCtr {
+            key,
+            counter: *iv,
+            keystream: [0; AES_BLOCK_LEN],
+            used: AES_BLOCK_LEN,
+        }
+    }
+
+    /// Encrypts or decrypts `data` in place. This method may be called as many
+    /// times needed, with any length.
+    pub fn apply_keystream(&mut self, data: &mut [u8]) -> Result<(), CipherError> {
+        for byte in data.iter_mut() {
+            if self.used == AES_BLOCK_LEN {
+                self.keystream = self.counter;
+                self.key.encrypt_block(&mut self.keystream)?;
+                for c in self.counter.iter_mut().rev() {
+                    *c = c.wrapping_add(1);
+                    if *c != 0 {
+                        break;
+                    }
+                }
+                self.used = 0;
+            }
+            *byte ^= self.keystream[self.used];
+            self.used += 1;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for AesCtr<'_> {
+    fn drop(&mut self) {
+        self.keystream.zeroize();
+    }
+}
+
+impl_aead!(
+    AesGcm,
+    AesKey,
+    cx_aes_gcm_context_t,
+    cx_aes_gcm_init,
+    cx_aes_gcm_set_key,
+    cx_aes_gcm_start,
+    cx_aes_gcm_update_aad,
+    cx_aes_gcm_update,
+    cx_aes_gcm_finish
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_eq_err as assert_eq;
+    use crate::testing::{TestType, hex};
+    use testmacro::test_item as test;
+
+    // NIST SP 800-38A, F.2.1 and F.5.1
+    const KEY: &str = "2b7e151628aed2a6abf7158809cf4f3c";
+    const PLAINTEXT: &str = "6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e51";
+
+    #[test]
+    fn aes_cbc() {
+        let key = AesKey::new(&hex::<16>(KEY)).unwrap();
+        let iv = hex::<16>("000102030405060708090a0b0c0d0e0f");
+        let ciphertext =
+            hex::<32>("7649abac8119b246cee98e9b12e9197d5086cb9b507219ee95db113a917678b2");
+        let mut data = hex::<32>(PLAINTEXT);
+        let mut cbc = AesCbc::new(&key, Operation::Encrypt, &iv);
+        assert_eq!(cbc.update(&mut data[..16]), Ok(()));
+        assert_eq!(cbc.update(&mut data[16..]), Ok(()));
+        assert_eq!(data, ciphertext);
+        assert_eq!(cbc.update(&mut data[..15]), Err(CipherError::InvalidLength));
+
+        let mut cbc = AesCbc::new(&key, Operation::Decrypt, &iv);
+        assert_eq!(cbc.update(&mut data), Ok(()));
+        assert_eq!(data, hex::<32>(PLAINTEXT));
+    }
+
+    #[test]
+    fn aes_ctr() {
+        let key = AesKey::new(&hex::<16>(KEY)).unwrap();
+        let iv = hex::<16>("f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff");
+        let mut data = hex::<32>(PLAINTEXT);
+        let mut ctr = AesCtr::new(&key, &iv);
+        // Chunks not aligned on blocks
+        assert_eq!(ctr.apply_keystream(&mut data[..5]), Ok(()));
+        assert_eq!(ctr.apply_keystream(&mut data[5..21]), Ok(()));
+        assert_eq!(ctr.apply_keystream(&mut data[21..]), Ok(()));
+        assert_eq!(
+            data,
+            hex::<32>("874d6191b620e3261bef6864990db6ce9806f66b7970fdff8617187bb9fffdff")
+        );
+        assert_eq!(AesCtr::new(&key, &iv).apply_keystream(&mut data), Ok(()));
+        assert_eq!(data, hex::<32>(PLAINTEXT));
+
+        assert_eq!(
+            AesKey::new(&[0; 20]).err(),
+            Some(CipherError::InvalidParameter)
+        );
+    }
+
+    #[test]
+    fn aes_gcm() {
+        // GCM specification, test case 4
+        let key = AesKey::new(&hex::<16>("feffe9928665731c6d6a8f9467308308")).unwrap();
+        let nonce = hex::<12>("cafebabefacedbaddecaf888");
+        let aad = hex::<20>("feedfacedeadbeeffeedfacedeadbeefabaddad2");
+        let plaintext = hex::<60>(
+            "d9313225f88406e5a55909c5aff5269a86a7a9531534f7da2e4c303d8a318a721c3c0c95956809532fcf0e2449a6b525b16aedf5aa0de657ba637b39",
+        );
+        let expected = hex::<60>(
+            "42831ec2217774244b7221b784d0d49ce3aa212f2c02a4e035c17e2329aca12e21d514b25466931c7d8f6a5aac84aa051ba30b396a0aac973d58e091",
+        );
+        let expected_tag = hex::<16>("5bc94fbc3221a5db94fae95ae7121a47");
+
+        let mut ciphertext = [0u8; 60];
+        let mut tag = [0u8; TAG_LEN];
+        let mut gcm = AesGcm::new(&key).unwrap();
+        assert_eq!(
+            gcm.seal(&nonce, &aad, &plaintext, &mut ciphertext, &mut tag),
+            Ok(())
+        );
+        assert_eq!(ciphertext, expected);
+        assert_eq!(tag, expected_tag);
+
+        let mut decrypted = [0u8; 60];
+        assert_eq!(
+            gcm.open(&nonce, &aad, &ciphertext, &mut decrypted, &tag),
+            Ok(())
+        );
+        assert_eq!(decrypted, plaintext);
+
+        tag[0] ^= 1;
+        assert_eq!(
+            gcm.open(&nonce, &aad, &ciphertext, &mut decrypted, &tag),
+            Err(CipherError::AuthenticationFailed)
+        );
+        assert_eq!(decrypted, [0u8; 60]);
+    }
+}
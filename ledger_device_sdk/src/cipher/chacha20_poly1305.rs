@@ -0,0 +1,98 @@
+//! ChaCha20-Poly1305 authenticated encryption, as defined in RFC 8439.
+use super::{AeadInit, CipherError, Operation, TAG_LEN, impl_aead};
+use ledger_secure_sdk_sys::{
+    CX_DECRYPT, CX_ENCRYPT, CX_OK, cx_chachapoly_context_t, cx_chachapoly_finish,
+    cx_chachapoly_init, cx_chachapoly_set_key, cx_chachapoly_start, cx_chachapoly_update,
+    cx_chachapoly_update_aad,
+};
+use zeroize::Zeroize;
+
+/// Size of a ChaCha20-Poly1305 key.
+pub const CHACHA20_POLY1305_KEY_LEN: usize = 32;
+/// Size of a ChaCha20-Poly1305 nonce.
+pub const CHACHA20_POLY1305_NONCE_LEN: usize = 12;
+
+/// ChaCha20-Poly1305 key.
+pub struct ChaCha20Poly1305Key([u8; CHACHA20_POLY1305_KEY_LEN]);
+
+impl ChaCha20Poly1305Key {
+    pub fn new(key: &[u8; CHACHA20_POLY1305_KEY_LEN]) -> ChaCha20Poly1305Key {
+        ChaCha20Poly1305Key(*key)
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Cleanup the key from memory when dropping this structure.
+impl Drop for ChaCha20Poly1305Key {
+    #[inline(never)]
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl_aead!(
+    ChaCha20Poly1305,
+    ChaCha20Poly1305Key,
+    cx_chachapoly_context_t,
+    cx_chachapoly_init,
+    cx_chachapoly_set_key,
+    cx_chachapoly_start,
+    cx_chachapoly_update_aad,
+    cx_chachapoly_update,
+    cx_chachapoly_finish
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_eq_err as assert_eq;
+    use crate::testing::{TestType, hex};
+    use testmacro::test_item as test;
+
+    #[test]
+    fn chacha20_poly1305() {
+        // RFC 8439, section 2.8.2
+        let mut raw_key = [0u8; CHACHA20_POLY1305_KEY_LEN];
+        for (i, b) in raw_key.iter_mut().enumerate() {
+            *b = 0x80 + i as u8;
+        }
+        let key = ChaCha20Poly1305Key::new(&raw_key);
+        let nonce = hex::<CHACHA20_POLY1305_NONCE_LEN>("070000004041424344454647");
+        let aad = hex::<12>("50515253c0c1c2c3c4c5c6c7");
+        let plaintext: &[u8; 114] = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+        let expected = hex::<114>(
+            "d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d63dbea45e8ca9671282fafb69da92728b1a71de0a9e060b2905d6a5b67ecd3b3692ddbd7f2d778b8c9803aee328091b58fab324e4fad675945585808b4831d7bc3ff4def08e4b7a9de576d26586cec64b6116",
+        );
+        let expected_tag = hex::<TAG_LEN>("1ae10b594f09e26a7e902ecbd0600691");
+
+        // Streaming encryption
+        let mut aead = ChaCha20Poly1305::new(&key).unwrap();
+        let mut ciphertext = [0u8; 114];
+        let mut tag = [0u8; TAG_LEN];
+        assert_eq!(aead.start(Operation::Encrypt, &nonce), Ok(()));
+        assert_eq!(aead.update_aad(&aad), Ok(()));
+        assert_eq!(aead.update(&plaintext[..64], &mut ciphertext[..64]), Ok(()));
+        assert_eq!(aead.update(&plaintext[64..], &mut ciphertext[64..]), Ok(()));
+        assert_eq!(aead.finish(&mut tag), Ok(()));
+        assert_eq!(ciphertext, expected);
+        assert_eq!(tag, expected_tag);
+
+        let mut decrypted = [0u8; 114];
+        assert_eq!(
+            aead.open(&nonce, &aad, &ciphertext, &mut decrypted, &tag),
+            Ok(())
+        );
+        assert_eq!(&decrypted, plaintext);
+        assert_eq!(
+            aead.open(&nonce, &aad[1..], &ciphertext, &mut decrypted, &tag),
+            Err(CipherError::AuthenticationFailed)
+        );
+        assert_eq!(
+            aead.update(&plaintext[..4], &mut ciphertext[..5]),
+            Err(CipherError::InvalidLength)
+        );
+    }
+}
@@ -7,6 +7,10 @@ pub mod math;
 pub use math::*;
 pub mod montgomery;
 pub use montgomery::*;
+pub mod public_key;
+pub use public_key::*;
+pub mod signature;
+pub use signature::*;
 pub mod weierstrass;
 pub use weierstrass::*;
 
@@ -296,43 +300,92 @@ impl<const P: usize, const TY: char> From<ECPublicKey<P, TY>> for [u8; P] {
     }
 }
 
-/// Wrapper for 'os_perso_derive_node_bip32'
+/// Wrapper for 'sys_hdkey_derive'
 ///
-/// Checks consistency of curve choice and key length
-/// in order to prevent the underlying syscall from throwing
+/// Derives the node at `path` with the derivation scheme `mode`, from the
+/// device seed or from `seed` if given, and fills `key` and the chain code
+/// `cc` if any. The expected length of `key` depends on the scheme: 64 bytes
+/// for BIP32 and SLIP-10 (twice the size of the key on larger curves), 128
+/// bytes for ZIP32 Sapling and 32 bytes for ZIP32 Orchard. The curves
+/// supported by each scheme are defined by the OS, which rejects the other
+/// ones.
+///
+/// SLIP-21 paths are made of labels rather than indices: use
+/// [`crate::kdf::slip21_derive`] instead.
+pub fn hdkey_derive(
+    mode: HDKeyDeriveMode,
+    curve: CurvesId,
+    path: &[u32],
+    key: &mut [u8],
+    cc: Option<&mut ChainCode>,
+    seed: Option<&[u8]>,
+) -> Result<(), CxError> {
+    let curve = match mode {
+        HDKeyDeriveMode::Slip21 => return Err(CxError::InvalidParameter),
+        // Orchard keys are not bound to a curve
+        HDKeyDeriveMode::Zip32Orchard => CX_CURVE_NONE,
+        _ => curve as cx_curve_t,
+    };
+    let (cc_ptr, cc_len) = match cc {
+        Some(cc) => (cc.value.as_mut_ptr(), cc.value.len()),
+        None => (core::ptr::null_mut(), 0usize),
+    };
+    let (seed_ptr, seed_len) = match seed {
+        Some(s) => (s.as_ptr() as *mut u8, s.len()),
+        None => (core::ptr::null_mut(), 0usize),
+    };
+    let err = unsafe {
+        sys_hdkey_derive(
+            mode as u8,
+            curve,
+            path.as_ptr(),
+            path.len(),
+            key.as_mut_ptr(),
+            key.len(),
+            cc_ptr,
+            cc_len,
+            seed_ptr,
+            seed_len,
+        )
+    };
+    if err != CX_OK {
+        return Err(err.into());
+    }
+    Ok(())
+}
+
+/// BIP32 derivation from the device seed
+///
+/// Checks the length of the key and chain code buffers
+/// in order to prevent the underlying syscall from failing
 pub fn bip32_derive(
     curve: CurvesId,
     path: &[u32],
     key: &mut [u8],
     cc: Option<&mut [u8]>,
 ) -> Result<(), CxError> {
-    match curve {
-        CurvesId::Secp256k1 | CurvesId::Secp256r1 | CurvesId::Ed25519 => {
-            if key.len() < 64 {
+    if key.len() < 64 {
+        return Err(CxError::InvalidParameter);
+    }
+    match cc {
+        Some(buf) => {
+            if buf.len() < 32 {
                 return Err(CxError::InvalidParameter);
             }
+            let mut chain_code = ChainCode::default();
+            hdkey_derive(
+                HDKeyDeriveMode::Bip32,
+                curve,
+                path,
+                key,
+                Some(&mut chain_code),
+                None,
+            )?;
+            buf[..32].copy_from_slice(&chain_code.value);
+            Ok(())
         }
-        _ => return Err(CxError::InvalidParameter),
+        None => hdkey_derive(HDKeyDeriveMode::Bip32, curve, path, key, None, None),
     }
-    unsafe {
-        match cc {
-            Some(buf) => os_perso_derive_node_bip32(
-                curve as u8,
-                path.as_ptr(),
-                path.len() as u32,
-                key.as_mut_ptr(),
-                buf.as_mut_ptr(),
-            ),
-            None => os_perso_derive_node_bip32(
-                curve as u8,
-                path.as_ptr(),
-                path.len() as u32,
-                key.as_mut_ptr(),
-                core::ptr::null_mut(),
-            ),
-        }
-    };
-    Ok(())
 }
 
 /// Helper buffer that stores secrets that need to be cleared after use
@@ -370,7 +423,7 @@ impl<const N: usize> Drop for Secret<N> {
 }
 
 #[repr(C)]
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct ChainCode {
     pub value: [u8; 32],
 }
@@ -378,15 +431,24 @@ pub struct ChainCode {
 /// Fill the key buffer `ECPrivateKey<_,_>.key` with bytes
 /// derived from the seed through BIP32 or other standard
 /// derivation scheme.
-/// Since the underlying OS function only supports Secp256k1,
-/// Secp256r1 and Ed25519, it is only implemented for these
-/// curves.
+/// It is implemented, with the scheme standard for each curve,
+/// for the Weierstrass and Edwards curves.
+/// Keys on other curves can be derived with [`hdkey_derive`].
 pub trait SeedDerive {
     type Target;
+    /// Panics if the derivation fails, like the syscall used to throw.
     fn derive_from(path: &[u32]) -> (Self::Target, Option<ChainCode>);
+    /// Returns the error of the derivation instead of panicking. The SDK
+    /// curves implement it; the default forwards to `derive_from`.
+    fn try_derive_from(path: &[u32]) -> Result<(Self::Target, Option<ChainCode>), CxError> {
+        Ok(Self::derive_from(path))
+    }
     fn derive_from_path(path: &[u32]) -> Self::Target {
         Self::derive_from(path).0
     }
+    fn try_derive_from_path(path: &[u32]) -> Result<Self::Target, CxError> {
+        Self::try_derive_from(path).map(|(sk, _)| sk)
+    }
 }
 
 #[repr(u8)]
@@ -574,4 +636,37 @@ mod tests {
             assert_eq!(P, [1234u32, 5678u32 + 0x80000000u32]);
         }
     }
+    #[test]
+    fn test_hdkey_derive_errors() {
+        const P: [u32; 3] = make_bip32_path(b"m/44'/0'/0'");
+        let mut key = [0u8; 64];
+        assert_eq!(
+            bip32_derive(CurvesId::Secp256k1, &P, &mut key[..32], None),
+            Err(CxError::InvalidParameter)
+        );
+        assert_eq!(
+            bip32_derive(CurvesId::Secp256k1, &P, &mut key, Some(&mut [0u8; 16])),
+            Err(CxError::InvalidParameter)
+        );
+        assert_eq!(
+            hdkey_derive(
+                HDKeyDeriveMode::Slip21,
+                CurvesId::Secp256k1,
+                &P,
+                &mut key,
+                None,
+                None
+            ),
+            Err(CxError::InvalidParameter)
+        );
+
+        let mut cc = [0u8; 32];
+        assert_eq!(
+            bip32_derive(CurvesId::Secp256k1, &P, &mut key, Some(&mut cc)),
+            Ok(())
+        );
+        let (sk, chain_code) = Secp256k1::try_derive_from(&P).unwrap();
+        assert_eq!(&sk.key, &key[..32]);
+        assert_eq!(chain_code.unwrap().value, cc);
+    }
 }
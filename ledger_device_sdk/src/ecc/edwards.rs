@@ -2,7 +2,7 @@ use crate::bn::BnLock;
 use crate::check_cx_ok;
 use crate::ecc::{
     ChainCode, CurvesId, CxError, ECPrivateKey, ECPublicKey, HDKeyDeriveMode, Secret, SeedDerive,
-    bip32_derive,
+    hdkey_derive,
 };
 use crate::hash::{HashInit, sha2::Sha2_512};
 use crate::impl_curve;
@@ -270,42 +270,47 @@ impl<const P: usize> ECPublicKey<P, 'E'> {
 impl SeedDerive for Ed25519 {
     type Target = ECPrivateKey<32, 'E'>;
     fn derive_from(path: &[u32]) -> (Self::Target, Option<ChainCode>) {
+        Self::try_derive_from(path).expect("seed derivation failed")
+    }
+    fn try_derive_from(path: &[u32]) -> Result<(Self::Target, Option<ChainCode>), CxError> {
         let mut tmp = Secret::<64>::new();
         let mut cc: ChainCode = Default::default();
-        // Ignoring 'Result' here because known to be valid
-        let _ = bip32_derive(
+        hdkey_derive(
+            HDKeyDeriveMode::Bip32,
             CurvesId::Ed25519,
             path,
             tmp.as_mut(),
-            Some(cc.value.as_mut()),
-        );
+            Some(&mut cc),
+            None,
+        )?;
         let mut sk = Self::Target::new(CurvesId::Ed25519);
         let keylen = sk.key.len();
         sk.key.copy_from_slice(&tmp.0[..keylen]);
-        (sk, Some(cc))
+        Ok((sk, Some(cc)))
     }
 }
 
 /// Support SLIP10 derivation for Ed25519
 impl Ed25519 {
-    pub fn derive_from_path_slip10(path: &[u32]) -> ECPrivateKey<32, 'E'> {
+    pub fn try_derive_from_path_slip10(path: &[u32]) -> Result<ECPrivateKey<32, 'E'>, CxError> {
         let mut tmp = Secret::<64>::new();
-        unsafe {
-            os_perso_derive_node_with_seed_key(
-                HDW_ED25519_SLIP10,
-                CurvesId::Ed25519 as u8,
-                path.as_ptr(),
-                path.len() as u32,
-                tmp.as_mut().as_mut_ptr(),
-                core::ptr::null_mut(),
-                core::ptr::null_mut(),
-                0,
-            );
-        }
+        hdkey_derive(
+            HDKeyDeriveMode::Slip10Ed25519,
+            CurvesId::Ed25519,
+            path,
+            tmp.as_mut(),
+            None,
+            None,
+        )?;
         let mut sk = ECPrivateKey::new(CurvesId::Ed25519);
         let keylen = sk.key.len();
         sk.key.copy_from_slice(&tmp.0[..keylen]);
-        sk
+        Ok(sk)
+    }
+
+    /// Panics if the derivation fails, like the syscall used to throw.
+    pub fn derive_from_path_slip10(path: &[u32]) -> ECPrivateKey<32, 'E'> {
+        Self::try_derive_from_path_slip10(path).expect("SLIP10 derivation failed")
     }
 }
 
@@ -324,31 +329,14 @@ impl JubJub {
         seed: Option<&[u8]>,
     ) -> Result<(Secret<32>, Secret<32>, Secret<32>, Secret<32>), CxError> {
         let mut tmp = Secret::<128>::new();
-        let (cc_ptr, cc_len) = match cc {
-            Some(cc) => (cc.value.as_mut_ptr(), 32usize),
-            None => (core::ptr::null_mut(), 0usize),
-        };
-        let (seed_ptr, seed_len) = match seed {
-            Some(s) => (s.as_ptr() as *mut u8, s.len()),
-            None => (core::ptr::null_mut(), 0usize),
-        };
-        let err = unsafe {
-            sys_hdkey_derive(
-                HDKeyDeriveMode::Zip32Sapling as u8,
-                CurvesId::JubJub as cx_curve_t,
-                path.as_ptr(),
-                path.len(),
-                tmp.as_mut().as_mut_ptr(),
-                128,
-                cc_ptr,
-                cc_len,
-                seed_ptr,
-                seed_len,
-            )
-        };
-        if err != CX_OK {
-            return Err(err.into());
-        }
+        hdkey_derive(
+            HDKeyDeriveMode::Zip32Sapling,
+            CurvesId::JubJub,
+            path,
+            tmp.as_mut(),
+            cc,
+            seed,
+        )?;
         let mut ask = Secret::<32>::new();
         let mut nsk = Secret::<32>::new();
         let mut ovk = Secret::<32>::new();
@@ -364,10 +352,12 @@ impl JubJub {
 impl SeedDerive for JubJub {
     type Target = (Secret<32>, Secret<32>, Secret<32>, Secret<32>);
     fn derive_from(path: &[u32]) -> (Self::Target, Option<ChainCode>) {
+        Self::try_derive_from(path).expect("seed derivation failed")
+    }
+    fn try_derive_from(path: &[u32]) -> Result<(Self::Target, Option<ChainCode>), CxError> {
         let mut cc: ChainCode = Default::default();
-        let keys = Self::zip32_sapling_derive(path, Some(&mut cc), None)
-            .expect("zip32_sapling_derive failed");
-        (keys, Some(cc))
+        let keys = Self::zip32_sapling_derive(path, Some(&mut cc), None)?;
+        Ok((keys, Some(cc)))
     }
 }
 
@@ -0,0 +1,418 @@
+//! Serialization of public keys.
+//!
+//! * SEC1 encodings of Weierstrass public keys, compressed or not,
+//! * the 32-byte encoding of Ed25519 public keys defined in RFC 8032,
+//! * BIP32 extended public keys (xpubs).
+//!
+//! ```
+//! let public_key = Secp256k1::derive_from_path(&PATH).public_key()?;
+//! let compressed = public_key.compressed();
+//! let decoded = ECPublicKey::<65, 'W'>::from_sec1(CurvesId::Secp256k1, &compressed)?;
+//!
+//! let xpub = ExtendedPublicKey::derive(&make_bip32_path(b"m/84'/0'/0'"), XPUB_VERSION)?;
+//! let mut buf = [0u8; XPUB_BASE58_MAX_LEN];
+//! let encoded: &str = xpub.to_base58check(&mut buf);
+//! ```
+
+use super::{ChainCode, CurvesId, CxError, ECPublicKey, EcPoint, Secp256k1, Secret, bip32_derive};
+use crate::hash::{HashInit, ripemd::Ripemd160, sha2::Sha2_256};
+use ledger_secure_sdk_sys::{
+    CX_OK, cx_curve_t, cx_edwards_compress_point_no_throw, cx_edwards_decompress_point_no_throw,
+};
+
+/// First byte of an uncompressed SEC1 point.
+const SEC1_UNCOMPRESSED: u8 = 0x04;
+/// First byte of a compressed SEC1 point with an even y coordinate, which is
+/// incremented for an odd one.
+const SEC1_COMPRESSED_EVEN: u8 = 0x02;
+
+/// Weierstrass public keys are stored as uncompressed SEC1 points.
+impl<const P: usize> ECPublicKey<P, 'W'> {
+    /// Size of the compressed SEC1 encoding.
+    pub const COMPRESSED_LEN: usize = P.div_ceil(2);
+
+    /// Returns the compressed SEC1 encoding: `0x02` or `0x03`, depending on
+    /// the parity of y, followed by x.
+    pub fn compressed(&self) -> [u8; Self::COMPRESSED_LEN] {
+        let mut compressed = [0u8; Self::COMPRESSED_LEN];
+        compressed[0] = SEC1_COMPRESSED_EVEN | (self.pubkey[P - 1] & 1);
+        compressed[1..].copy_from_slice(&self.pubkey[1..Self::COMPRESSED_LEN]);
+        compressed
+    }
+
+    /// Returns the uncompressed SEC1 encoding: `0x04` followed by x and y.
+    pub fn uncompressed(&self) -> &[u8; P] {
+        &self.pubkey
+    }
+
+    /// Decodes a compressed or uncompressed SEC1 point on `curve`.
+    ///
+    /// # Errors
+    ///
+    /// * [`CxError::InvalidParameterSize`] if the length of `bytes` does not
+    ///   match the curve and the encoding,
+    /// * [`CxError::InvalidPoint`] if `bytes` is not a point of the curve.
+    pub fn from_sec1(curve: CurvesId, bytes: &[u8]) -> Result<ECPublicKey<P, 'W'>, CxError> {
+        let size = (P - 1) / 2;
+        if curve.size_bytes() != size {
+            return Err(CxError::InvalidParameterSize);
+        }
+        let mut point = EcPoint::new(curve)?;
+        match bytes.first() {
+            Some(&SEC1_UNCOMPRESSED) if bytes.len() == P => {
+                point.init(&bytes[1..=size], &bytes[size + 1..])?;
+                if !point.is_on_curve()? {
+                    return Err(CxError::InvalidPoint);
+                }
+            }
+            Some(&(prefix @ (0x02 | 0x03))) if bytes.len() == size + 1 => {
+                point.decompress(&bytes[1..], (prefix & 1) as u32)?;
+            }
+            _ => return Err(CxError::InvalidParameterSize),
+        }
+        let mut public_key = ECPublicKey::new(curve);
+        public_key.pubkey[0] = SEC1_UNCOMPRESSED;
+        let (x, y) = public_key.pubkey[1..].split_at_mut(size);
+        point.export(x, y)?;
+        Ok(public_key)
+    }
+}
+
+/// Ed25519 public keys are stored as uncompressed points, as Weierstrass ones.
+impl ECPublicKey<65, 'E'> {
+    fn check_ed25519(&self) -> Result<(), CxError> {
+        match self.curve {
+            CurvesId::Ed25519 => Ok(()),
+            _ => Err(CxError::InvalidCurve),
+        }
+    }
+
+    /// Returns the 32-byte encoding of the point defined in RFC 8032.
+    pub fn ed25519_bytes(&self) -> Result<[u8; 32], CxError> {
+        self.check_ed25519()?;
+        let mut point = self.pubkey;
+        let err = unsafe {
+            cx_edwards_compress_point_no_throw(
+                CurvesId::Ed25519 as cx_curve_t,
+                point.as_mut_ptr(),
+                point.len(),
+            )
+        };
+        if err != CX_OK {
+            return Err(err.into());
+        }
+        // The compressed point follows a prefix byte
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(&point[1..33]);
+        Ok(bytes)
+    }
+
+    /// Decodes the 32-byte encoding of an Ed25519 point defined in RFC 8032.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`CxError::InvalidPoint`] if `bytes` is not the encoding of
+    /// a point of the curve.
+    pub fn from_ed25519_bytes(bytes: &[u8; 32]) -> Result<ECPublicKey<65, 'E'>, CxError> {
+        let mut public_key = ECPublicKey::<65, 'E'>::new(CurvesId::Ed25519);
+        public_key.pubkey[0] = SEC1_COMPRESSED_EVEN;
+        public_key.pubkey[1..33].copy_from_slice(bytes);
+        let err = unsafe {
+            cx_edwards_decompress_point_no_throw(
+                CurvesId::Ed25519 as cx_curve_t,
+                public_key.pubkey.as_mut_ptr(),
+                public_key.pubkey.len(),
+            )
+        };
+        if err != CX_OK {
+            return Err(CxError::InvalidPoint);
+        }
+        Ok(public_key)
+    }
+}
+
+/// Version bytes of mainnet extended public keys (`xpub`).
+pub const XPUB_VERSION: [u8; 4] = [0x04, 0x88, 0xb2, 0x1e];
+/// Version bytes of testnet extended public keys (`tpub`).
+pub const TPUB_VERSION: [u8; 4] = [0x04, 0x35, 0x87, 0xcf];
+
+/// Size of the binary serialization of an extended key.
+pub const EXTENDED_KEY_LEN: usize = 78;
+/// Maximum size of the Base58Check encoding of an extended key.
+pub const XPUB_BASE58_MAX_LEN: usize = 112;
+
+const BASE58_ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// Returns `RIPEMD160(SHA256(data))`.
+fn hash160(data: &[u8]) -> Result<[u8; 20], CxError> {
+    let mut sha256 = [0u8; 32];
+    let mut hash = [0u8; 20];
+    Sha2_256::new()
+        .hash(data, &mut sha256)
+        .map_err(|_| CxError::GenericError)?;
+    Ripemd160::new()
+        .hash(&sha256, &mut hash)
+        .map_err(|_| CxError::GenericError)?;
+    Ok(hash)
+}
+
+/// Returns the BIP32 fingerprint of a key: the first 4 bytes of the HASH160 of
+/// its compressed encoding.
+pub fn key_fingerprint(compressed: &[u8; 33]) -> Result<[u8; 4], CxError> {
+    let hash = hash160(compressed)?;
+    Ok([hash[0], hash[1], hash[2], hash[3]])
+}
+
+/// Writes the Base58 encoding of `data` at the end of `out`, and returns its
+/// length. `out` must be large enough.
+fn base58_encode(data: &[u8], out: &mut [u8]) -> usize {
+    let mut len = 0;
+    for &byte in data {
+        // Multiply the digits, stored from the end of `out`, by 256 and add
+        // the byte
+        let mut carry = byte as u32;
+        for digit in out.iter_mut().rev().take(len) {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            len += 1;
+            let i = out.len() - len;
+            out[i] = (carry % 58) as u8;
+            carry /= 58;
+        }
+    }
+    // Leading zero bytes are encoded as zero digits
+    for _ in data.iter().take_while(|&&b| b == 0) {
+        len += 1;
+        let i = out.len() - len;
+        out[i] = 0;
+    }
+    let start = out.len() - len;
+    for digit in out[start..].iter_mut() {
+        *digit = BASE58_ALPHABET[*digit as usize];
+    }
+    len
+}
+
+/// BIP32 extended public key.
+#[derive(Clone)]
+pub struct ExtendedPublicKey {
+    pub version: [u8; 4],
+    pub depth: u8,
+    pub parent_fingerprint: [u8; 4],
+    pub child_number: u32,
+    pub chain_code: ChainCode,
+    /// Compressed SEC1 encoding of the key.
+    pub public_key: [u8; 33],
+}
+
+impl ExtendedPublicKey {
+    /// Derives the Secp256k1 extended public key at `path` from the seed.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`CxError::InvalidParameter`] if `path` has more than 255
+    /// components, or if the derivation fails.
+    pub fn derive(path: &[u32], version: [u8; 4]) -> Result<ExtendedPublicKey, CxError> {
+        fn node(path: &[u32]) -> Result<([u8; 33], ChainCode), CxError> {
+            let mut tmp = Secret::<64>::new();
+            let mut chain_code = ChainCode::default();
+            bip32_derive(
+                CurvesId::Secp256k1,
+                path,
+                tmp.as_mut(),
+                Some(chain_code.value.as_mut()),
+            )?;
+            let public_key = Secp256k1::from(&tmp.as_ref()[..32]).public_key()?;
+            Ok((public_key.compressed(), chain_code))
+        }
+
+        let depth = u8::try_from(path.len()).map_err(|_| CxError::InvalidParameter)?;
+        let (public_key, chain_code) = node(path)?;
+        let (parent_fingerprint, child_number) = match path.split_last() {
+            Some((&child_number, parent)) => (key_fingerprint(&node(parent)?.0)?, child_number),
+            None => ([0; 4], 0),
+        };
+        Ok(ExtendedPublicKey {
+            version,
+            depth,
+            parent_fingerprint,
+            child_number,
+            chain_code,
+            public_key,
+        })
+    }
+
+    /// Returns the BIP32 fingerprint of the key, which is the parent
+    /// fingerprint of its children.
+    pub fn fingerprint(&self) -> Result<[u8; 4], CxError> {
+        key_fingerprint(&self.public_key)
+    }
+
+    /// Returns the 78-byte serialization defined in BIP32.
+    pub fn serialize(&self) -> [u8; EXTENDED_KEY_LEN] {
+        let mut bytes = [0u8; EXTENDED_KEY_LEN];
+        bytes[..4].copy_from_slice(&self.version);
+        bytes[4] = self.depth;
+        bytes[5..9].copy_from_slice(&self.parent_fingerprint);
+        bytes[9..13].copy_from_slice(&self.child_number.to_be_bytes());
+        bytes[13..45].copy_from_slice(&self.chain_code.value);
+        bytes[45..].copy_from_slice(&self.public_key);
+        bytes
+    }
+
+    /// Decodes the 78-byte serialization defined in BIP32.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`CxError::InvalidPoint`] if the key is not a compressed
+    /// Secp256k1 point.
+    pub fn deserialize(bytes: &[u8; EXTENDED_KEY_LEN]) -> Result<ExtendedPublicKey, CxError> {
+        let mut xpub = ExtendedPublicKey {
+            version: [0; 4],
+            depth: bytes[4],
+            parent_fingerprint: [0; 4],
+            child_number: u32::from_be_bytes([bytes[9], bytes[10], bytes[11], bytes[12]]),
+            chain_code: ChainCode::default(),
+            public_key: [0; 33],
+        };
+        xpub.version.copy_from_slice(&bytes[..4]);
+        xpub.parent_fingerprint.copy_from_slice(&bytes[5..9]);
+        xpub.chain_code.value.copy_from_slice(&bytes[13..45]);
+        xpub.public_key.copy_from_slice(&bytes[45..]);
+        ECPublicKey::<65, 'W'>::from_sec1(CurvesId::Secp256k1, &xpub.public_key)
+            .map_err(|_| CxError::InvalidPoint)?;
+        Ok(xpub)
+    }
+
+    /// Returns the Base58Check encoding of the key, as displayed by wallets,
+    /// written in `buf`.
+    pub fn to_base58check<'a>(&self, buf: &'a mut [u8; XPUB_BASE58_MAX_LEN]) -> &'a str {
+        let mut data = [0u8; EXTENDED_KEY_LEN + 4];
+        data[..EXTENDED_KEY_LEN].copy_from_slice(&self.serialize());
+        let mut digest = [0u8; 32];
+        let mut checksum = [0u8; 32];
+        // Hashing cannot fail with a 32-byte output
+        let _ = Sha2_256::new().hash(&data[..EXTENDED_KEY_LEN], &mut digest);
+        let _ = Sha2_256::new().hash(&digest, &mut checksum);
+        data[EXTENDED_KEY_LEN..].copy_from_slice(&checksum[..4]);
+        let len = base58_encode(&data, buf);
+        // Only contains characters of the Base58 alphabet
+        core::str::from_utf8(&buf[XPUB_BASE58_MAX_LEN - len..]).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_eq_err as assert_eq;
+    use crate::ecc::Ed25519;
+    use crate::testing::{TestType, hex};
+    use testmacro::test_item as test;
+
+    const GENERATOR_X: &str = "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+    const GENERATOR_Y: &str = "483ada7726a3c4655da4fbfc0e1108a8fd17b448a68554199c47d08ffb10d4b8";
+
+    #[test]
+    fn sec1_encoding() {
+        let mut compressed = [0u8; 33];
+        compressed[0] = 0x02;
+        compressed[1..].copy_from_slice(&hex::<32>(GENERATOR_X));
+        let public_key =
+            ECPublicKey::<65, 'W'>::from_sec1(CurvesId::Secp256k1, &compressed).unwrap();
+        assert_eq!(public_key.pubkey[0], 0x04);
+        assert_eq!(public_key.pubkey[1..33], hex::<32>(GENERATOR_X));
+        assert_eq!(public_key.pubkey[33..], hex::<32>(GENERATOR_Y));
+        assert_eq!(public_key.compressed(), compressed);
+
+        let decoded =
+            ECPublicKey::<65, 'W'>::from_sec1(CurvesId::Secp256k1, public_key.uncompressed())
+                .unwrap();
+        assert_eq!(decoded.pubkey, public_key.pubkey);
+
+        // Opposite point
+        compressed[0] = 0x03;
+        let negated = ECPublicKey::<65, 'W'>::from_sec1(CurvesId::Secp256k1, &compressed).unwrap();
+        assert_eq!(negated.compressed(), compressed);
+        assert_eq!(negated.pubkey[33..] == public_key.pubkey[33..], false);
+
+        // Not on the curve
+        let mut uncompressed = public_key.pubkey;
+        uncompressed[64] ^= 1;
+        assert_eq!(
+            ECPublicKey::<65, 'W'>::from_sec1(CurvesId::Secp256k1, &uncompressed).err(),
+            Some(CxError::InvalidPoint)
+        );
+        // Wrong size for the curve
+        assert_eq!(
+            ECPublicKey::<97, 'W'>::from_sec1(CurvesId::Secp384r1, &compressed).err(),
+            Some(CxError::InvalidParameterSize)
+        );
+    }
+
+    #[test]
+    fn ed25519_encoding() {
+        // RFC 8032, test 1
+        let sk = Ed25519::from(&hex::<32>(
+            "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+        ));
+        let public_key = sk.public_key().unwrap();
+        let encoded = hex::<32>("d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a");
+        assert_eq!(public_key.ed25519_bytes(), Ok(encoded));
+        let decoded = ECPublicKey::<65, 'E'>::from_ed25519_bytes(&encoded).unwrap();
+        assert_eq!(decoded.pubkey, public_key.pubkey);
+    }
+
+    #[test]
+    fn xpub_serialization() {
+        // BIP32 test vector 1, chain m/0H
+        let master = ExtendedPublicKey {
+            version: XPUB_VERSION,
+            depth: 0,
+            parent_fingerprint: [0; 4],
+            child_number: 0,
+            chain_code: ChainCode {
+                value: hex("873dff81c02f525623fd1fe5167eac3a55a049de3d314bb42ee227ffed37d508"),
+            },
+            public_key: hex("0339a36013301597daef41fbe593a02cc513d0b55527ec2df1050e2e8ff49c85c2"),
+        };
+        let mut buf = [0u8; XPUB_BASE58_MAX_LEN];
+        assert_eq!(
+            master.to_base58check(&mut buf),
+            "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8"
+        );
+
+        let child = ExtendedPublicKey {
+            version: XPUB_VERSION,
+            depth: 1,
+            parent_fingerprint: master.fingerprint().unwrap(),
+            child_number: 0x8000_0000,
+            chain_code: ChainCode {
+                value: hex("47fdacbd0f1097043b78c63c20c34ef4ed9a111d980047ad16282c7ae6236141"),
+            },
+            public_key: hex("035a784662a4a20a65bf6aab9ae98a6c068a81c52e4b032c0fb5400c706cfccc56"),
+        };
+        assert_eq!(child.parent_fingerprint, [0x34, 0x42, 0x19, 0x3e]);
+        assert_eq!(
+            child.to_base58check(&mut buf),
+            "xpub68Gmy5EdvgibQVfPdqkBBCHxA5htiqg55crXYuXoQRKfDBFA1WEjWgP6LHhwBZeNK1VTsfTFUHCdrfp1bgwQ9xv5ski8PX9rL2dZXvgGDnw"
+        );
+
+        let decoded = ExtendedPublicKey::deserialize(&child.serialize()).unwrap();
+        assert_eq!(decoded.serialize(), child.serialize());
+    }
+
+    #[test]
+    fn xpub_derivation() {
+        let path = crate::ecc::make_bip32_path::<3>(b"m/84'/0'/0'");
+        let xpub = ExtendedPublicKey::derive(&path, XPUB_VERSION).unwrap();
+        let parent = ExtendedPublicKey::derive(&path[..2], XPUB_VERSION).unwrap();
+        assert_eq!(xpub.depth, 3);
+        assert_eq!(xpub.child_number, path[2]);
+        assert_eq!(xpub.parent_fingerprint, parent.fingerprint().unwrap());
+        let mut buf = [0u8; XPUB_BASE58_MAX_LEN];
+        assert_eq!(xpub.to_base58check(&mut buf).starts_with("xpub6"), true);
+    }
+}
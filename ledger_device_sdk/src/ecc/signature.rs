@@ -0,0 +1,386 @@
+//! Encoding of ECDSA signatures.
+//!
+//! `ECPrivateKey::sign` returns a DER-encoded signature in a fixed size
+//! buffer, along with its length. [`EcdsaSignature`] holds the `r` and `s`
+//! scalars of a signature, and converts them from and to strict DER, and the
+//! compact encoding made of `r` followed by `s`:
+//!
+//! ```
+//! let (der, len, _) = sk.deterministic_sign(&hash)?;
+//! let signature = EcdsaSignature::<32>::from_der(&der[..len as usize])?;
+//! let compact = signature.to_compact();
+//!
+//! let signature = EcdsaSignature::<32>::from_compact(&compact)?;
+//! let der = signature.to_der();
+//! assert!(public_key.verify(der.verify_input(), &hash));
+//! ```
+
+use super::{CurveDomainParam, CurvesId, CxError};
+
+/// Largest scalar size supported by [`EcdsaSignature`], the one of the
+/// 512-bit curves.
+pub const MAX_SIGNATURE_SCALAR_LEN: usize = 64;
+
+/// Maximum length of a DER-encoded signature: a sequence, whose length may
+/// take two bytes, of two integers of up to `MAX_SIGNATURE_SCALAR_LEN + 1`
+/// bytes.
+pub const MAX_DER_SIGNATURE_LEN: usize = 3 + 2 * (2 + MAX_SIGNATURE_SCALAR_LEN + 1);
+
+const DER_SEQUENCE: u8 = 0x30;
+const DER_INTEGER: u8 = 0x02;
+/// First byte of a length encoded on two bytes.
+const DER_LENGTH_1: u8 = 0x81;
+
+/// ECDSA signature on a curve whose scalars are `N` bytes long.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct EcdsaSignature<const N: usize> {
+    pub r: [u8; N],
+    pub s: [u8; N],
+}
+
+/// DER-encoded signature, as accepted by `ECPublicKey::verify` and
+/// `pki::pki_check_signature`.
+#[derive(Copy, Clone)]
+pub struct DerSignature {
+    bytes: [u8; MAX_DER_SIGNATURE_LEN],
+    len: usize,
+}
+
+impl DerSignature {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+
+    /// Mutable access to the encoding, for `pki::pki_check_signature`.
+    pub fn as_mut_bytes(&mut self) -> &mut [u8] {
+        &mut self.bytes[..self.len]
+    }
+
+    /// Returns the signature in the form expected by `ECPublicKey::verify`.
+    pub fn verify_input(&self) -> (&[u8], u32) {
+        (self.as_bytes(), self.len as u32)
+    }
+}
+
+impl AsRef<[u8]> for DerSignature {
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+/// Reads one strictly encoded, positive, non-zero DER integer of up to `N`
+/// bytes, and returns the remaining input.
+fn read_integer<'a, const N: usize>(
+    input: &'a [u8],
+    out: &mut [u8; N],
+) -> Result<&'a [u8], CxError> {
+    let (value, rest) = match input {
+        [DER_INTEGER, len, rest @ ..] if *len < 0x80 && (*len as usize) <= rest.len() => {
+            rest.split_at(*len as usize)
+        }
+        _ => return Err(CxError::InvalidParameter),
+    };
+    let value = match value {
+        // Empty or negative
+        [] | [0x80..=0xff, ..] => return Err(CxError::InvalidParameter),
+        // The padding byte is only allowed before a byte with its high bit set
+        [0, 0x80..=0xff, ..] => &value[1..],
+        [0, ..] => return Err(CxError::InvalidParameter),
+        value => value,
+    };
+    if value.len() > N {
+        return Err(CxError::InvalidParameter);
+    }
+    out[N - value.len()..].copy_from_slice(value);
+    Ok(rest)
+}
+
+/// Appends the DER encoding of the big-endian integer `value` to `out`.
+fn write_integer(value: &[u8], out: &mut [u8], pos: &mut usize) {
+    let start = value
+        .iter()
+        .position(|&b| b != 0)
+        .unwrap_or(value.len() - 1);
+    let value = &value[start..];
+    let padding = value[0] & 0x80 != 0;
+    out[*pos] = DER_INTEGER;
+    out[*pos + 1] = (value.len() + padding as usize) as u8;
+    *pos += 2;
+    if padding {
+        out[*pos] = 0;
+        *pos += 1;
+    }
+    out[*pos..*pos + value.len()].copy_from_slice(value);
+    *pos += value.len();
+}
+
+/// Returns the length of the DER encoding of the big-endian integer `value`.
+fn integer_len(value: &[u8]) -> usize {
+    match value.iter().position(|&b| b != 0) {
+        Some(start) => 2 + value.len() - start + (value[start] & 0x80 != 0) as usize,
+        None => 3,
+    }
+}
+
+/// Returns `2 * x > n` for big-endian integers of the same length.
+fn is_high(x: &[u8], n: &[u8]) -> bool {
+    let mut double = [0u8; MAX_SIGNATURE_SCALAR_LEN + 1];
+    let double = &mut double[..x.len() + 1];
+    let mut carry = 0;
+    for (d, x) in double[1..].iter_mut().zip(x.iter()).rev() {
+        *d = (*x << 1) | carry;
+        carry = *x >> 7;
+    }
+    double[0] = carry;
+    double[0] != 0 || &double[1..] > n
+}
+
+impl<const N: usize> EcdsaSignature<N> {
+    /// Length of the compact encoding.
+    pub const COMPACT_LEN: usize = 2 * N;
+
+    pub const fn new(r: [u8; N], s: [u8; N]) -> EcdsaSignature<N> {
+        const { assert!(N <= MAX_SIGNATURE_SCALAR_LEN) };
+        EcdsaSignature { r, s }
+    }
+
+    /// Decodes a DER-encoded signature. The encoding must be strict: minimal
+    /// lengths and integers, no trailing bytes, and non-zero positive scalars
+    /// of at most `N` bytes.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`CxError::InvalidParameter`] if the encoding is not valid.
+    pub fn from_der(der: &[u8]) -> Result<EcdsaSignature<N>, CxError> {
+        let (len, body) = match der {
+            [DER_SEQUENCE, len, body @ ..] if *len < 0x80 => (*len, body),
+            [DER_SEQUENCE, DER_LENGTH_1, len, body @ ..] if *len >= 0x80 => (*len, body),
+            _ => return Err(CxError::InvalidParameter),
+        };
+        if len as usize != body.len() {
+            return Err(CxError::InvalidParameter);
+        }
+        let mut signature = EcdsaSignature::new([0; N], [0; N]);
+        let rest = read_integer(body, &mut signature.r)?;
+        let rest = read_integer(rest, &mut signature.s)?;
+        if !rest.is_empty() || signature.r == [0; N] || signature.s == [0; N] {
+            return Err(CxError::InvalidParameter);
+        }
+        Ok(signature)
+    }
+
+    /// Returns the DER encoding of the signature.
+    pub fn to_der(&self) -> DerSignature {
+        let body_len = integer_len(&self.r) + integer_len(&self.s);
+        let mut der = DerSignature {
+            bytes: [0; MAX_DER_SIGNATURE_LEN],
+            len: 0,
+        };
+        let mut pos = 0;
+        der.bytes[pos] = DER_SEQUENCE;
+        pos += 1;
+        if body_len >= 0x80 {
+            der.bytes[pos] = DER_LENGTH_1;
+            pos += 1;
+        }
+        der.bytes[pos] = body_len as u8;
+        pos += 1;
+        write_integer(&self.r, &mut der.bytes, &mut pos);
+        write_integer(&self.s, &mut der.bytes, &mut pos);
+        der.len = pos;
+        der
+    }
+
+    /// Decodes the compact encoding of a signature: `r` followed by `s`.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`CxError::InvalidParameterSize`] if `compact` is not
+    /// `2 * N` bytes long.
+    pub fn from_compact(compact: &[u8]) -> Result<EcdsaSignature<N>, CxError> {
+        if compact.len() != Self::COMPACT_LEN {
+            return Err(CxError::InvalidParameterSize);
+        }
+        let mut signature = EcdsaSignature::new([0; N], [0; N]);
+        signature.r.copy_from_slice(&compact[..N]);
+        signature.s.copy_from_slice(&compact[N..]);
+        Ok(signature)
+    }
+
+    /// Returns the compact encoding of the signature: `r` followed by `s`.
+    pub fn to_compact(&self) -> [u8; Self::COMPACT_LEN] {
+        let mut compact = [0u8; Self::COMPACT_LEN];
+        compact[..N].copy_from_slice(&self.r);
+        compact[N..].copy_from_slice(&self.s);
+        compact
+    }
+
+    /// Returns true if `s` is at most half the order of `curve`.
+    pub fn is_low_s(&self, curve: CurvesId) -> Result<bool, CxError> {
+        let mut n = [0u8; N];
+        curve.domain_parameter(CurveDomainParam::Order, &mut n)?;
+        Ok(!is_high(&self.s, &n))
+    }
+
+    /// Replaces `s` with `n - s` if it is greater than half the order of
+    /// `curve`, so that the signature is accepted by verifiers requiring a
+    /// low `s`.
+    ///
+    /// Returns true if `s` has been changed. The signature then corresponds to
+    /// the opposite point R, which flips the parity returned by `sign`.
+    pub fn normalize_s(&mut self, curve: CurvesId) -> Result<bool, CxError> {
+        let mut n = [0u8; N];
+        curve.domain_parameter(CurveDomainParam::Order, &mut n)?;
+        if !is_high(&self.s, &n) {
+            return Ok(false);
+        }
+        let mut borrow = 0i16;
+        for (s, n) in self.s.iter_mut().zip(n.iter()).rev() {
+            let d = *n as i16 - *s as i16 - borrow;
+            borrow = (d < 0) as i16;
+            *s = d as u8;
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_eq_err as assert_eq;
+    use crate::ecc::{Secp256k1, Secp384r1, SeedDerive, make_bip32_path};
+    use crate::testing::{TestType, hex};
+    use testmacro::test_item as test;
+
+    const R: &str = "bb50e2d89a4ed70663d080659fe0ad4b9bc3e06c17a227433966cb59ceee020d";
+    const S: &str = "006a41ed3c0c79cbf299b205c46c4606d008fddfd763bec415465fc979ade875";
+
+    #[test]
+    fn der_roundtrip() {
+        let signature = EcdsaSignature::new(hex::<32>(R), hex::<32>(S));
+        let der = signature.to_der();
+        // r is padded, s has a leading zero removed
+        assert_eq!(der.as_bytes().len(), 2 + 35 + 33);
+        assert_eq!(der.as_bytes()[..5], [0x30, 68, 0x02, 33, 0x00]);
+        assert_eq!(der.as_bytes()[37..40], [0x02, 31, 0x6a]);
+        assert_eq!(
+            EcdsaSignature::<32>::from_der(der.as_bytes()),
+            Ok(signature)
+        );
+
+        // Signatures on 512-bit curves use a long-form length
+        let large = EcdsaSignature::new([0xff; 64], [0xff; 64]);
+        let der = large.to_der();
+        assert_eq!(der.as_bytes().len(), MAX_DER_SIGNATURE_LEN);
+        assert_eq!(der.as_bytes()[..3], [0x30, 0x81, 134]);
+        assert_eq!(EcdsaSignature::<64>::from_der(der.as_bytes()), Ok(large));
+    }
+
+    #[test]
+    fn der_strictness() {
+        let der = EcdsaSignature::new(hex::<32>(R), hex::<32>(S)).to_der();
+        let valid = der.as_bytes();
+        let mut buf = [0u8; MAX_DER_SIGNATURE_LEN];
+
+        // Trailing byte
+        buf[..valid.len()].copy_from_slice(valid);
+        assert_eq!(
+            EcdsaSignature::<32>::from_der(&buf[..valid.len() + 1]),
+            Err(CxError::InvalidParameter)
+        );
+        // Truncated
+        assert_eq!(
+            EcdsaSignature::<32>::from_der(&valid[..valid.len() - 1]),
+            Err(CxError::InvalidParameter)
+        );
+        // Negative r
+        buf[..valid.len()].copy_from_slice(valid);
+        buf[3] = 32;
+        buf[1] = 67;
+        buf[4..valid.len() - 1].copy_from_slice(&valid[5..]);
+        assert_eq!(
+            EcdsaSignature::<32>::from_der(&buf[..valid.len() - 1]),
+            Err(CxError::InvalidParameter)
+        );
+        // Unnecessary padding of s
+        let mut padded = [0u8; 71];
+        padded[..37].copy_from_slice(&valid[..37]);
+        padded[1] = 69;
+        padded[37..39].copy_from_slice(&[0x02, 32]);
+        padded[39..].copy_from_slice(&hex::<32>(S));
+        assert_eq!(
+            EcdsaSignature::<32>::from_der(&padded),
+            Err(CxError::InvalidParameter)
+        );
+        // Scalar larger than the curve size
+        let der = EcdsaSignature::new([0x42; 48], [0x42; 48]).to_der();
+        assert_eq!(
+            EcdsaSignature::<32>::from_der(der.as_bytes()),
+            Err(CxError::InvalidParameter)
+        );
+        // Zero scalar
+        let der = EcdsaSignature::new([0; 32], hex::<32>(S)).to_der();
+        assert_eq!(
+            EcdsaSignature::<32>::from_der(der.as_bytes()),
+            Err(CxError::InvalidParameter)
+        );
+    }
+
+    #[test]
+    fn compact_and_low_s() {
+        let mut signature = EcdsaSignature::new(hex::<32>(R), hex::<32>(S));
+        let compact = signature.to_compact();
+        assert_eq!(compact[..32], hex::<32>(R));
+        assert_eq!(EcdsaSignature::<32>::from_compact(&compact), Ok(signature));
+        assert_eq!(
+            EcdsaSignature::<32>::from_compact(&compact[1..]),
+            Err(CxError::InvalidParameterSize)
+        );
+
+        assert_eq!(signature.is_low_s(CurvesId::Secp256k1), Ok(true));
+        assert_eq!(signature.normalize_s(CurvesId::Secp256k1), Ok(false));
+        signature.s = [
+            0xe9, 0x6a, 0x41, 0xed, 0x3c, 0x0c, 0x79, 0xcb, 0xf2, 0x99, 0xb2, 0x05, 0xc4, 0x6c,
+            0x46, 0x06, 0xd0, 0x08, 0xfd, 0xdf, 0xd7, 0x63, 0xbe, 0xc4, 0x15, 0x46, 0x5f, 0xc9,
+            0x79, 0xad, 0xe8, 0x75,
+        ];
+        assert_eq!(signature.is_low_s(CurvesId::Secp256k1), Ok(false));
+        assert_eq!(signature.normalize_s(CurvesId::Secp256k1), Ok(true));
+        assert_eq!(
+            signature.s,
+            [
+                0x16, 0x95, 0xbe, 0x12, 0xc3, 0xf3, 0x86, 0x34, 0x0d, 0x66, 0x4d, 0xfa, 0x3b, 0x93,
+                0xb9, 0xf7, 0xea, 0xa5, 0xdf, 0x06, 0xd7, 0xe4, 0xe1, 0x77, 0xaa, 0x8b, 0xfe, 0xc3,
+                0x56, 0x88, 0x58, 0xcc,
+            ]
+        );
+    }
+
+    #[test]
+    fn sign_and_verify() {
+        const PATH: [u32; 5] = make_bip32_path(b"m/44'/535348'/0'/0/0");
+        let hash = [0x11u8; 32];
+        let sk = Secp256k1::derive_from_path(&PATH);
+        let public_key = sk.public_key().unwrap();
+        let (der, len, _) = sk.deterministic_sign(&hash).unwrap();
+        let mut signature = EcdsaSignature::<32>::from_der(&der[..len as usize]).unwrap();
+        assert_eq!(signature.to_der().as_bytes(), &der[..len as usize]);
+        signature.normalize_s(CurvesId::Secp256k1).unwrap();
+        assert_eq!(
+            public_key.verify(signature.to_der().verify_input(), &hash),
+            true
+        );
+
+        let hash = [0x11u8; 48];
+        let sk = Secp384r1::from(&[0x42u8; 48]);
+        let public_key = sk.public_key().unwrap();
+        let (der, len, _) = sk.deterministic_sign(&hash).unwrap();
+        let signature = EcdsaSignature::<48>::from_der(&der[..len as usize]).unwrap();
+        let compact = signature.to_compact();
+        let signature = EcdsaSignature::<48>::from_compact(&compact).unwrap();
+        assert_eq!(
+            public_key.verify(signature.to_der().verify_input(), &hash),
+            true
+        );
+    }
+}
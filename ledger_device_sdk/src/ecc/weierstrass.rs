@@ -1,12 +1,16 @@
 use crate::ecc::{
     ChainCode, CurvesId, CxError, ECPrivateKey, ECPublicKey, HDKeyDeriveMode, Secret, SeedDerive,
-    bip32_derive,
+    hdkey_derive,
 };
 use crate::impl_curve;
 use ledger_secure_sdk_sys::*;
 
 pub mod stark;
 pub use stark::*;
+pub mod recovery;
+pub use recovery::*;
+pub mod schnorr;
+pub use schnorr::*;
 
 impl_curve!(Secp256k1, 32, 'W');
 impl_curve!(Secp256r1, 32, 'W');
@@ -21,12 +25,26 @@ impl_curve!(BrainpoolP512R1, 64, 'W');
 impl_curve!(BrainpoolP512T1, 64, 'W');
 impl_curve!(Pallas, 32, 'W');
 
+/// Order of the Secp256k1 group.
+pub(crate) const SECP256K1_N: [u8; 32] = [
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe,
+    0xba, 0xae, 0xdc, 0xe6, 0xaf, 0x48, 0xa0, 0x3b, 0xbf, 0xd2, 0x5e, 0x8c, 0xd0, 0x36, 0x41, 0x41,
+];
+
+/// Order of the Secp256k1 base field.
+pub(crate) const SECP256K1_P: [u8; 32] = [
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe, 0xff, 0xff, 0xfc, 0x2f,
+];
+
 /// Weierstrass Curves-specific implementation
 impl<const N: usize> ECPrivateKey<N, 'W'> {
     /// Sign the incoming message/hash using ECDSA in the given `mode` and with the given hash identifier.
     /// This is a helper function. The two main interfaces are
     /// - [`deterministic_sign`]
     /// - [`sign`]
+    ///
+    /// The returned information is the raw `CX_ECCINFO_*` flags of the signature.
     fn ecdsa_sign(
         &self,
         hash: &[u8],
@@ -52,7 +70,7 @@ impl<const N: usize> ECPrivateKey<N, 'W'> {
         if len != CX_OK {
             Err(len.into())
         } else {
-            Ok((sig, sig_len as u32, info & CX_ECCINFO_PARITY_ODD))
+            Ok((sig, sig_len as u32, info))
         }
     }
 
@@ -66,12 +84,14 @@ impl<const N: usize> ECPrivateKey<N, 'W'> {
             x if x <= 64 => CX_SHA512,
             _ => CX_BLAKE2B,
         };
-        self.ecdsa_sign(hash, hash_id, CX_RND_RFC6979 | CX_LAST)
+        let (sig, sig_len, info) = self.ecdsa_sign(hash, hash_id, CX_RND_RFC6979 | CX_LAST)?;
+        Ok((sig, sig_len, info & CX_ECCINFO_PARITY_ODD))
     }
 
     /// Sign a message/hash using ECDSA in its original form
     pub fn sign(&self, hash: &[u8]) -> Result<([u8; Self::S], u32, u32), CxError> {
-        self.ecdsa_sign(hash, 0, CX_RND_TRNG | CX_LAST)
+        let (sig, sig_len, info) = self.ecdsa_sign(hash, 0, CX_RND_TRNG | CX_LAST)?;
+        Ok((sig, sig_len, info & CX_ECCINFO_PARITY_ODD))
     }
 
     /// Perform a Diffie-Hellman key exchange using the given uncompressed point `p`.
@@ -112,43 +132,54 @@ impl<const P: usize> ECPublicKey<P, 'W'> {
     }
 }
 
-impl SeedDerive for Secp256k1 {
-    type Target = ECPrivateKey<32, 'W'>;
-    fn derive_from(path: &[u32]) -> (Self::Target, Option<ChainCode>) {
-        let mut tmp = Secret::<64>::new();
-        let mut cc: ChainCode = Default::default();
-        // Ignoring 'Result' here because known to be valid
-        let _ = bip32_derive(
-            CurvesId::Secp256k1,
-            path,
-            tmp.as_mut(),
-            Some(cc.value.as_mut()),
-        );
-        let mut sk = Self::Target::new(CurvesId::Secp256k1);
-        let keylen = sk.key.len();
-        sk.key.copy_from_slice(&tmp.0[..keylen]);
-        (sk, Some(cc))
-    }
+/// Implements [`SeedDerive`] with BIP32 derivation for the given curves.
+///
+/// The OS writes up to twice the size of the key, so the buffer is sized
+/// from the curve.
+macro_rules! impl_bip32_seed_derive {
+    ($($typename:ident, $size:expr);* $(;)?) => {
+        $(
+            impl SeedDerive for $typename {
+                type Target = ECPrivateKey<$size, 'W'>;
+                fn derive_from(path: &[u32]) -> (Self::Target, Option<ChainCode>) {
+                    Self::try_derive_from(path).expect("seed derivation failed")
+                }
+                fn try_derive_from(
+                    path: &[u32],
+                ) -> Result<(Self::Target, Option<ChainCode>), CxError> {
+                    let mut tmp = Secret::<{ 2 * $size }>::new();
+                    let mut cc: ChainCode = Default::default();
+                    hdkey_derive(
+                        HDKeyDeriveMode::Bip32,
+                        CurvesId::$typename,
+                        path,
+                        tmp.as_mut(),
+                        Some(&mut cc),
+                        None,
+                    )?;
+                    let mut sk = Self::Target::new(CurvesId::$typename);
+                    let keylen = sk.key.len();
+                    sk.key.copy_from_slice(&tmp.0[..keylen]);
+                    Ok((sk, Some(cc)))
+                }
+            }
+        )*
+    };
 }
 
-impl SeedDerive for Secp256r1 {
-    type Target = ECPrivateKey<32, 'W'>;
-    fn derive_from(path: &[u32]) -> (Self::Target, Option<ChainCode>) {
-        let mut tmp = Secret::<64>::new();
-        let mut cc: ChainCode = Default::default();
-        // Ignoring 'Result' here because known to be valid
-        let _ = bip32_derive(
-            CurvesId::Secp256r1,
-            path,
-            tmp.as_mut(),
-            Some(cc.value.as_mut()),
-        );
-        let mut sk = Self::Target::new(CurvesId::Secp256r1);
-        let keylen = sk.key.len();
-        sk.key.copy_from_slice(&tmp.0[..keylen]);
-        (sk, Some(cc))
-    }
-}
+impl_bip32_seed_derive!(
+    Secp256k1, 32;
+    Secp256r1, 32;
+    Secp384r1, 48;
+    BrainpoolP256R1, 32;
+    BrainpoolP256T1, 32;
+    BrainpoolP320R1, 40;
+    BrainpoolP320T1, 40;
+    BrainpoolP384R1, 48;
+    BrainpoolP384T1, 48;
+    BrainpoolP512R1, 64;
+    BrainpoolP512T1, 64;
+);
 
 impl Pallas {
     /// Support ZIP32 Orchard derivation for Pallas
@@ -164,35 +195,15 @@ impl Pallas {
         cc: Option<&mut ChainCode>,
         seed: Option<&[u8]>,
     ) -> Result<Secret<32>, CxError> {
-        let mut tmp = Secret::<32>::new();
-        let (cc_ptr, cc_len) = match cc {
-            Some(cc) => (cc.value.as_mut_ptr(), 32usize),
-            None => (core::ptr::null_mut(), 0usize),
-        };
-        let (seed_ptr, seed_len) = match seed {
-            Some(s) => (s.as_ptr() as *mut u8, s.len()),
-            None => (core::ptr::null_mut(), 0usize),
-        };
-        let err = unsafe {
-            sys_hdkey_derive(
-                HDKeyDeriveMode::Zip32Orchard as u8,
-                ledger_secure_sdk_sys::CX_CURVE_NONE,
-                path.as_ptr(),
-                path.len(),
-                tmp.as_mut().as_mut_ptr(),
-                32,
-                cc_ptr,
-                cc_len,
-                seed_ptr,
-                seed_len,
-            )
-        };
-        if err != CX_OK {
-            return Err(err.into());
-        }
         let mut sk = Secret::<32>::new();
-        let keylen = sk.0.len();
-        sk.0.copy_from_slice(&tmp.0[..keylen]);
+        hdkey_derive(
+            HDKeyDeriveMode::Zip32Orchard,
+            CurvesId::Pallas,
+            path,
+            sk.as_mut(),
+            cc,
+            seed,
+        )?;
         Ok(sk)
     }
 }
@@ -200,10 +211,12 @@ impl Pallas {
 impl SeedDerive for Pallas {
     type Target = Secret<32>;
     fn derive_from(path: &[u32]) -> (Self::Target, Option<ChainCode>) {
+        Self::try_derive_from(path).expect("seed derivation failed")
+    }
+    fn try_derive_from(path: &[u32]) -> Result<(Self::Target, Option<ChainCode>), CxError> {
         let mut cc: ChainCode = Default::default();
-        let sk = Self::zip32_orchard_derive(path, Some(&mut cc), None)
-            .expect("zip32_orchard_derive failed");
-        (sk, Some(cc))
+        let sk = Self::zip32_orchard_derive(path, Some(&mut cc), None)?;
+        Ok((sk, Some(cc)))
     }
 }
 
@@ -300,6 +313,96 @@ mod tests {
         assert_eq!(pk.verify((&s.0, s.1), TEST_HASH), true);
     }
 
+    #[test]
+    fn ecdsa_secp384r1_derived() {
+        let sk = Secp384r1::try_derive_from_path(&PATH0).map_err(display_error_code)?;
+        let s = sk
+            .deterministic_sign(TEST_HASH)
+            .map_err(display_error_code)?;
+        let pk = sk.public_key().map_err(display_error_code)?;
+        assert_eq!(pk.verify((&s.0, s.1), TEST_HASH), true);
+    }
+
+    #[test]
+    fn ecdsa_brainpool256r1_derived() {
+        let sk = BrainpoolP256R1::try_derive_from_path(&PATH0).map_err(display_error_code)?;
+        let s = sk
+            .deterministic_sign(TEST_HASH)
+            .map_err(display_error_code)?;
+        let pk = sk.public_key().map_err(display_error_code)?;
+        assert_eq!(pk.verify((&s.0, s.1), TEST_HASH), true);
+    }
+
+    #[test]
+    fn ecdsa_brainpool256t1_derived() {
+        let sk = BrainpoolP256T1::try_derive_from_path(&PATH0).map_err(display_error_code)?;
+        let s = sk
+            .deterministic_sign(TEST_HASH)
+            .map_err(display_error_code)?;
+        let pk = sk.public_key().map_err(display_error_code)?;
+        assert_eq!(pk.verify((&s.0, s.1), TEST_HASH), true);
+    }
+
+    #[test]
+    fn ecdsa_brainpool320r1_derived() {
+        let sk = BrainpoolP320R1::try_derive_from_path(&PATH0).map_err(display_error_code)?;
+        let s = sk
+            .deterministic_sign(TEST_HASH)
+            .map_err(display_error_code)?;
+        let pk = sk.public_key().map_err(display_error_code)?;
+        assert_eq!(pk.verify((&s.0, s.1), TEST_HASH), true);
+    }
+
+    #[test]
+    fn ecdsa_brainpool320t1_derived() {
+        let sk = BrainpoolP320T1::try_derive_from_path(&PATH0).map_err(display_error_code)?;
+        let s = sk
+            .deterministic_sign(TEST_HASH)
+            .map_err(display_error_code)?;
+        let pk = sk.public_key().map_err(display_error_code)?;
+        assert_eq!(pk.verify((&s.0, s.1), TEST_HASH), true);
+    }
+
+    #[test]
+    fn ecdsa_brainpool384r1_derived() {
+        let sk = BrainpoolP384R1::try_derive_from_path(&PATH0).map_err(display_error_code)?;
+        let s = sk
+            .deterministic_sign(TEST_HASH)
+            .map_err(display_error_code)?;
+        let pk = sk.public_key().map_err(display_error_code)?;
+        assert_eq!(pk.verify((&s.0, s.1), TEST_HASH), true);
+    }
+
+    #[test]
+    fn ecdsa_brainpool384t1_derived() {
+        let sk = BrainpoolP384T1::try_derive_from_path(&PATH0).map_err(display_error_code)?;
+        let s = sk
+            .deterministic_sign(TEST_HASH)
+            .map_err(display_error_code)?;
+        let pk = sk.public_key().map_err(display_error_code)?;
+        assert_eq!(pk.verify((&s.0, s.1), TEST_HASH), true);
+    }
+
+    #[test]
+    fn ecdsa_brainpool512r1_derived() {
+        let sk = BrainpoolP512R1::try_derive_from_path(&PATH0).map_err(display_error_code)?;
+        let s = sk
+            .deterministic_sign(TEST_HASH)
+            .map_err(display_error_code)?;
+        let pk = sk.public_key().map_err(display_error_code)?;
+        assert_eq!(pk.verify((&s.0, s.1), TEST_HASH), true);
+    }
+
+    #[test]
+    fn ecdsa_brainpool512t1_derived() {
+        let sk = BrainpoolP512T1::try_derive_from_path(&PATH0).map_err(display_error_code)?;
+        let s = sk
+            .deterministic_sign(TEST_HASH)
+            .map_err(display_error_code)?;
+        let pk = sk.public_key().map_err(display_error_code)?;
+        assert_eq!(pk.verify((&s.0, s.1), TEST_HASH), true);
+    }
+
     #[test]
     fn ecdsa_brainpool256r1() {
         let mut sk = BrainpoolP256R1::new();
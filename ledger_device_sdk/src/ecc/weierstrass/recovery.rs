@@ -0,0 +1,346 @@
+//! Recoverable ECDSA signatures on Secp256k1, as used by Ethereum.
+//!
+//! ```
+//! let sk = Secp256k1::derive_from_path(&PATH);
+//! let signature = sk.sign_recoverable(&hash)?;
+//! // EIP-155 encoding of the recovery id
+//! let v = signature.v(Some(chain_id))?;
+//!
+//! let public_key = ECPublicKey::<65, 'W'>::recover_public_key(&hash, &signature)?;
+//! ```
+
+use super::{SECP256K1_N, SECP256K1_P};
+use crate::bn::Bn;
+use crate::ecc::{CurvesId, CxError, ECPrivateKey, ECPublicKey, EcPoint, EcdsaSignature};
+use ledger_secure_sdk_sys::{
+    CX_ECCINFO_PARITY_ODD, CX_ECCINFO_xGTn, CX_LAST, CX_RND_RFC6979, CX_SHA256,
+};
+
+/// Half of the order of the Secp256k1 group, rounded down.
+const SECP256K1_HALF_N: [u8; 32] = [
+    0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0x5d, 0x57, 0x6e, 0x73, 0x57, 0xa4, 0x50, 0x1d, 0xdf, 0xe9, 0x2f, 0x46, 0x68, 0x1b, 0x20, 0xa0,
+];
+
+/// Offset of the `v` value of legacy Ethereum signatures.
+const LEGACY_V_OFFSET: u64 = 27;
+/// Offset of the `v` value of EIP-155 signatures, added to twice the chain id.
+const EIP155_V_OFFSET: u64 = 35;
+
+/// ECDSA signature along with the recovery id, which identifies the public
+/// key among the ones for which the signature is valid.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct RecoverableSignature {
+    pub r: [u8; 32],
+    pub s: [u8; 32],
+    /// Bit 0 is the parity of the y coordinate of the point R, and bit 1 is
+    /// set if its x coordinate is greater than the group order.
+    pub recovery_id: u8,
+}
+
+impl RecoverableSignature {
+    /// # Errors
+    ///
+    /// Fails with [`CxError::InvalidParameter`] if `recovery_id` is greater
+    /// than 3.
+    pub fn new(r: [u8; 32], s: [u8; 32], recovery_id: u8) -> Result<Self, CxError> {
+        if recovery_id > 3 {
+            return Err(CxError::InvalidParameter);
+        }
+        Ok(RecoverableSignature { r, s, recovery_id })
+    }
+
+    /// Creates a signature from its Ethereum encoding. `v` is expected to be
+    /// 27 or 28 when `chain_id` is None, and to follow EIP-155 otherwise.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`CxError::InvalidParameter`] if `v` does not match
+    /// `chain_id`.
+    pub fn from_v(
+        r: [u8; 32],
+        s: [u8; 32],
+        v: u64,
+        chain_id: Option<u64>,
+    ) -> Result<Self, CxError> {
+        let offset = match chain_id {
+            Some(id) => id
+                .checked_mul(2)
+                .and_then(|x| x.checked_add(EIP155_V_OFFSET))
+                .ok_or(CxError::InvalidParameter)?,
+            None => LEGACY_V_OFFSET,
+        };
+        match v.checked_sub(offset) {
+            Some(id @ (0 | 1)) => Self::new(r, s, id as u8),
+            _ => Err(CxError::InvalidParameter),
+        }
+    }
+
+    /// Returns the Ethereum encoding of the recovery id: 27 or 28 when
+    /// `chain_id` is None, or `chain_id * 2 + 35` plus the parity with EIP-155.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`CxError::InvalidParameter`] if the recovery id is 2 or 3,
+    /// which Ethereum cannot represent, or if the EIP-155 value overflows.
+    /// Recovery ids 2 and 3 are set by
+    /// [`sign_recoverable`](ECPrivateKey::sign_recoverable) when the x
+    /// coordinate of R is greater than the group order, which only happens
+    /// with a negligible probability.
+    pub fn v(&self, chain_id: Option<u64>) -> Result<u64, CxError> {
+        if self.recovery_id > 1 {
+            return Err(CxError::InvalidParameter);
+        }
+        let offset = match chain_id {
+            Some(id) => id
+                .checked_mul(2)
+                .and_then(|x| x.checked_add(EIP155_V_OFFSET))
+                .ok_or(CxError::InvalidParameter)?,
+            None => LEGACY_V_OFFSET,
+        };
+        offset
+            .checked_add(self.recovery_id as u64)
+            .ok_or(CxError::InvalidParameter)
+    }
+
+    /// Returns true if `s` is at most half the group order, as required by
+    /// Ethereum transactions and Bitcoin standardness rules.
+    pub fn is_low_s(&self) -> bool {
+        self.s <= SECP256K1_HALF_N
+    }
+
+    /// Replaces `s` with `n - s` if it is greater than half the group order.
+    /// The signature stays valid, for the opposite point R.
+    pub fn normalize_s(&mut self) -> Result<(), CxError> {
+        let mut signature = self.signature();
+        if signature.normalize_s(CurvesId::Secp256k1)? {
+            self.s = signature.s;
+            self.recovery_id ^= 1;
+        }
+        Ok(())
+    }
+
+    /// Returns the 64-byte concatenation of `r` and `s`.
+    pub fn to_compact(&self) -> [u8; 64] {
+        self.signature().to_compact()
+    }
+
+    /// Returns the signature without its recovery id.
+    pub fn signature(&self) -> EcdsaSignature<32> {
+        EcdsaSignature::new(self.r, self.s)
+    }
+}
+
+/// Recoverable ECDSA signatures for Secp256k1 private keys.
+impl ECPrivateKey<32, 'W'> {
+    /// Signs `hash` using ECDSA with RFC6979, and returns the signature with
+    /// its recovery id. The signature is normalized to a low `s`.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`CxError::InvalidCurve`] if the key is not on Secp256k1.
+    pub fn sign_recoverable(&self, hash: &[u8]) -> Result<RecoverableSignature, CxError> {
+        if !matches!(self.curve, CurvesId::Secp256k1) {
+            return Err(CxError::InvalidCurve);
+        }
+        let (der, len, info) = self.ecdsa_sign(hash, CX_SHA256, CX_RND_RFC6979 | CX_LAST)?;
+        let EcdsaSignature { r, s } = EcdsaSignature::<32>::from_der(&der[..len as usize])?;
+        let mut recovery_id = 0;
+        if info & CX_ECCINFO_PARITY_ODD != 0 {
+            recovery_id |= 1;
+        }
+        if info & CX_ECCINFO_xGTn != 0 {
+            recovery_id |= 2;
+        }
+        let mut signature = RecoverableSignature::new(r, s, recovery_id)?;
+        signature.normalize_s()?;
+        Ok(signature)
+    }
+}
+
+fn is_scalar(x: &[u8; 32]) -> bool {
+    *x != [0u8; 32] && *x < SECP256K1_N
+}
+
+/// Public key recovery for Secp256k1.
+impl ECPublicKey<65, 'W'> {
+    /// Recovers the Secp256k1 public key for which `signature` is a valid
+    /// signature of `hash`.
+    ///
+    /// # Errors
+    ///
+    /// * [`CxError::InvalidParameter`] if `r` or `s` is not in `[1, n - 1]`,
+    /// * [`CxError::InvalidPoint`] if no point R matches `r` and the recovery
+    ///   id,
+    /// * [`CxError::PointAtInfinity`] if the recovered point is the point at
+    ///   infinity.
+    pub fn recover_public_key(
+        hash: &[u8],
+        signature: &RecoverableSignature,
+    ) -> Result<ECPublicKey<65, 'W'>, CxError> {
+        let RecoverableSignature { r, s, recovery_id } = *signature;
+        if !is_scalar(&r) || !is_scalar(&s) || recovery_id > 3 {
+            return Err(CxError::InvalidParameter);
+        }
+
+        // x coordinate of R: r, or r + n
+        let mut x = r;
+        if recovery_id & 2 != 0 {
+            let mut carry = 0u16;
+            for (x, n) in x.iter_mut().zip(SECP256K1_N.iter()).rev() {
+                let sum = *x as u16 + *n as u16 + carry;
+                *x = sum as u8;
+                carry = sum >> 8;
+            }
+            if carry != 0 {
+                return Err(CxError::InvalidPoint);
+            }
+        }
+        if x >= SECP256K1_P {
+            return Err(CxError::InvalidPoint);
+        }
+        let mut point_r = EcPoint::new(CurvesId::Secp256k1)?;
+        point_r.decompress(&x, (recovery_id & 1) as u32)?;
+
+        // The hash is truncated to the size of the group order
+        let mut e = [0u8; 32];
+        let len = hash.len().min(32);
+        e[32 - len..].copy_from_slice(&hash[..len]);
+
+        // Q = r^-1 (s·R - e·G) = (-e·r^-1)·G + (s·r^-1)·R
+        let n = Bn::alloc_init(&SECP256K1_N)?;
+        let bn_r = Bn::alloc_init(&r)?;
+        let r_inv = Bn::alloc(32)?;
+        r_inv.mod_invert_nprime(&bn_r, &n)?;
+        let bn_e = Bn::alloc_init(&e)?;
+        let e_mod_n = Bn::alloc(32)?;
+        e_mod_n.reduce(&bn_e, &n)?;
+        let zero = Bn::alloc_init(&[0u8; 32])?;
+        let neg_e = Bn::alloc(32)?;
+        neg_e.mod_sub(&zero, &e_mod_n, &n)?;
+        let u1 = Bn::alloc(32)?;
+        u1.mod_mul(&neg_e, &r_inv, &n)?;
+        let bn_s = Bn::alloc_init(&s)?;
+        let u2 = Bn::alloc(32)?;
+        u2.mod_mul(&bn_s, &r_inv, &n)?;
+        let mut u1_bytes = [0u8; 32];
+        let mut u2_bytes = [0u8; 32];
+        u1.export(&mut u1_bytes)?;
+        u2.export(&mut u2_bytes)?;
+
+        let mut generator = EcPoint::new(CurvesId::Secp256k1)?;
+        CurvesId::Secp256k1.generator_bn(&mut generator)?;
+        let mut q = EcPoint::new(CurvesId::Secp256k1)?;
+        q.double_scalarmul(&mut generator, &mut point_r, &u1_bytes, &u2_bytes)?;
+        if q.is_at_infinity()? {
+            return Err(CxError::PointAtInfinity);
+        }
+
+        let mut public_key = ECPublicKey::<65, 'W'>::new(CurvesId::Secp256k1);
+        public_key.pubkey[0] = 0x04;
+        let (x, y) = public_key.pubkey[1..].split_at_mut(32);
+        q.export(x, y)?;
+        Ok(public_key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_eq_err as assert_eq;
+    use crate::ecc::{Secp256k1, Secp256r1};
+    use crate::testing::{TestType, hex};
+    use testmacro::test_item as test;
+
+    const SECRET_KEY: &str = "b7e151628aed2a6abf7158809cf4f3c762e7160f38b4da56a784d9045190cfef";
+    const PUBLIC_KEY: &str = "04dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba6592ce19b946c4ee58546f5251d441a065ea50735606985e5b228788bec4e582898";
+    // SHA256("recoverable")
+    const HASH: &str = "4e9e8bb939136f139c0830a0c33ef4faddc541261dba7a58effb41a4302f88dd";
+
+    #[test]
+    fn recover_public_key() {
+        let hash = hex::<32>(HASH);
+        let expected = hex::<65>(PUBLIC_KEY);
+        // Signature with a high s, and its normalized form
+        let mut signature = RecoverableSignature::new(
+            hex("bb50e2d89a4ed70663d080659fe0ad4b9bc3e06c17a227433966cb59ceee020d"),
+            hex("e96a41ed3c0c79cbf299b205c46c4606d008fddfd763bec415465fc979ade875"),
+            0,
+        )
+        .unwrap();
+        assert_eq!(signature.is_low_s(), false);
+        let public_key = ECPublicKey::<65, 'W'>::recover_public_key(&hash, &signature).unwrap();
+        assert_eq!(public_key.pubkey, expected);
+
+        assert_eq!(signature.normalize_s(), Ok(()));
+        assert_eq!(
+            signature.s,
+            hex("1695be12c3f386340d664dfa3b93b9f7eaa5df06d7e4e177aa8bfec3568858cc")
+        );
+        assert_eq!(signature.recovery_id, 1);
+        let public_key = ECPublicKey::<65, 'W'>::recover_public_key(&hash, &signature).unwrap();
+        assert_eq!(public_key.pubkey, expected);
+
+        // The other parity gives another key
+        signature.recovery_id = 0;
+        let public_key = ECPublicKey::<65, 'W'>::recover_public_key(&hash, &signature).unwrap();
+        assert_eq!(public_key.pubkey == expected, false);
+
+        signature.s = SECP256K1_N;
+        assert_eq!(
+            ECPublicKey::<65, 'W'>::recover_public_key(&hash, &signature).err(),
+            Some(CxError::InvalidParameter)
+        );
+    }
+
+    #[test]
+    fn sign_recoverable() {
+        let sk = Secp256k1::from(&hex::<32>(SECRET_KEY));
+        let hash = hex::<32>(HASH);
+        let signature = sk.sign_recoverable(&hash).unwrap();
+        assert_eq!(signature.is_low_s(), true);
+        let public_key = ECPublicKey::<65, 'W'>::recover_public_key(&hash, &signature).unwrap();
+        assert_eq!(public_key.pubkey, hex::<65>(PUBLIC_KEY));
+        let compact = signature.to_compact();
+        assert_eq!(&compact[..32], &signature.r[..]);
+        assert_eq!(&compact[32..], &signature.s[..]);
+
+        assert_eq!(
+            Secp256r1::from(&hex::<32>(SECRET_KEY))
+                .sign_recoverable(&hash)
+                .err(),
+            Some(CxError::InvalidCurve)
+        );
+    }
+
+    #[test]
+    fn eip155_v() {
+        let r = [1u8; 32];
+        let s = [2u8; 32];
+        let signature = RecoverableSignature::new(r, s, 1).unwrap();
+        assert_eq!(signature.v(None), Ok(28));
+        assert_eq!(signature.v(Some(1)), Ok(38));
+        assert_eq!(signature.v(Some(u64::MAX)), Err(CxError::InvalidParameter));
+        assert_eq!(
+            RecoverableSignature::new(r, s, 2).unwrap().v(None),
+            Err(CxError::InvalidParameter)
+        );
+        assert_eq!(
+            RecoverableSignature::from_v(r, s, 38, Some(1)),
+            Ok(signature)
+        );
+        assert_eq!(RecoverableSignature::from_v(r, s, 28, None), Ok(signature));
+        assert_eq!(
+            RecoverableSignature::from_v(r, s, 37, Some(1)).map(|s| s.recovery_id),
+            Ok(0)
+        );
+        assert_eq!(
+            RecoverableSignature::from_v(r, s, 38, Some(2)),
+            Err(CxError::InvalidParameter)
+        );
+        assert_eq!(
+            RecoverableSignature::from_v(r, s, 29, None),
+            Err(CxError::InvalidParameter)
+        );
+    }
+}
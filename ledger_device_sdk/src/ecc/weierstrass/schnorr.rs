@@ -0,0 +1,463 @@
+//! BIP340 Schnorr signatures and BIP341 key tweaking on Secp256k1.
+//!
+//! ```
+//! let sk = Secp256k1::derive_from_path(&PATH);
+//! let (public_key, _) = sk.x_only_public_key()?;
+//! let signature = sk.sign_bip340(&msg, None)?;
+//! assert!(public_key.verify(&signature, &msg));
+//!
+//! // Taproot key path spending, without script tree
+//! let (output_key, parity) = public_key.tap_tweak(None)?;
+//! let signature = sk.tap_tweak(None)?.sign_bip340(&sighash, None)?;
+//! ```
+
+use super::{SECP256K1_N, SECP256K1_P};
+use crate::bn::Bn;
+use crate::ecc::{CurvesId, CxError, ECPrivateKey, ECPublicKey, EcPoint, Secp256k1, Secret};
+use crate::hash::{HashInit, sha2::Sha2_256};
+use crate::random::rand_bytes;
+use zeroize::Zeroize;
+
+const ONE: [u8; 32] = {
+    let mut one = [0u8; 32];
+    one[31] = 1;
+    one
+};
+
+/// Computes the tagged hash defined in BIP340:
+/// `SHA256(SHA256(tag) || SHA256(tag) || data[0] || data[1] || ...)`.
+pub fn tagged_hash(tag: &[u8], data: &[&[u8]]) -> [u8; 32] {
+    let mut tag_hash = [0u8; 32];
+    let mut hash = [0u8; 32];
+    // Hashing cannot fail with a 32-byte output
+    let _ = Sha2_256::new().hash(tag, &mut tag_hash);
+    let mut sha256 = Sha2_256::new();
+    let _ = sha256.update(&tag_hash);
+    let _ = sha256.update(&tag_hash);
+    for chunk in data {
+        let _ = sha256.update(chunk);
+    }
+    let _ = sha256.finalize(&mut hash);
+    hash
+}
+
+/// Returns `a - b` for big-endian integers, with `a >= b`.
+fn sub(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+    let mut r = [0u8; 32];
+    let mut borrow = 0;
+    for i in (0..32).rev() {
+        let d = a[i] as i16 - b[i] as i16 - borrow;
+        borrow = (d < 0) as i16;
+        r[i] = d as u8;
+    }
+    r
+}
+
+/// Reduces a 256-bit integer modulo the group order.
+fn reduce(x: &[u8; 32]) -> [u8; 32] {
+    // x < 2^256 < 2n
+    if *x >= SECP256K1_N {
+        sub(x, &SECP256K1_N)
+    } else {
+        *x
+    }
+}
+
+/// Returns `n - x` for a non-zero scalar `x`.
+fn negate(x: &[u8; 32]) -> [u8; 32] {
+    sub(&SECP256K1_N, x)
+}
+
+fn is_scalar(x: &[u8; 32]) -> bool {
+    *x != [0u8; 32] && *x < SECP256K1_N
+}
+
+/// Returns `(a + b * c) mod n`, for scalars smaller than `n`.
+fn mul_add(a: &[u8; 32], b: &[u8; 32], c: &[u8; 32]) -> Result<[u8; 32], CxError> {
+    let n = Bn::alloc_init(&SECP256K1_N)?;
+    let a = Bn::alloc_init(a)?;
+    let b = Bn::alloc_init(b)?;
+    let c = Bn::alloc_init(c)?;
+    let bc = Bn::alloc(32)?;
+    bc.mod_mul(&b, &c, &n)?;
+    let r = Bn::alloc(32)?;
+    r.mod_add(&a, &bc, &n)?;
+    let mut out = [0u8; 32];
+    r.export(&mut out)?;
+    Ok(out)
+}
+
+fn export(point: &EcPoint) -> Result<([u8; 32], [u8; 32]), CxError> {
+    let mut x = [0u8; 32];
+    let mut y = [0u8; 32];
+    point.export(&mut x, &mut y)?;
+    Ok((x, y))
+}
+
+fn is_odd(y: &[u8; 32]) -> bool {
+    y[31] & 1 == 1
+}
+
+/// Returns `k·G`. The multiplication is randomized, as `k` may be secret.
+fn mul_generator(k: &[u8; 32]) -> Result<EcPoint, CxError> {
+    let mut point = EcPoint::new(CurvesId::Secp256k1)?;
+    CurvesId::Secp256k1.generator_bn(&mut point)?;
+    point.rnd_scalarmul(k)?;
+    Ok(point)
+}
+
+/// Returns the point with x coordinate `x` and an even y coordinate.
+fn lift_x(x: &[u8; 32]) -> Result<EcPoint, CxError> {
+    if *x >= SECP256K1_P {
+        return Err(CxError::InvalidPoint);
+    }
+    let mut point = EcPoint::new(CurvesId::Secp256k1)?;
+    point.decompress(x, 0)?;
+    Ok(point)
+}
+
+/// A BIP340 public key: the x coordinate of a Secp256k1 point, whose y
+/// coordinate is implicitly even.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct XOnlyPublicKey([u8; 32]);
+
+impl XOnlyPublicKey {
+    /// Creates a public key from its serialization.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`CxError::InvalidPoint`] if `bytes` is not the x coordinate
+    /// of a point of the curve.
+    pub fn from_bytes(bytes: &[u8; 32]) -> Result<XOnlyPublicKey, CxError> {
+        lift_x(bytes)?;
+        Ok(XOnlyPublicKey(*bytes))
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// Verifies a BIP340 signature of `msg`.
+    pub fn verify(&self, signature: &[u8; 64], msg: &[u8]) -> bool {
+        self.verify_inner(signature, msg).unwrap_or(false)
+    }
+
+    fn verify_inner(&self, signature: &[u8; 64], msg: &[u8]) -> Result<bool, CxError> {
+        let mut r = [0u8; 32];
+        let mut s = [0u8; 32];
+        r.copy_from_slice(&signature[..32]);
+        s.copy_from_slice(&signature[32..]);
+        if r >= SECP256K1_P || s >= SECP256K1_N {
+            return Ok(false);
+        }
+        let e = reduce(&tagged_hash(b"BIP0340/challenge", &[&r, &self.0, msg]));
+
+        // R = s·G - e·P
+        let mut s_g = EcPoint::new(CurvesId::Secp256k1)?;
+        CurvesId::Secp256k1.generator_bn(&mut s_g)?;
+        s_g.scalarmul(&s)?;
+        let mut e_p = lift_x(&self.0)?;
+        e_p.scalarmul(&e)?;
+        e_p.neg()?;
+        let mut point_r = EcPoint::new(CurvesId::Secp256k1)?;
+        point_r.add(&s_g, &e_p)?;
+        if point_r.is_at_infinity()? {
+            return Ok(false);
+        }
+        let (x, y) = export(&point_r)?;
+        Ok(!is_odd(&y) && x == r)
+    }
+
+    /// Tweaks the key as defined in BIP341, to obtain the output key of a
+    /// Taproot output whose internal key is `self`. `merkle_root` is the root
+    /// of the script tree, if any.
+    ///
+    /// Returns the output key, and true if its y coordinate is odd (the parity
+    /// bit of the control block).
+    pub fn tap_tweak(
+        &self,
+        merkle_root: Option<&[u8; 32]>,
+    ) -> Result<(XOnlyPublicKey, bool), CxError> {
+        let t = tap_tweak_hash(&self.0, merkle_root)?;
+        let p = lift_x(&self.0)?;
+        let mut t_g = EcPoint::new(CurvesId::Secp256k1)?;
+        CurvesId::Secp256k1.generator_bn(&mut t_g)?;
+        t_g.scalarmul(&t)?;
+        let mut q = EcPoint::new(CurvesId::Secp256k1)?;
+        q.add(&p, &t_g)?;
+        if q.is_at_infinity()? {
+            return Err(CxError::PointAtInfinity);
+        }
+        let (x, y) = export(&q)?;
+        Ok((XOnlyPublicKey(x), is_odd(&y)))
+    }
+}
+
+impl AsRef<[u8]> for XOnlyPublicKey {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<XOnlyPublicKey> for [u8; 32] {
+    fn from(key: XOnlyPublicKey) -> Self {
+        key.0
+    }
+}
+
+/// Returns the BIP341 tweak of the internal key `x`.
+fn tap_tweak_hash(x: &[u8; 32], merkle_root: Option<&[u8; 32]>) -> Result<[u8; 32], CxError> {
+    let t = match merkle_root {
+        Some(root) => tagged_hash(b"TapTweak", &[x, root]),
+        None => tagged_hash(b"TapTweak", &[x]),
+    };
+    if t >= SECP256K1_N {
+        return Err(CxError::InvalidParameter);
+    }
+    Ok(t)
+}
+
+/// BIP340 and BIP341 support for Secp256k1 private keys.
+///
+/// These methods fail with [`CxError::InvalidCurve`] when the key is on
+/// another curve.
+impl ECPrivateKey<32, 'W'> {
+    fn check_secp256k1(&self) -> Result<(), CxError> {
+        match self.curve {
+            CurvesId::Secp256k1 if is_scalar(&self.key) => Ok(()),
+            CurvesId::Secp256k1 => Err(CxError::InvalidParameter),
+            _ => Err(CxError::InvalidCurve),
+        }
+    }
+
+    /// Returns the x coordinate of the public point, and the scalar to sign
+    /// with: the key, or its negation if the y coordinate is odd.
+    fn even_y_key(&self) -> Result<([u8; 32], Secret<32>), CxError> {
+        self.check_secp256k1()?;
+        let (x, y) = export(&mul_generator(&self.key)?)?;
+        let mut d = Secret::<32>::new();
+        d.0 = if is_odd(&y) {
+            negate(&self.key)
+        } else {
+            self.key
+        };
+        Ok((x, d))
+    }
+
+    /// Returns the BIP340 public key, and true if the y coordinate of the
+    /// public point is odd.
+    pub fn x_only_public_key(&self) -> Result<(XOnlyPublicKey, bool), CxError> {
+        self.check_secp256k1()?;
+        let (x, y) = export(&mul_generator(&self.key)?)?;
+        Ok((XOnlyPublicKey(x), is_odd(&y)))
+    }
+
+    /// Signs `msg` with BIP340 Schnorr.
+    ///
+    /// `aux_rand` is the auxiliary randomness mixed into the nonce. When None,
+    /// 32 random bytes are used, as recommended by BIP340.
+    ///
+    /// The signature is verified before being returned.
+    pub fn sign_bip340(
+        &self,
+        msg: &[u8],
+        aux_rand: Option<&[u8; 32]>,
+    ) -> Result<[u8; 64], CxError> {
+        let (px, d) = self.even_y_key()?;
+        let mut aux = [0u8; 32];
+        match aux_rand {
+            Some(a) => aux = *a,
+            None => rand_bytes(&mut aux),
+        }
+
+        let mut t = Secret::<32>::new();
+        t.0 = tagged_hash(b"BIP0340/aux", &[&aux]);
+        for (t, d) in t.0.iter_mut().zip(d.0.iter()) {
+            *t ^= d;
+        }
+        let mut k = Secret::<32>::new();
+        k.0 = reduce(&tagged_hash(b"BIP0340/nonce", &[&t.0, &px, msg]));
+        if k.0 == [0u8; 32] {
+            return Err(CxError::GenericError);
+        }
+        let (rx, ry) = export(&mul_generator(&k.0)?)?;
+        if is_odd(&ry) {
+            k.0 = negate(&k.0);
+        }
+        let e = reduce(&tagged_hash(b"BIP0340/challenge", &[&rx, &px, msg]));
+
+        let mut signature = [0u8; 64];
+        signature[..32].copy_from_slice(&rx);
+        signature[32..].copy_from_slice(&mul_add(&k.0, &e, &d.0)?);
+        aux.zeroize();
+
+        if !XOnlyPublicKey(px).verify(&signature, msg) {
+            return Err(CxError::GenericError);
+        }
+        Ok(signature)
+    }
+
+    /// Tweaks the key as defined in BIP341, to obtain the key signing for a
+    /// Taproot output whose internal key is the public key of `self`.
+    /// `merkle_root` is the root of the script tree, if any.
+    pub fn tap_tweak(
+        &self,
+        merkle_root: Option<&[u8; 32]>,
+    ) -> Result<ECPrivateKey<32, 'W'>, CxError> {
+        let (px, d) = self.even_y_key()?;
+        let t = tap_tweak_hash(&px, merkle_root)?;
+        let mut sk = Secp256k1::new();
+        sk.key = mul_add(&t, &d.0, &ONE)?;
+        sk.check_secp256k1()?;
+        Ok(sk)
+    }
+}
+
+/// BIP340 support for uncompressed Secp256k1 public keys.
+impl ECPublicKey<65, 'W'> {
+    /// Returns the BIP340 public key, and true if the y coordinate of the
+    /// point is odd.
+    pub fn x_only(&self) -> (XOnlyPublicKey, bool) {
+        let mut x = [0u8; 32];
+        x.copy_from_slice(&self.pubkey[1..33]);
+        (XOnlyPublicKey(x), self.pubkey[64] & 1 == 1)
+    }
+
+    /// Verifies a BIP340 signature of `msg`. Returns false if the key is not
+    /// on Secp256k1.
+    pub fn verify_bip340(&self, signature: &[u8; 64], msg: &[u8]) -> bool {
+        matches!(self.curve, CurvesId::Secp256k1) && self.x_only().0.verify(signature, msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_eq_err as assert_eq;
+    use crate::testing::{TestType, hex};
+    use testmacro::test_item as test;
+
+    /// BIP340 test vectors 0 to 3: secret key, public key, aux_rand, message,
+    /// signature.
+    const SIGNING_VECTORS: [[&str; 5]; 4] = [
+        [
+            "0000000000000000000000000000000000000000000000000000000000000003",
+            "F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "E907831F80848D1069A5371B402410364BDF1C5F8307B0084C55F1CE2DCA821525F66A4A85EA8B71E482A74F382D2CE5EBEEE8FDB2172F477DF4900D310536C0",
+        ],
+        [
+            "B7E151628AED2A6ABF7158809CF4F3C762E7160F38B4DA56A784D9045190CFEF",
+            "DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+            "0000000000000000000000000000000000000000000000000000000000000001",
+            "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89",
+            "6896BD60EEAE296DB48A229FF71DFE071BDE413E6D43F917DC8DCF8C78DE33418906D11AC976ABCCB20B091292BFF4EA897EFCB639EA871CFA95F6DE339E4B0A",
+        ],
+        [
+            "C90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74020BBEA63B14E5C9",
+            "DD308AFEC5777E13121FA72B9CC1B7CC0139715309B086C960E18FD969774EB8",
+            "C87AA53824B4D7AE2EB035A2B5BBBCCC080E76CDC6D1692C4B0B62D798E6D906",
+            "7E2D58D8B3BCDF1ABADEC7829054F90DDA9805AAB56C77333024B9D0A508B75C",
+            "5831AAEED7B44BB74E5EAB94BA9D4294C49BCF2A60728D8B4C200F50DD313C1BAB745879A5AD954A72C45A91C3A51D3C7ADEA98D82F8481E0E1E03674A6F3FB7",
+        ],
+        [
+            "0B432B2677937381AEF05BB02A66ECD012773062CF3FA2549E44F58ED2401710",
+            "25D1DFF95105F5253C4022F628A996AD3A0D95FBF21D468A1B33F8C160D8F517",
+            "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF",
+            "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF",
+            "7EB0509757E246F19449885651611CB965ECC1A187DD51B64FDA1EDC9637D5EC97582B9CB13DB3933705B32BA982AF5AF25FD78881EBB32771FC5922EFC66EA3",
+        ],
+    ];
+
+    #[test]
+    fn bip340_sign() {
+        for [sk, pk, aux, msg, sig] in SIGNING_VECTORS {
+            let sk = Secp256k1::from(&hex::<32>(sk));
+            let pk = XOnlyPublicKey(hex(pk));
+            let msg = hex::<32>(msg);
+            let sig = hex::<64>(sig);
+            assert_eq!(sk.x_only_public_key().map(|k| k.0), Ok(pk));
+            assert_eq!(sk.sign_bip340(&msg, Some(&hex(aux))), Ok(sig));
+            assert_eq!(pk.verify(&sig, &msg), true);
+            let public_key = sk.public_key().unwrap();
+            assert_eq!(public_key.verify_bip340(&sig, &msg), true);
+        }
+    }
+
+    #[test]
+    fn bip340_verify() {
+        // Vector 4
+        let pk = XOnlyPublicKey::from_bytes(&hex(
+            "D69C3509BB99E412E68B0FE8544E72837DFA30746D8BE2AA65975F29D22DC7B9",
+        ))
+        .unwrap();
+        let msg = hex::<32>("4DF3C3F68FCC83B27E9D42C90431A72499F17875C81A599B566C9889B9696703");
+        let mut sig = hex::<64>(
+            "00000000000000000000003B78CE563F89A0ED9414F5AA28AD0D96D6795F9C6376AFB1548AF603B3EB45C9F8207DEE1060CB71C04E80F593060B07D28308D7F4",
+        );
+        assert_eq!(pk.verify(&sig, &msg), true);
+        sig[63] ^= 1;
+        assert_eq!(pk.verify(&sig, &msg), false);
+        // s equal to the group order
+        sig[32..].copy_from_slice(&SECP256K1_N);
+        assert_eq!(pk.verify(&sig, &msg), false);
+
+        // Vector 5: public key not on the curve
+        assert_eq!(
+            XOnlyPublicKey::from_bytes(&hex(
+                "EEFDEA4CDB677750A420FEE807EACF21EB9898AE79B9768766E4FAA04A2D4A34"
+            )),
+            Err(CxError::InvalidPoint)
+        );
+    }
+
+    #[test]
+    fn bip340_random_aux() {
+        let sk = Secp256k1::from(&[0x42u8; 32]);
+        let (pk, _) = sk.x_only_public_key().unwrap();
+        let sig = sk.sign_bip340(b"message", None).unwrap();
+        assert_eq!(pk.verify(&sig, b"message"), true);
+        assert_eq!(pk.verify(&sig, b"other message"), false);
+        assert_eq!(
+            crate::ecc::Secp256r1::from(&[0x42u8; 32])
+                .sign_bip340(b"message", None)
+                .err(),
+            Some(CxError::InvalidCurve)
+        );
+    }
+
+    #[test]
+    fn bip341_tweak() {
+        // From the BIP341 wallet test vectors
+        let internal = XOnlyPublicKey::from_bytes(&hex(
+            "d6889cb081036e0faefa3a35157ad71086b123b2b144b649798b494c300a961d",
+        ))
+        .unwrap();
+        let expected = XOnlyPublicKey(hex(
+            "53a1f6e454df1aa2776a2814a721372d6258050de330b3c6d10ee8f4e0dda343",
+        ));
+        assert_eq!(internal.tap_tweak(None), Ok((expected, true)));
+
+        let sk = Secp256k1::from(&hex::<32>(SIGNING_VECTORS[1][0]));
+        let (pk, _) = sk.x_only_public_key().unwrap();
+        let mut merkle_root = [0u8; 32];
+        for (i, b) in merkle_root.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        let tweaked_sk = sk.tap_tweak(Some(&merkle_root)).unwrap();
+        assert_eq!(
+            tweaked_sk.key,
+            hex("d393018af0abb123b1348a370d69575ffd77c219ba147b69630b939788b2bdfc")
+        );
+        let (output_key, odd) = pk.tap_tweak(Some(&merkle_root)).unwrap();
+        assert_eq!(
+            output_key,
+            XOnlyPublicKey(hex(
+                "6d525349f9ca494a8521b03eede166d21f94f8c2ea3d2bec7633f248e171901e"
+            ))
+        );
+        assert_eq!(odd, false);
+        assert_eq!(tweaked_sk.x_only_public_key().map(|k| k.0), Ok(output_key));
+    }
+}
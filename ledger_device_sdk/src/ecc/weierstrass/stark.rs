@@ -1,4 +1,4 @@
-use crate::ecc::{ChainCode, CurvesId, ECPrivateKey, Secret, SeedDerive, bip32_derive};
+use crate::ecc::{ChainCode, CurvesId, CxError, ECPrivateKey, Secret, SeedDerive, bip32_derive};
 use crate::impl_curve;
 use ledger_secure_sdk_sys::*;
 
@@ -19,17 +19,19 @@ const C_CX_STARK256_N: [u8; 32] = [
 impl SeedDerive for Stark256 {
     type Target = ECPrivateKey<32, 'W'>;
     fn derive_from(path: &[u32]) -> (Self::Target, Option<ChainCode>) {
+        Self::try_derive_from(path).expect("seed derivation failed")
+    }
+    fn try_derive_from(path: &[u32]) -> Result<(Self::Target, Option<ChainCode>), CxError> {
         let mut sk = Self::Target::new(CurvesId::Stark256);
-        eip2645_derive(path, &mut sk.key);
-        (sk, None)
+        eip2645_derive(path, &mut sk.key)?;
+        Ok((sk, None))
     }
 }
 
 /// https://github.com/ethereum/EIPs/blob/master/EIPS/eip-2645.md
-fn eip2645_derive(path: &[u32], key: &mut [u8]) {
+fn eip2645_derive(path: &[u32], key: &mut [u8]) -> Result<(), CxError> {
     let mut x_key = Secret::<64>::new();
-    // Ignoring 'Result' here because known to be valid
-    let _ = bip32_derive(CurvesId::Secp256k1, path, x_key.as_mut(), None);
+    bip32_derive(CurvesId::Secp256k1, path, x_key.as_mut(), None)?;
 
     let mut index = 0;
     let mut cmp = 0;
@@ -44,6 +46,7 @@ fn eip2645_derive(path: &[u32], key: &mut [u8]) {
         }
         index += 1;
     }
+    Ok(())
 }
 
 #[cfg(test)]
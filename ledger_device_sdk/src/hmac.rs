@@ -29,6 +29,15 @@ impl From<HMACError> for u32 {
     }
 }
 
+/// Compares two MACs in constant time.
+pub(crate) fn mac_eq(a: &[u8], b: &[u8]) -> bool {
+    let diff = a
+        .iter()
+        .zip(b.iter())
+        .fold(0u8, |acc, (x, y)| acc | (x ^ y));
+    a.len() == b.len() && diff == 0
+}
+
 /// Defines the behavior of a rust HMAC object.
 /// The implementation for a given algorithm is done using a rust macro
 /// to avoid code duplication since only the C structures and functions
@@ -0,0 +1,61 @@
+//! Interface shared by the legacy and the new `Comm` implementations.
+//!
+//! Both `io_legacy::Comm` and `io_new::Comm` implement [`IoComm`], and their
+//! response builders implement [`ResponseWriter`], so that code which only
+//! needs to wait for APDUs or to write response data (UX loops, NBGL callbacks,
+//! swap helpers) can be written once instead of being duplicated under
+//! `#[cfg(feature = "io_new")]` branches.
+//!
+//! # Migrating from the legacy API
+//!
+//! The legacy API is still the default, the new one being selected with the
+//! `io_new` feature. Helpers tied to one of the implementations are deprecated
+//! in favor of generic ones:
+//!
+//! | Deprecated                                  | Replacement                 |
+//! |---------------------------------------------|-----------------------------|
+//! | `UxEvent::block_and_get_event`              | `UxEvent::block_with_comm`  |
+//! | `SwapError::append_to_comm` (legacy)        | `SwapError::append_to`      |
+//! | `SwapError::append_to_response` (`io_new`)  | `SwapError::append_to`      |
+//! | `NbglReview::show`, ... (legacy)            | `NbglReview::show_with_comm`|
+//! | `NbglKeypad::ask` (legacy)                  | `NbglKeypad::ask_with_comm` |
+//! | `NbglReviewExtended::start` (legacy)        | `start_with_comm`           |
+//!
+//! The `*_with_comm` methods of the NBGL use cases take the `Comm` and return
+//! the results of the `io_new` API, e.g. `Result<bool, u8>` instead of a
+//! `SyncNbgl`. With `io_new`, `show`, `ask` and `start` are kept as aliases.
+
+use crate::io_legacy::{ApduHeader, Reply};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommError {
+    Overflow,
+    IoError,
+}
+
+/// Event loop operations of a `Comm`.
+///
+/// These are the operations needed by code running while the application is
+/// not waiting for a command itself, e.g. while a screen is displayed.
+pub trait IoComm {
+    /// Receives and processes the next event.
+    ///
+    /// Returns true if an application APDU has been received. It is kept
+    /// pending, and is returned by the next call to `next_command`.
+    fn poll_apdu(&mut self) -> bool;
+
+    /// Returns the header of the pending APDU, if any.
+    fn pending_apdu_header(&self) -> Option<ApduHeader>;
+
+    /// Replies to the pending APDU with a status word and no data.
+    fn reply_status(&mut self, reply: Reply);
+}
+
+/// Destination of response data.
+pub trait ResponseWriter {
+    /// Appends `data` to the response being built.
+    ///
+    /// Fails with [`CommError::Overflow`] if it does not fit, in which case
+    /// nothing is written.
+    fn append_data(&mut self, data: &[u8]) -> Result<(), CommError>;
+}
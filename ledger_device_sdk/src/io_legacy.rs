@@ -8,6 +8,7 @@ use ledger_secure_sdk_sys::seph as sys_seph;
 use ledger_secure_sdk_sys::*;
 
 use crate::io_callbacks::nbgl_register_callbacks;
+use crate::io_common::{CommError, IoComm, ResponseWriter};
 use crate::seph;
 
 #[cfg(any(
@@ -40,6 +41,7 @@ pub enum StatusWords {
     BadIns = 0x6e01,
     BadP1P2 = 0x6e02,
     BadLen = 0x6e03,
+    WrongLength = 0x6700,
     UserCancelled = 0x6985,
     Unknown = 0x6d00,
     Panic = 0xe000,
@@ -717,6 +719,40 @@ impl Comm {
     }
 }
 
+impl IoComm for Comm {
+    fn poll_apdu(&mut self) -> bool {
+        self.next_event_ahead::<ApduHeader>()
+    }
+
+    fn pending_apdu_header(&self) -> Option<ApduHeader> {
+        if self.event_pending && self.rx_length >= 5 {
+            return Some(*self.get_apdu_metadata());
+        }
+        None
+    }
+
+    fn reply_status(&mut self, reply: Reply) {
+        self.reply(reply);
+    }
+}
+
+impl ResponseWriter for Comm {
+    fn append_data(&mut self, data: &[u8]) -> Result<(), CommError> {
+        // Keep room for the status word.
+        if self.tx_length + data.len() + 2 > self.io_buffer.len() {
+            return Err(CommError::Overflow);
+        }
+        self.append(data);
+        Ok(())
+    }
+}
+
+/// Initialize the global reference to the Comm instance used by Nbgl.
+/// This function should be called from the main function of the application.
+pub fn init_comm(comm: &mut Comm) {
+    comm.nbgl_register_comm();
+}
+
 static mut CURRENT_COMM: *mut Comm = core::ptr::null_mut();
 
 fn default_nbgl_next_event_ahead() -> bool {
@@ -724,7 +760,7 @@ fn default_nbgl_next_event_ahead() -> bool {
         if CURRENT_COMM.is_null() {
             panic!("No Comm instance registered");
         }
-        (*CURRENT_COMM).next_event_ahead::<ApduHeader>()
+        (*CURRENT_COMM).poll_apdu()
     }
 }
 
@@ -733,11 +769,7 @@ fn default_nbgl_fetch_apdu_header() -> Option<ApduHeader> {
         if CURRENT_COMM.is_null() {
             panic!("No Comm instance registered");
         }
-        let comm = &mut *CURRENT_COMM;
-        if comm.event_pending && comm.rx_length >= 5 {
-            return Some(*comm.get_apdu_metadata());
-        }
-        None
+        (*CURRENT_COMM).pending_apdu_header()
     }
 }
 
@@ -746,7 +778,7 @@ fn default_nbgl_reply_status(reply: Reply) {
         if CURRENT_COMM.is_null() {
             panic!("No Comm instance registered");
         }
-        (*CURRENT_COMM).reply(reply);
+        (*CURRENT_COMM).reply_status(reply);
     }
 }
 
@@ -4,18 +4,44 @@ use core::mem::MaybeUninit;
 use crate::seph::PacketTypes;
 
 mod event;
-pub use event::{DecodedEvent, DecodedEventType};
+pub use event::{ApduParsing, DecodedEvent, DecodedEventType};
+
+mod chunk;
+pub use chunk::{ChunkReassembler, ChunkStatus, ChunkedCommand, P2_LAST, P2_MORE};
+
+mod transport;
+pub use transport::{MockTransport, SephTransport, Transport};
+
+mod codec;
+pub use codec::{ApduReader, MAX_BIP32_PATH_LEN};
+
+mod chaining;
+pub use chaining::INS_GET_RESPONSE;
+use chaining::ResponseChain;
+
+mod dispatch;
+pub use dispatch::{Dispatcher, Guard, Handler, Route};
+
+mod session;
+pub use session::{
+    SESSION_COUNTER_LEN, SESSION_OVERHEAD, SESSION_TAG_LEN, SecureSession, SessionCurve,
+    SessionError,
+};
 
 mod bolos;
 pub(crate) mod callbacks;
-use bolos::handle_bolos_apdu;
-
+pub use bolos::{
+    BOLOS_INS_GET_APP_CAPABILITIES, BolosBuiltin, BolosHandler, CAPABILITY_ISO7816,
+    CAPABILITY_RESPONSE_CHAINING, MAX_BOLOS_HANDLERS,
+};
+use bolos::{BolosConfig, handle_bolos_apdu};
+
+pub use crate::io_common::CommError;
+use crate::io_common::{IoComm, ResponseWriter};
 pub use crate::io_legacy::{ApduHeader, Event, Reply, StatusWords};
 
 use crate::io_callbacks::nbgl_register_callbacks;
 
-use ledger_secure_sdk_sys::seph as sys_seph;
-
 /// Default buffer size for `Comm` when no custom size is specified.
 pub const DEFAULT_BUF_SIZE: usize = 273;
 
@@ -130,17 +156,24 @@ use crate::buttons::ButtonEvent;
 #[cfg(any(target_os = "nanosplus", target_os = "nanox"))]
 use ledger_secure_sdk_sys::buttons::{ButtonsState, get_button_event};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum CommError {
-    Overflow,
-    IoError,
-}
-
-pub struct Comm<const N: usize = DEFAULT_BUF_SIZE> {
+/// APDU communication handler.
+///
+/// Packets are received and sent through a [`Transport`], which defaults to the
+/// OS SEPH syscalls. Other transports can be plugged with [`Comm::with_transport`],
+/// for instance a [`MockTransport`] to test APDU handlers on Speculos.
+pub struct Comm<const N: usize = DEFAULT_BUF_SIZE, T: Transport = SephTransport> {
     buf: [u8; N],
+    transport: T,
     expected_cla: Option<u8>,
 
     apdu_type: u8,
+    apdu_parsing: ApduParsing,
+    // Expected response length (Le) of the last received APDU, and whether it
+    // used extended lengths.
+    le: Option<usize>,
+    extended_apdu: bool,
+    chain: ResponseChain,
+    bolos: BolosConfig<N, T>,
     #[cfg(any(target_os = "nanosplus", target_os = "nanox"))]
     buttons: ButtonsState,
     // Pending APDU state (set by next_event_ahead callback path). When set, the buffer
@@ -153,10 +186,33 @@ pub struct Comm<const N: usize = DEFAULT_BUF_SIZE> {
 
 impl<const N: usize> Comm<N> {
     pub fn new() -> Self {
+        Self::with_transport(SephTransport::new())
+    }
+
+    pub(crate) fn nbgl_register_comm(&mut self) {
+        // Register NBGL callbacks if not already set and record current Comm singleton.
+        callbacks::set_comm::<N>(self);
+        nbgl_register_callbacks(
+            callbacks::next_event_ahead_impl::<N>,
+            callbacks::fetch_apdu_header_impl::<N>,
+            callbacks::reply_status_impl::<N>,
+        );
+    }
+}
+
+impl<const N: usize, T: Transport> Comm<N, T> {
+    /// Creates a `Comm` exchanging packets through the given transport.
+    pub fn with_transport(transport: T) -> Self {
         Self {
             buf: [0; N],
+            transport,
             expected_cla: None,
             apdu_type: PacketTypes::PacketTypeNone as u8,
+            apdu_parsing: ApduParsing::default(),
+            le: None,
+            extended_apdu: false,
+            chain: ResponseChain::new(),
+            bolos: BolosConfig::new(),
             #[cfg(any(target_os = "nanosplus", target_os = "nanox"))]
             buttons: ButtonsState::default(),
             pending_apdu: false,
@@ -171,19 +227,19 @@ impl<const N: usize> Comm<N> {
         }
     }
 
-    pub(crate) fn nbgl_register_comm(&mut self) {
-        // Register NBGL callbacks if not already set and record current Comm singleton.
-        callbacks::set_comm::<N>(self);
-        nbgl_register_callbacks(
-            callbacks::next_event_ahead_impl::<N>,
-            callbacks::fetch_apdu_header_impl::<N>,
-            callbacks::reply_status_impl::<N>,
-        );
+    /// Returns a reference to the underlying transport.
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Returns a mutable reference to the underlying transport.
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
     }
 
     /// Receive into the internal buffer. Returns a read-only guard.
-    fn recv(&mut self, check_se_event: bool) -> Result<Rx<'_, N>, CommError> {
-        let result = sys_seph::io_rx(&mut self.buf, check_se_event);
+    fn recv(&mut self, check_se_event: bool) -> Result<Rx<'_, N, T>, CommError> {
+        let result = self.transport.rx(&mut self.buf, check_se_event);
         if result < 0 {
             return Err(CommError::IoError);
         }
@@ -194,12 +250,61 @@ impl<const N: usize> Comm<N> {
     }
 
     /// Start building a message in the internal buffer. Returns a mutable guard.
-    pub fn begin_response(&mut self) -> CommandResponse<'_, N> {
-        CommandResponse { comm: self, len: 0 }
+    pub fn begin_response(&mut self) -> CommandResponse<'_, N, T> {
+        CommandResponse::new(self)
+    }
+
+    /// Send the first `len` bytes of the internal buffer followed by `sw`.
+    fn transmit(&mut self, len: usize, sw: u16) -> Result<(), CommError> {
+        let n = len + 2;
+        self.buf[len..n].copy_from_slice(&sw.to_be_bytes());
+        if 0 > self.transport.tx(self.apdu_type, &self.buf[..n], n) {
+            return Err(CommError::IoError);
+        }
+        // Clear the pending APDU state after sending a reply, so the next
+        // call to try_next_event will fetch a new event from io_rx.
+        self.pending_apdu = false;
+        Ok(())
+    }
+
+    /// Maximum length of the response data to the last received APDU.
+    fn max_response_len(&self) -> usize {
+        match self.le {
+            Some(le) => le.min(N - 2),
+            None => N - 2,
+        }
+    }
+
+    /// Answer a GET RESPONSE command with the next part of the chained response.
+    fn send_next_chained_part(&mut self) {
+        let max = self.max_response_len();
+        let len = self.chain.take(&mut self.buf[..max]);
+        let sw = self.chain.status_word();
+        let _ = self.transmit(len, sw);
+    }
+
+    /// Answers a GET RESPONSE command if a chained response is pending, and
+    /// returns true. Any other command drops the rest of the response.
+    ///
+    /// GET RESPONSE commands are recognized with the ISO CLA `0x00`, or with
+    /// the CLA set with [`set_expected_cla`](Self::set_expected_cla), so that
+    /// the application can still use INS `0xC0` with its own CLA.
+    fn serve_chained_part(&mut self, header: ApduHeader) -> bool {
+        if !self.chain.is_pending() {
+            return false;
+        }
+        let get_response = header.ins == INS_GET_RESPONSE
+            && (header.cla == 0x00 || self.expected_cla == Some(header.cla));
+        if !get_response {
+            self.chain.reset();
+            return false;
+        }
+        self.send_next_chained_part();
+        true
     }
 
     /// Send directly from an external slice, bypassing the internal buffer.
-    pub fn send<T: Into<Reply>>(&mut self, data: &[u8], reply: T) -> Result<(), CommError> {
+    pub fn send<R: Into<Reply>>(&mut self, data: &[u8], reply: R) -> Result<(), CommError> {
         self.begin_response().extend(data)?.send(reply).unwrap();
         Ok(())
     }
@@ -232,7 +337,7 @@ impl<const N: usize> Comm<N> {
         }
     }
 
-    pub fn next_command(&mut self) -> Command<'_, N> {
+    pub fn next_command(&mut self) -> Command<'_, N, T> {
         loop {
             let ety = self.next_event().into_type();
             match ety {
@@ -244,7 +349,10 @@ impl<const N: usize> Comm<N> {
                     // Handle BOLOS internal APDUs (CLA = 0xB0) internally
                     // and continue looping until an application APDU arrives.
                     if header.cla == 0xB0 {
-                        handle_bolos_apdu::<N>(self, header.ins, header.p1, header.p2);
+                        handle_bolos_apdu(self, header, offset, length);
+                        continue;
+                    }
+                    if self.serve_chained_part(header) {
                         continue;
                     }
                     // If CLA filtering is enabled, automatically reject APDUs with wrong CLA.
@@ -274,8 +382,108 @@ impl<const N: usize> Comm<N> {
     pub fn set_expected_cla(&mut self, cla: u8) {
         self.expected_cla = Some(cla);
     }
+
+    /// Selects how incoming APDUs are parsed. Defaults to [`ApduParsing::Legacy`],
+    /// which is what existing clients expect; [`ApduParsing::Iso7816`] enables
+    /// standard short and extended Lc/Le handling.
+    ///
+    /// Note that receiving extended APDUs requires a buffer size `N` large enough
+    /// to hold them.
+    ///
+    /// Usage:
+    /// ```ignore
+    /// let mut comm = Comm::new();
+    /// comm.set_apdu_parsing(ApduParsing::Iso7816);
+    /// ```
+    pub fn set_apdu_parsing(&mut self, parsing: ApduParsing) {
+        self.apdu_parsing = parsing;
+    }
+
+    /// Enables response chaining, using `buf` to hold the part of responses
+    /// which does not fit in a single reply.
+    ///
+    /// Responses may then exceed the internal buffer by up to `buf.len()` bytes:
+    /// the first part is sent with a `61xx` status word, and subsequent GET
+    /// RESPONSE commands ([`INS_GET_RESPONSE`], with CLA `0x00` or the
+    /// [expected CLA](Self::set_expected_cla)) are answered by
+    /// [`next_command`](Self::next_command), or while an NBGL screen waits for
+    /// events, until the response is drained.
+    /// Responses exceeding the Le of the command are chained as well.
+    ///
+    /// With [`ApduParsing::Legacy`], clients must send GET RESPONSE commands
+    /// without Le or with `Le = 0`.
+    ///
+    /// Usage:
+    /// ```ignore
+    /// static mut CHAINING_BUF: [u8; 1024] = [0; 1024];
+    ///
+    /// let comm = init_comm(&COMM);
+    /// comm.set_response_chaining(unsafe { &mut *(&raw mut CHAINING_BUF) });
+    /// ```
+    pub fn set_response_chaining(&mut self, buf: &'static mut [u8]) {
+        self.chain.set_buffer(buf);
+    }
+
+    /// Status word replied instead of a response of `len` bytes exceeding Le.
+    fn wrong_length_reply(&self, len: usize) -> Reply {
+        match len {
+            // 6Cxx gives the exact length to the client, 00 standing for 256
+            1..=256 if !self.extended_apdu => Reply(0x6C00 | (len & 0xff) as u16),
+            _ => Reply::from(StatusWords::WrongLength),
+        }
+    }
+}
+
+impl<const N: usize, T: Transport> IoComm for Comm<N, T> {
+    fn poll_apdu(&mut self) -> bool {
+        // If there's already a pending APDU, return true immediately without
+        // fetching another event. This prevents consuming the same APDU repeatedly
+        // when ux_sync_wait loops with exit_on_apdu=false.
+        if self.pending_apdu {
+            return true;
+        }
+        match self.next_event().into_type() {
+            DecodedEventType::Apdu {
+                header,
+                offset,
+                length,
+            } => {
+                // Handle BOLOS internal APDUs (CLA = 0xB0) inline so they don't
+                // block the ux_sync_wait loop. Without this, a BOLOS APDU arriving
+                // during an NBGL screen (e.g. stack consumption measurement) would
+                // set pending_apdu=true and never be consumed when exit_on_apdu=false,
+                // causing an infinite loop.
+                #[cfg(feature = "stack_usage")]
+                if header.cla == 0xB0 {
+                    handle_bolos_apdu(self, header, offset, length);
+                    return false;
+                }
+                // Serve the chained response the application replied with
+                // before displaying the screen.
+                if self.serve_chained_part(header) {
+                    return false;
+                }
+                self.pending_apdu = true;
+                self.pending_header = header;
+                self.pending_offset = offset;
+                self.pending_length = length;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn pending_apdu_header(&self) -> Option<ApduHeader> {
+        self.pending_apdu.then_some(self.pending_header)
+    }
+
+    fn reply_status(&mut self, reply: Reply) {
+        self.pending_apdu = false;
+        let _ = self.begin_response().send(reply);
+    }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ApduError {
     BadLen,
 }
@@ -288,15 +496,21 @@ impl From<ApduError> for StatusWords {
     }
 }
 
-pub struct Command<'a, const N: usize = DEFAULT_BUF_SIZE> {
-    comm: &'a mut Comm<N>,
+impl From<ApduError> for Reply {
+    fn from(e: ApduError) -> Self {
+        Reply::from(StatusWords::from(e))
+    }
+}
+
+pub struct Command<'a, const N: usize = DEFAULT_BUF_SIZE, T: Transport = SephTransport> {
+    comm: &'a mut Comm<N, T>,
     header: ApduHeader,
     offset: usize,
     length: usize,
 }
 
-impl<'a, const N: usize> Command<'a, N> {
-    pub fn new(comm: &'a mut Comm<N>, header: ApduHeader, offset: usize, length: usize) -> Self {
+impl<'a, const N: usize, T: Transport> Command<'a, N, T> {
+    pub fn new(comm: &'a mut Comm<N, T>, header: ApduHeader, offset: usize, length: usize) -> Self {
         Self {
             comm,
             header,
@@ -305,49 +519,57 @@ impl<'a, const N: usize> Command<'a, N> {
         }
     }
 
-    pub fn decode<T>(&self) -> Result<T, Reply>
+    pub fn decode<I>(&self) -> Result<I, Reply>
     where
-        T: TryFrom<ApduHeader>,
-        Reply: From<<T as TryFrom<ApduHeader>>::Error>,
+        I: TryFrom<ApduHeader>,
+        Reply: From<<I as TryFrom<ApduHeader>>::Error>,
     {
-        T::try_from(self.header).map_err(Reply::from)
+        I::try_from(self.header).map_err(Reply::from)
     }
 
     pub fn get_data(&self) -> &[u8] {
         &self.comm.buf[self.offset..self.offset + self.length]
     }
 
-    pub fn into_response(self) -> CommandResponse<'a, N> {
-        CommandResponse {
-            comm: self.comm,
-            len: 0,
-        }
+    /// Returns a reader over the command data.
+    pub fn reader(&self) -> ApduReader<'_> {
+        ApduReader::new(self.get_data())
+    }
+
+    /// Maximum length of the response data expected by the client (Le), if
+    /// specified by the APDU. Only set with [`ApduParsing::Iso7816`].
+    pub fn le(&self) -> Option<usize> {
+        self.comm.le
     }
 
-    pub fn into_comm(self) -> &'a mut Comm<N> {
+    pub fn into_response(self) -> CommandResponse<'a, N, T> {
+        CommandResponse::new(self.comm)
+    }
+
+    pub fn into_comm(self) -> &'a mut Comm<N, T> {
         self.comm
     }
 
-    pub fn reply<T: Into<Reply>>(self, data: &[u8], reply: T) -> Result<(), CommError> {
+    pub fn reply<R: Into<Reply>>(self, data: &[u8], reply: R) -> Result<(), CommError> {
         self.into_response().extend(data)?.send(reply)?;
         Ok(())
     }
 }
 
 /// Immutable read view.
-pub(crate) struct Rx<'a, const N: usize = DEFAULT_BUF_SIZE> {
-    comm: &'a mut Comm<N>,
+pub(crate) struct Rx<'a, const N: usize = DEFAULT_BUF_SIZE, T: Transport = SephTransport> {
+    comm: &'a mut Comm<N, T>,
     len: usize,
 }
 
-impl<'a, const N: usize> core::ops::Deref for Rx<'a, N> {
+impl<'a, const N: usize, T: Transport> core::ops::Deref for Rx<'a, N, T> {
     type Target = [u8];
     fn deref(&self) -> &Self::Target {
         self.as_slice()
     }
 }
 
-impl<'a, const N: usize> Rx<'a, N> {
+impl<'a, const N: usize, T: Transport> Rx<'a, N, T> {
     pub fn as_slice(&self) -> &[u8] {
         &self.comm.buf[..self.len]
     }
@@ -359,19 +581,27 @@ impl<'a, const N: usize> Rx<'a, N> {
 }
 
 /// Mutable write view for building a send.
-pub struct CommandResponse<'a, const N: usize = DEFAULT_BUF_SIZE> {
-    comm: &'a mut Comm<N>,
+pub struct CommandResponse<'a, const N: usize = DEFAULT_BUF_SIZE, T: Transport = SephTransport> {
+    comm: &'a mut Comm<N, T>,
     len: usize,
 }
 
-impl<'a, const N: usize> CommandResponse<'a, N> {
-    pub fn new(comm: &'a mut Comm<N>) -> Self {
+impl<'a, const N: usize, T: Transport> CommandResponse<'a, N, T> {
+    pub fn new(comm: &'a mut Comm<N, T>) -> Self {
+        // A new response supersedes any previous chained response.
+        comm.chain.reset();
         Self { comm, len: 0 }
     }
 
     /// Current staged length.
     pub fn len(&self) -> usize {
-        self.len
+        self.len + self.comm.chain.len()
+    }
+
+    /// Number of bytes which can still be appended, including the space left
+    /// in the [response chaining](Comm::set_response_chaining) buffer.
+    pub fn remaining(&self) -> usize {
+        N - 2 - self.len + self.comm.chain.free()
     }
 
     #[inline]
@@ -380,7 +610,15 @@ impl<'a, const N: usize> CommandResponse<'a, N> {
         let end = start.checked_add(src.len()).ok_or(CommError::Overflow)?;
         // reserve 2 bytes for the status word
         if end > N - 2 {
-            return Err(CommError::Overflow);
+            if !self.comm.chain.is_enabled() {
+                return Err(CommError::Overflow);
+            }
+            // Fill the internal buffer and stage the rest for response chaining.
+            let (head, tail) = src.split_at(N - 2 - start);
+            self.comm.chain.stage(tail)?;
+            self.comm.buf[start..N - 2].copy_from_slice(head);
+            self.len = N - 2;
+            return Ok(());
         }
         self.comm.buf[start..end].copy_from_slice(src);
         self.len = end;
@@ -402,36 +640,59 @@ impl<'a, const N: usize> CommandResponse<'a, N> {
         Ok(self)
     }
 
-    /// Send the staged bytes, adding a status word based on the reply
-    pub fn send<T: Into<Reply>>(mut self, reply: T) -> Result<&'a mut Comm<N>, CommError> {
-        let sw: u16 = reply.into().0;
-        self.append(sw.to_be_bytes().as_ref())?;
-        let n = self.len;
-        if 0 > sys_seph::io_tx(self.comm.apdu_type, self.comm.buf[..n].as_ref(), n) {
-            return Err(CommError::IoError);
+    /// Send the staged bytes, adding a status word based on the reply.
+    ///
+    /// If the staged data does not fit in a single reply, it is chained when
+    /// [response chaining](Comm::set_response_chaining) is enabled: the first
+    /// part is sent with a `61xx` status word, and the status word of the reply
+    /// is sent with the last part. Otherwise, when the command being answered
+    /// specified an Le smaller than the staged data, no data is sent and the
+    /// reply is replaced by a wrong length status word (`6Cxx` for short APDUs,
+    /// `6700` for extended ones).
+    pub fn send<R: Into<Reply>>(mut self, reply: R) -> Result<&'a mut Comm<N, T>, CommError> {
+        let mut sw: u16 = reply.into().0;
+        let comm = &mut *self.comm;
+        let max = comm.max_response_len();
+        if self.len + comm.chain.len() > max {
+            // `max` never exceeds the internal buffer, which is full whenever
+            // data has been staged for chaining, so `max <= self.len` here.
+            if comm.chain.is_enabled() && comm.chain.prepend(&comm.buf[max..self.len]).is_ok() {
+                comm.chain.start(sw);
+                sw = comm.chain.status_word();
+                self.len = max;
+            } else {
+                sw = comm.wrong_length_reply(self.len + comm.chain.len()).0;
+                comm.chain.reset();
+                self.len = 0;
+            }
         }
-        // Clear the pending APDU state after sending a reply, so the next
-        // call to try_next_event will fetch a new event from io_rx.
-        self.comm.pending_apdu = false;
+        self.comm.transmit(self.len, sw)?;
         Ok(self.comm)
     }
 
     /// Clear staged bytes length.
     pub fn clear(&mut self) {
         self.len = 0;
+        self.comm.chain.reset();
     }
 }
 
-impl<const N: usize> Drop for Comm<N> {
-    fn drop(&mut self) {
-        callbacks::clear_comm();
-        callbacks::clear_panic_handler();
-        // Allow a new CommStorage to be initialized after this one is dropped.
-        // SAFETY: single-threaded runtime; no concurrent access is possible.
-        unsafe { COMM_INITIALIZED = false };
+impl<'a, const N: usize, T: Transport> ResponseWriter for CommandResponse<'a, N, T> {
+    fn append_data(&mut self, data: &[u8]) -> Result<(), CommError> {
+        self.try_append(data)
     }
 }
 
+/// Clears the global state of the `Comm` using the SEPH transport, when it is
+/// dropped. Comms built on other transports never touch it.
+fn release_comm() {
+    callbacks::clear_comm();
+    callbacks::clear_panic_handler();
+    // Allow a new CommStorage to be initialized after this one is dropped.
+    // SAFETY: single-threaded runtime; no concurrent access is possible.
+    unsafe { COMM_INITIALIZED = false };
+}
+
 /// Initializes the `Comm` instance in static storage and registers NBGL callbacks.
 ///
 /// This function combines the creation of a `Comm` instance, its storage in static memory,
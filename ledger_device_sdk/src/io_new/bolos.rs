@@ -1,4 +1,38 @@
-use super::{Comm, StatusWords};
+//! Handling of BOLOS APDUs (CLA = 0xB0).
+//!
+//! These APDUs are answered by `Comm` without reaching the application. The
+//! built-in instructions (see [`BolosBuiltin`]) can be disabled individually
+//! with [`Comm::disable_bolos_builtin`], and applications can register their
+//! own handlers with [`Comm::register_bolos_handler`], which take precedence
+//! over the built-ins:
+//!
+//! ```ignore
+//! fn handle_get_config(command: Command) {
+//!     let _ = command.reply(&CONFIG, StatusWords::Ok);
+//! }
+//!
+//! comm.register_bolos_handler(0x20, handle_get_config).unwrap();
+//! comm.disable_bolos_builtin(BolosBuiltin::Quit);
+//! ```
+//!
+//! # Get app capabilities
+//!
+//! [`BOLOS_INS_GET_APP_CAPABILITIES`] returns a list of TLV entries (tag and
+//! length on one byte each):
+//!
+//! | Tag    | Value                                                       |
+//! |--------|-------------------------------------------------------------|
+//! | `0x01` | Application name                                            |
+//! | `0x02` | Application version                                         |
+//! | `0x03` | Application flags, as declared in the manifest              |
+//! | `0x04` | SDK version                                                 |
+//! | `0x05` | Maximum APDU data length (`u16`, big-endian)                |
+//! | `0x06` | Supported features (see [`CAPABILITY_ISO7816`] and others)  |
+//! | `0x07` | Expected CLA, if set with [`Comm::set_expected_cla`]        |
+//! | `0x08` | Application-defined data, see [`Comm::set_app_capabilities`] |
+
+use super::{ApduHeader, ApduParsing, Comm, CommError, Command, StatusWords, Transport};
+use crate::app_info::{APP_FLAGS, APP_NAME, APP_VERSION, SDK_VERSION};
 use ledger_secure_sdk_sys::*;
 
 #[cfg(feature = "stack_usage")]
@@ -8,11 +42,181 @@ use crate::io_legacy::{
     SyscallError,
 };
 
+/// INS of the "get app capabilities" BOLOS APDU.
+pub const BOLOS_INS_GET_APP_CAPABILITIES: u8 = 0x02;
+
+/// Maximum number of handlers registered with [`Comm::register_bolos_handler`].
+pub const MAX_BOLOS_HANDLERS: usize = 4;
+
+/// Feature flag of the capabilities: ISO 7816-4 APDU parsing (Le, extended lengths).
+pub const CAPABILITY_ISO7816: u8 = 0x01;
+/// Feature flag of the capabilities: response chaining with GET RESPONSE.
+pub const CAPABILITY_RESPONSE_CHAINING: u8 = 0x02;
+
+/// Handler of an application-defined BOLOS APDU. It is responsible for replying.
+pub type BolosHandler<const N: usize, T> = fn(Command<'_, N, T>);
+
+/// BOLOS APDUs handled by `Comm` unless disabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BolosBuiltin {
+    GetVersion,
+    Quit,
+    SetPkiCert,
+    GetAppCapabilities,
+}
+
+impl BolosBuiltin {
+    pub fn ins(self) -> u8 {
+        match self {
+            BolosBuiltin::GetVersion => BOLOS_INS_GET_VERSION,
+            BolosBuiltin::Quit => BOLOS_INS_QUIT,
+            BolosBuiltin::SetPkiCert => BOLOS_INS_SET_PKI_CERT,
+            BolosBuiltin::GetAppCapabilities => BOLOS_INS_GET_APP_CAPABILITIES,
+        }
+    }
+
+    fn mask(self) -> u8 {
+        1 << (self as u8)
+    }
+}
+
+pub(crate) struct BolosConfig<const N: usize, T: Transport> {
+    disabled: u8,
+    handlers: [Option<(u8, BolosHandler<N, T>)>; MAX_BOLOS_HANDLERS],
+    app_capabilities: &'static [u8],
+}
+
+impl<const N: usize, T: Transport> BolosConfig<N, T> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            disabled: 0,
+            handlers: [None; MAX_BOLOS_HANDLERS],
+            app_capabilities: &[],
+        }
+    }
+
+    fn is_enabled(&self, builtin: BolosBuiltin) -> bool {
+        self.disabled & builtin.mask() == 0
+    }
+
+    fn handler(&self, ins: u8) -> Option<BolosHandler<N, T>> {
+        self.handlers
+            .iter()
+            .flatten()
+            .find(|(i, _)| *i == ins)
+            .map(|(_, h)| *h)
+    }
+}
+
+impl<const N: usize, T: Transport> Comm<N, T> {
+    /// Registers a handler for the BOLOS APDUs with the given INS, replacing any
+    /// handler previously registered for it. Registering a handler for the INS
+    /// of a built-in overrides the built-in.
+    ///
+    /// Fails with [`CommError::Overflow`] if [`MAX_BOLOS_HANDLERS`] handlers are
+    /// already registered.
+    pub fn register_bolos_handler(
+        &mut self,
+        ins: u8,
+        handler: BolosHandler<N, T>,
+    ) -> Result<(), CommError> {
+        let handlers = &mut self.bolos.handlers;
+        let slot = match handlers
+            .iter()
+            .position(|h| matches!(h, Some((i, _)) if *i == ins))
+        {
+            Some(pos) => &mut handlers[pos],
+            None => handlers
+                .iter_mut()
+                .find(|h| h.is_none())
+                .ok_or(CommError::Overflow)?,
+        };
+        *slot = Some((ins, handler));
+        Ok(())
+    }
+
+    /// Removes the handler registered for the given INS, if any.
+    pub fn unregister_bolos_handler(&mut self, ins: u8) {
+        for h in self.bolos.handlers.iter_mut() {
+            if matches!(h, Some((i, _)) if *i == ins) {
+                *h = None;
+            }
+        }
+    }
+
+    /// Disables a built-in BOLOS APDU, which is then rejected with
+    /// `StatusWords::BadIns` unless a handler is registered for it.
+    pub fn disable_bolos_builtin(&mut self, builtin: BolosBuiltin) {
+        self.bolos.disabled |= builtin.mask();
+    }
+
+    /// Enables a built-in BOLOS APDU. All built-ins are enabled by default.
+    pub fn enable_bolos_builtin(&mut self, builtin: BolosBuiltin) {
+        self.bolos.disabled &= !builtin.mask();
+    }
+
+    /// Sets application-defined data appended to the "get app capabilities" response.
+    pub fn set_app_capabilities(&mut self, data: &'static [u8]) {
+        self.bolos.app_capabilities = data;
+    }
+}
+
+/// Sends the "get app capabilities" response.
+fn send_app_capabilities<const N: usize, T: Transport>(
+    comm: &mut Comm<N, T>,
+) -> Result<(), CommError> {
+    // Data of a short APDU follows the packet type byte and the 5-byte header.
+    let max_data_len = N.saturating_sub(6).min(u16::MAX as usize) as u16;
+    let mut features = 0;
+    if comm.apdu_parsing == ApduParsing::Iso7816 {
+        features |= CAPABILITY_ISO7816;
+    }
+    if comm.chain.is_enabled() {
+        features |= CAPABILITY_RESPONSE_CHAINING;
+    }
+    let expected_cla = comm.expected_cla;
+    let app_capabilities = comm.bolos.app_capabilities;
+
+    let mut response = comm.begin_response();
+    response
+        .append_u8(0x01)?
+        .append_length_prefixed(APP_NAME.as_bytes())?
+        .append_u8(0x02)?
+        .append_length_prefixed(APP_VERSION.as_bytes())?
+        .append_u8(0x03)?
+        .append_length_prefixed(APP_FLAGS.as_bytes())?
+        .append_u8(0x04)?
+        .append_length_prefixed(SDK_VERSION.as_bytes())?
+        .append_u8(0x05)?
+        .append_length_prefixed(&max_data_len.to_be_bytes())?
+        .append_u8(0x06)?
+        .append_length_prefixed(&[features])?;
+    if let Some(cla) = expected_cla {
+        response.append_u8(0x07)?.append_length_prefixed(&[cla])?;
+    }
+    if !app_capabilities.is_empty() {
+        response
+            .append_u8(0x08)?
+            .append_length_prefixed(app_capabilities)?;
+    }
+    response.send(StatusWords::Ok)
+}
+
 /// Handle internal BOLOS APDUs (CLA = 0xB0).
-pub(crate) fn handle_bolos_apdu<const N: usize>(comm: &mut Comm<N>, ins: u8, p1: u8, p2: u8) {
-    match ins {
+pub(crate) fn handle_bolos_apdu<const N: usize, T: Transport>(
+    comm: &mut Comm<N, T>,
+    header: ApduHeader,
+    offset: usize,
+    length: usize,
+) {
+    if let Some(handler) = comm.bolos.handler(header.ins) {
+        handler(Command::new(comm, header, offset, length));
+        return;
+    }
+
+    match header.ins {
         // Get Information INS: retrieve App name and version
-        BOLOS_INS_GET_VERSION => {
+        BOLOS_INS_GET_VERSION if comm.bolos.is_enabled(BolosBuiltin::GetVersion) => {
             let mut response = comm.begin_response();
             let _ = response.append(&[0x01]);
             const MAX_TAG_LENGTH: u8 = 32; // maximum length for the buffer containing app name/version.
@@ -60,11 +264,11 @@ pub(crate) fn handle_bolos_apdu<const N: usize>(comm: &mut Comm<N>, ins: u8, p1:
             let _ = response.send(StatusWords::Ok);
         }
         // Quit Application INS
-        BOLOS_INS_QUIT => {
+        BOLOS_INS_QUIT if comm.bolos.is_enabled(BolosBuiltin::Quit) => {
             let _ = comm.begin_response().send(StatusWords::Ok);
             crate::exit_app(0);
         }
-        BOLOS_INS_SET_PKI_CERT => unsafe {
+        BOLOS_INS_SET_PKI_CERT if comm.bolos.is_enabled(BolosBuiltin::SetPkiCert) => unsafe {
             let public_key = cx_ecfp_384_public_key_t::default();
             let err = os_pki_load_certificate(
                 comm.buf[3],                // P1
@@ -82,13 +286,76 @@ pub(crate) fn handle_bolos_apdu<const N: usize>(comm: &mut Comm<N>, ins: u8, p1:
                 let _ = comm.begin_response().send(StatusWords::Ok);
             }
         },
+        BOLOS_INS_GET_APP_CAPABILITIES
+            if comm.bolos.is_enabled(BolosBuiltin::GetAppCapabilities) =>
+        {
+            if send_app_capabilities(comm).is_err() {
+                let _ = comm.begin_response().send(StatusWords::Panic);
+            }
+        }
         #[cfg(feature = "stack_usage")]
         BOLOS_INS_STACK_CONSUMPTION => {
-            crate::testing::handle_stack_consumption_apdu_new(p1, p2, comm);
+            crate::testing::handle_stack_consumption_apdu_new(header.p1, header.p2, comm);
         }
-        // Unknown INS within BOLOS namespace
+        // Unknown or disabled INS within BOLOS namespace
         _ => {
             let _ = comm.begin_response().send(StatusWords::BadIns);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_eq_err as assert_eq;
+    use crate::io_new::{DEFAULT_BUF_SIZE, MockTransport};
+    use crate::testing::TestType;
+    use testmacro::test_item as test;
+
+    fn handle_custom(command: Command<'_, DEFAULT_BUF_SIZE, MockTransport>) {
+        let _ = command.reply(&[0x42], StatusWords::Ok);
+    }
+
+    #[test]
+    fn bolos_custom_handler() {
+        let apdus: &[&[u8]] = &[&[0xb0, 0x20, 0x00, 0x00], &[0xe0, 0x01, 0x00, 0x00]];
+        let mut comm: Comm<DEFAULT_BUF_SIZE, MockTransport> =
+            Comm::with_transport(MockTransport::new(apdus));
+        assert_eq!(comm.register_bolos_handler(0x20, handle_custom), Ok(()));
+        let comm = comm.next_command().into_comm();
+        assert_eq!(comm.transport().reply_count(), 1);
+        assert_eq!(comm.transport().last_reply(), &[0x42]);
+        assert_eq!(comm.transport().last_status_word(), Some(0x9000));
+    }
+
+    #[test]
+    fn bolos_disabled_builtin() {
+        let apdus: &[&[u8]] = &[&[0xb0, 0x02, 0x00, 0x00], &[0xe0, 0x01, 0x00, 0x00]];
+        let mut comm: Comm<DEFAULT_BUF_SIZE, MockTransport> =
+            Comm::with_transport(MockTransport::new(apdus));
+        comm.disable_bolos_builtin(BolosBuiltin::GetAppCapabilities);
+        let comm = comm.next_command().into_comm();
+        assert_eq!(
+            comm.transport().last_status_word(),
+            Some(StatusWords::BadIns as u16)
+        );
+    }
+
+    #[test]
+    fn bolos_app_capabilities() {
+        let apdus: &[&[u8]] = &[&[0xb0, 0x02, 0x00, 0x00], &[0xe0, 0x01, 0x00, 0x00]];
+        let mut comm: Comm<DEFAULT_BUF_SIZE, MockTransport> =
+            Comm::with_transport(MockTransport::new(apdus));
+        comm.set_expected_cla(0xe0);
+        comm.set_app_capabilities(&[0xaa, 0xbb]);
+        let comm = comm.next_command().into_comm();
+        assert_eq!(comm.transport().last_status_word(), Some(0x9000));
+        let reply = comm.transport().last_reply();
+        assert_eq!(reply[0], 0x01);
+        assert_eq!(&reply[2..2 + reply[1] as usize], APP_NAME.as_bytes());
+        assert_eq!(
+            &reply[reply.len() - 7..],
+            &[0x07, 0x01, 0xe0, 0x08, 0x02, 0xaa, 0xbb]
+        );
+    }
+}
@@ -3,11 +3,10 @@
 //! This module holds the erased pointer to the current `Comm` instance and the
 //! generic callback wrappers that are registered through `nbgl_register_callbacks`.
 
+use crate::io_common::IoComm;
 use crate::io_legacy::{ApduHeader, Reply};
 
-#[cfg(feature = "stack_usage")]
-use super::bolos::handle_bolos_apdu;
-use super::{Comm, DecodedEventType};
+use super::Comm;
 
 // Erased pointer to the Comm instance (generic parameter erased).
 static mut CURRENT_COMM: *mut core::ffi::c_void = core::ptr::null_mut();
@@ -68,53 +67,13 @@ fn panic_reply_impl<const N: usize>(reply: Reply) {
 // Implementation wrappers specialized per const N.
 
 pub(super) fn next_event_ahead_impl<const N: usize>() -> bool {
-    let comm = unsafe { get_comm::<N>() };
-    // If there's already a pending APDU, return true immediately without
-    // fetching another event. This prevents consuming the same APDU repeatedly
-    // when ux_sync_wait loops with exit_on_apdu=false.
-    if comm.pending_apdu {
-        return true;
-    }
-    match comm.next_event().into_type() {
-        DecodedEventType::Apdu {
-            header,
-            offset,
-            length,
-        } => {
-            // Handle BOLOS internal APDUs (CLA = 0xB0) inline so they don't
-            // block the ux_sync_wait loop. Without this, a BOLOS APDU arriving
-            // during an NBGL screen (e.g. stack consumption measurement) would
-            // set pending_apdu=true and never be consumed when exit_on_apdu=false,
-            // causing an infinite loop.
-            #[cfg(feature = "stack_usage")]
-            if header.cla == 0xB0 {
-                handle_bolos_apdu::<N>(comm, header.ins, header.p1, header.p2);
-                return false;
-            }
-            comm.pending_apdu = true;
-            comm.pending_header = header;
-            comm.pending_offset = offset;
-            comm.pending_length = length;
-            return true;
-        }
-        _ => {}
-    }
-    false
+    unsafe { get_comm::<N>() }.poll_apdu()
 }
 
 pub(super) fn fetch_apdu_header_impl<const N: usize>() -> Option<ApduHeader> {
-    let comm = unsafe { get_comm::<N>() };
-    if comm.pending_apdu {
-        Some(comm.pending_header)
-    } else {
-        None
-    }
+    unsafe { get_comm::<N>() }.pending_apdu_header()
 }
 
 pub(super) fn reply_status_impl<const N: usize>(reply: Reply) {
-    let comm = unsafe { get_comm::<N>() };
-    if comm.pending_apdu {
-        comm.pending_apdu = false;
-    }
-    let _ = comm.begin_response().send(reply);
+    unsafe { get_comm::<N>() }.reply_status(reply);
 }
@@ -0,0 +1,240 @@
+//! Response chaining (ISO 7816-4 `61xx` / GET RESPONSE).
+//!
+//! When a response does not fit in a single reply, either because it exceeds the
+//! `Comm` buffer or the Le of the command, the first part is sent with a `61xx`
+//! status word, `xx` being the number of bytes still available (`00` meaning 256
+//! or more). The client then fetches the remaining parts with GET RESPONSE
+//! commands (INS `0xC0`, with CLA `0x00` or the expected CLA of the `Comm`), the
+//! last part being sent with the status word of the whole response. Any other
+//! command drops the rest of the response.
+//!
+//! The remaining parts are kept in a buffer registered with
+//! [`Comm::set_response_chaining`](super::Comm::set_response_chaining), whose
+//! size bounds the amount of data a response can exceed the `Comm` buffer by.
+
+use super::CommError;
+
+/// Instruction used by clients to fetch the next part of a chained response.
+pub const INS_GET_RESPONSE: u8 = 0xC0;
+
+/// Returns the `61xx` status word announcing `remaining` bytes.
+pub(crate) fn bytes_remaining_sw(remaining: usize) -> u16 {
+    0x6100 | (remaining.min(256) & 0xff) as u16
+}
+
+pub(crate) struct ResponseChain {
+    buf: Option<&'static mut [u8]>,
+    // Bytes staged (while building a response) or still to be sent (while
+    // answering GET RESPONSE commands), starting at `offset`.
+    len: usize,
+    offset: usize,
+    // Status word of the whole response, sent along with its last part.
+    sw: u16,
+    pending: bool,
+}
+
+impl ResponseChain {
+    pub(crate) const fn new() -> Self {
+        Self {
+            buf: None,
+            len: 0,
+            offset: 0,
+            sw: 0,
+            pending: false,
+        }
+    }
+
+    pub(crate) fn set_buffer(&mut self, buf: &'static mut [u8]) {
+        self.reset();
+        self.buf = Some(buf);
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.buf.is_some()
+    }
+
+    /// Returns true if a chained response has been started and not fully sent.
+    pub(crate) fn is_pending(&self) -> bool {
+        self.pending
+    }
+
+    /// Drops any staged or pending data.
+    pub(crate) fn reset(&mut self) {
+        self.len = 0;
+        self.offset = 0;
+        self.pending = false;
+    }
+
+    /// Number of bytes staged or still to be sent.
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Number of bytes which can still be staged.
+    pub(crate) fn free(&self) -> usize {
+        self.buf.as_deref().map_or(0, |buf| buf.len() - self.len)
+    }
+
+    /// Appends `src` to the staged data. Nothing is written if it does not fit.
+    pub(crate) fn stage(&mut self, src: &[u8]) -> Result<(), CommError> {
+        let buf = self.buf.as_deref_mut().ok_or(CommError::Overflow)?;
+        let end = self
+            .len
+            .checked_add(src.len())
+            .filter(|&end| end <= buf.len())
+            .ok_or(CommError::Overflow)?;
+        buf[self.len..end].copy_from_slice(src);
+        self.len = end;
+        Ok(())
+    }
+
+    /// Inserts `src` before the staged data. Nothing is written if it does not fit.
+    pub(crate) fn prepend(&mut self, src: &[u8]) -> Result<(), CommError> {
+        let buf = self.buf.as_deref_mut().ok_or(CommError::Overflow)?;
+        let end = self
+            .len
+            .checked_add(src.len())
+            .filter(|&end| end <= buf.len())
+            .ok_or(CommError::Overflow)?;
+        buf.copy_within(0..self.len, src.len());
+        buf[..src.len()].copy_from_slice(src);
+        self.len = end;
+        Ok(())
+    }
+
+    /// Starts answering GET RESPONSE commands with the staged data, `sw` being
+    /// the status word of the whole response.
+    pub(crate) fn start(&mut self, sw: u16) {
+        self.offset = 0;
+        self.sw = sw;
+        self.pending = true;
+    }
+
+    /// Copies the next part of the response into `dst`, returning its length.
+    pub(crate) fn take(&mut self, dst: &mut [u8]) -> usize {
+        let buf = match self.buf.as_deref() {
+            Some(buf) => buf,
+            None => return 0,
+        };
+        let n = self.len.min(dst.len());
+        dst[..n].copy_from_slice(&buf[self.offset..self.offset + n]);
+        self.offset += n;
+        self.len -= n;
+        if self.len == 0 {
+            self.pending = false;
+        }
+        n
+    }
+
+    /// Status word to send along with the part just taken.
+    pub(crate) fn status_word(&self) -> u16 {
+        if self.len > 0 {
+            bytes_remaining_sw(self.len)
+        } else {
+            self.sw
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assert_eq_err as assert_eq;
+    use crate::io_common::IoComm;
+    use crate::io_new::{Comm, MockTransport, StatusWords};
+    use crate::testing::TestType;
+    use testmacro::test_item as test;
+
+    static mut CHAINING_BUF: [u8; 32] = [0; 32];
+
+    #[test]
+    fn chained_response() {
+        let apdus: &[&[u8]] = &[
+            &[0xe0, 0x02, 0x00, 0x00],
+            &[0x00, 0xc0, 0x00, 0x00],
+            &[0x00, 0xc0, 0x00, 0x00],
+            &[0xe0, 0x03, 0x00, 0x00],
+        ];
+        // 14 bytes of data per reply
+        let mut comm: Comm<16, MockTransport> = Comm::with_transport(MockTransport::new(apdus));
+        comm.set_response_chaining(unsafe { &mut *(&raw mut CHAINING_BUF) });
+
+        let data = [0x5a; 30];
+        let command = comm.next_command();
+        let _ = command.reply(&data, StatusWords::Ok);
+        assert_eq!(comm.transport().last_reply(), &data[..14]);
+        assert_eq!(comm.transport().last_status_word(), Some(0x6110));
+
+        // GET RESPONSE commands are answered without reaching the application
+        let command = comm.next_command();
+        assert_eq!(
+            command
+                .decode::<crate::io_new::ApduHeader>()
+                .ok()
+                .map(|h| h.ins),
+            Some(0x03)
+        );
+        let comm = command.into_comm();
+        assert_eq!(comm.transport().reply_count(), 3);
+        assert_eq!(comm.transport().last_reply(), &data[28..]);
+        assert_eq!(comm.transport().last_status_word(), Some(0x9000));
+    }
+
+    #[test]
+    fn get_response_cla() {
+        let apdus: &[&[u8]] = &[
+            &[0xe0, 0x02, 0x00, 0x00],
+            &[0xe0, 0xc0, 0x00, 0x00],
+            &[0xe0, 0x02, 0x00, 0x00],
+            &[0xe1, 0xc0, 0x00, 0x00],
+        ];
+        let mut comm: Comm<16, MockTransport> = Comm::with_transport(MockTransport::new(apdus));
+        comm.set_response_chaining(unsafe { &mut *(&raw mut CHAINING_BUF) });
+
+        // Without an expected CLA, only CLA 0x00 is a GET RESPONSE: the
+        // command reaches the application, and drops the rest of the response
+        let data = [0x5a; 20];
+        let _ = comm.next_command().reply(&data, StatusWords::Ok);
+        let command = comm.next_command();
+        assert_eq!(
+            command
+                .decode::<crate::io_new::ApduHeader>()
+                .ok()
+                .map(|h| (h.cla, h.ins)),
+            Some((0xe0, 0xc0))
+        );
+        let comm = command.into_comm();
+        assert_eq!(comm.transport().reply_count(), 1);
+
+        // With an expected CLA, GET RESPONSE commands of another CLA are not
+        // answered either
+        comm.set_expected_cla(0xe0);
+        let _ = comm.next_command().reply(&data, StatusWords::Ok);
+        assert_eq!(comm.poll_apdu(), true);
+        assert_eq!(comm.pending_apdu_header().map(|h| h.cla), Some(0xe1));
+        assert_eq!(comm.transport().reply_count(), 2);
+    }
+
+    #[test]
+    fn chained_response_polled() {
+        let apdus: &[&[u8]] = &[
+            &[0xe0, 0x02, 0x00, 0x00],
+            &[0xe0, 0xc0, 0x00, 0x00],
+            &[0xe0, 0x03, 0x00, 0x00],
+        ];
+        let mut comm: Comm<16, MockTransport> = Comm::with_transport(MockTransport::new(apdus));
+        comm.set_response_chaining(unsafe { &mut *(&raw mut CHAINING_BUF) });
+        comm.set_expected_cla(0xe0);
+
+        let data = [0x5a; 20];
+        let _ = comm.next_command().reply(&data, StatusWords::Ok);
+        assert_eq!(comm.transport().last_status_word(), Some(0x6106));
+
+        // GET RESPONSE commands received while a screen is displayed are
+        // answered without being reported to it
+        assert_eq!(comm.poll_apdu(), false);
+        assert_eq!(comm.transport().last_reply(), &data[14..]);
+        assert_eq!(comm.transport().last_status_word(), Some(0x9000));
+        assert_eq!(comm.poll_apdu(), true);
+        assert_eq!(comm.pending_apdu_header().map(|h| h.ins), Some(0x03));
+    }
+}
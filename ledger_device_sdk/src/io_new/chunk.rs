@@ -0,0 +1,322 @@
+//! Reassembly of payloads split over several APDUs.
+//!
+//! Payloads larger than what a single APDU can carry are sent by the host as a
+//! sequence of chunks sharing the same CLA and INS, using the following convention:
+//!
+//! * `P1` holds the index of the chunk, starting at 0 for the first one;
+//! * `P2` is [`P2_MORE`] when more chunks follow, [`P2_LAST`] for the last one.
+//!
+//! A chunk with index 0 always (re)starts a transfer, discarding any payload
+//! previously accumulated.
+//!
+//! # Example
+//!
+//! ```ignore
+//! let mut buf = [0u8; 1024];
+//! let mut chunks = ChunkReassembler::new(&mut buf);
+//! loop {
+//!     let command = comm.next_command();
+//!     match command.decode::<Instruction>() {
+//!         Ok(Instruction::SignTx) => {
+//!             // Intermediate chunks are acknowledged automatically.
+//!             if let Some(tx) = chunks.feed(command) {
+//!                 let payload = tx.get_data();
+//!                 // ...
+//!                 tx.reply(&signature, StatusWords::Ok).unwrap();
+//!             }
+//!         }
+//!         // ...
+//!     }
+//! }
+//! ```
+
+use super::{
+    ApduHeader, ApduReader, Comm, CommError, Command, CommandResponse, DEFAULT_BUF_SIZE, Reply,
+    SephTransport, StatusWords, Transport,
+};
+
+/// `P2` value of a chunk followed by other chunks.
+pub const P2_MORE: u8 = 0x80;
+/// `P2` value of the last chunk of a transfer.
+pub const P2_LAST: u8 = 0x00;
+
+/// Outcome of a successfully processed chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkStatus {
+    /// More chunks are expected.
+    Pending,
+    /// The last chunk has been received, the payload is complete.
+    Complete,
+}
+
+/// Accumulates APDU chunks into a caller-supplied buffer.
+///
+/// The capacity of the buffer is the maximum payload size accepted: a chunk that
+/// would overflow it aborts the transfer with [`StatusWords::BadLen`].
+pub struct ChunkReassembler<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+    // CLA and INS of the transfer in progress, if any.
+    current: Option<(u8, u8)>,
+    next_index: u16,
+}
+
+impl<'b> ChunkReassembler<'b> {
+    pub fn new(buf: &'b mut [u8]) -> Self {
+        Self {
+            buf,
+            len: 0,
+            current: None,
+            next_index: 0,
+        }
+    }
+
+    /// Abort the transfer in progress, if any, and discard accumulated data.
+    pub fn reset(&mut self) {
+        self.len = 0;
+        self.current = None;
+        self.next_index = 0;
+    }
+
+    /// Returns true if a transfer has started and its last chunk has not been received yet.
+    pub fn in_progress(&self) -> bool {
+        self.current.is_some()
+    }
+
+    /// Number of payload bytes accumulated so far.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Maximum payload size.
+    pub fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Payload accumulated so far.
+    pub fn data(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    /// Process a chunk given its header and data.
+    ///
+    /// On error, the transfer in progress is aborted and the status word to
+    /// reply with is returned:
+    /// * [`StatusWords::BadP1P2`] for an out-of-order chunk or an invalid `P2`,
+    /// * [`StatusWords::BadCla`] or [`StatusWords::BadIns`] when the chunk does
+    ///   not belong to the transfer in progress,
+    /// * [`StatusWords::BadLen`] when the payload exceeds the buffer capacity.
+    pub fn push(&mut self, header: &ApduHeader, data: &[u8]) -> Result<ChunkStatus, StatusWords> {
+        let result = self.try_push(header, data);
+        if result.is_err() {
+            self.reset();
+        }
+        result
+    }
+
+    fn try_push(&mut self, header: &ApduHeader, data: &[u8]) -> Result<ChunkStatus, StatusWords> {
+        if header.p2 != P2_MORE && header.p2 != P2_LAST {
+            return Err(StatusWords::BadP1P2);
+        }
+
+        if header.p1 == 0 {
+            self.reset();
+            self.current = Some((header.cla, header.ins));
+        } else {
+            let (cla, ins) = self.current.ok_or(StatusWords::BadP1P2)?;
+            if header.cla != cla {
+                return Err(StatusWords::BadCla);
+            }
+            if header.ins != ins {
+                return Err(StatusWords::BadIns);
+            }
+            if header.p1 as u16 != self.next_index {
+                return Err(StatusWords::BadP1P2);
+            }
+        }
+
+        let end = self
+            .len
+            .checked_add(data.len())
+            .filter(|&end| end <= self.buf.len())
+            .ok_or(StatusWords::BadLen)?;
+        self.buf[self.len..end].copy_from_slice(data);
+        self.len = end;
+        self.next_index += 1;
+
+        if header.p2 == P2_LAST {
+            self.current = None;
+            self.next_index = 0;
+            Ok(ChunkStatus::Complete)
+        } else {
+            Ok(ChunkStatus::Pending)
+        }
+    }
+
+    /// Process a received `Command` as a chunk.
+    ///
+    /// Intermediate chunks are acknowledged with [`StatusWords::Ok`] and invalid
+    /// ones are answered with the corresponding error status word; in both cases
+    /// `None` is returned. Once the last chunk is received, a [`ChunkedCommand`]
+    /// exposing the whole payload is returned, and the caller is responsible for
+    /// replying to it.
+    pub fn feed<'c, 'r, const N: usize, T: Transport>(
+        &'r mut self,
+        command: Command<'c, N, T>,
+    ) -> Option<ChunkedCommand<'c, 'r, N, T>> {
+        match self.push(&command.header, command.get_data()) {
+            Ok(ChunkStatus::Complete) => Some(ChunkedCommand {
+                command,
+                data: &self.buf[..self.len],
+            }),
+            Ok(ChunkStatus::Pending) => {
+                let _ = command.reply(&[], StatusWords::Ok);
+                None
+            }
+            Err(sw) => {
+                let _ = command.reply(&[], sw);
+                None
+            }
+        }
+    }
+}
+
+/// A command whose payload has been reassembled from several chunks.
+///
+/// This is the counterpart of [`Command`] for chunked transfers: the header is
+/// the one of the last chunk, and [`get_data`](Self::get_data) returns the whole
+/// payload.
+pub struct ChunkedCommand<'c, 'r, const N: usize = DEFAULT_BUF_SIZE, T: Transport = SephTransport> {
+    command: Command<'c, N, T>,
+    data: &'r [u8],
+}
+
+impl<'c, 'r, const N: usize, T: Transport> ChunkedCommand<'c, 'r, N, T> {
+    pub fn header(&self) -> ApduHeader {
+        self.command.header
+    }
+
+    pub fn decode<I>(&self) -> Result<I, Reply>
+    where
+        I: TryFrom<ApduHeader>,
+        Reply: From<<I as TryFrom<ApduHeader>>::Error>,
+    {
+        self.command.decode()
+    }
+
+    /// Reassembled payload.
+    pub fn get_data(&self) -> &'r [u8] {
+        self.data
+    }
+
+    /// Returns a reader over the reassembled payload.
+    pub fn reader(&self) -> ApduReader<'r> {
+        ApduReader::new(self.data)
+    }
+
+    pub fn into_response(self) -> CommandResponse<'c, N, T> {
+        self.command.into_response()
+    }
+
+    pub fn into_comm(self) -> &'c mut Comm<N, T> {
+        self.command.into_comm()
+    }
+
+    pub fn reply<R: Into<Reply>>(self, data: &[u8], reply: R) -> Result<(), CommError> {
+        self.command.reply(data, reply)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_eq_err as assert_eq;
+    use crate::testing::TestType;
+    use testmacro::test_item as test;
+
+    fn header(p1: u8, p2: u8) -> ApduHeader {
+        ApduHeader {
+            cla: 0xe0,
+            ins: 0x04,
+            p1,
+            p2,
+        }
+    }
+
+    #[test]
+    fn chunks_in_order() {
+        let mut buf = [0u8; 8];
+        let mut chunks = ChunkReassembler::new(&mut buf);
+        assert_eq!(
+            chunks.push(&header(0, P2_MORE), &[1, 2, 3]).ok(),
+            Some(ChunkStatus::Pending)
+        );
+        assert_eq!(chunks.in_progress(), true);
+        assert_eq!(
+            chunks.push(&header(1, P2_MORE), &[4, 5]).ok(),
+            Some(ChunkStatus::Pending)
+        );
+        assert_eq!(
+            chunks.push(&header(2, P2_LAST), &[6]).ok(),
+            Some(ChunkStatus::Complete)
+        );
+        assert_eq!(chunks.in_progress(), false);
+        assert_eq!(chunks.data(), &[1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn chunks_out_of_order() {
+        let mut buf = [0u8; 8];
+        let mut chunks = ChunkReassembler::new(&mut buf);
+        assert_eq!(
+            matches!(
+                chunks.push(&header(1, P2_MORE), &[1]),
+                Err(StatusWords::BadP1P2)
+            ),
+            true
+        );
+        let _ = chunks.push(&header(0, P2_MORE), &[1]);
+        assert_eq!(
+            matches!(
+                chunks.push(&header(2, P2_LAST), &[2]),
+                Err(StatusWords::BadP1P2)
+            ),
+            true
+        );
+        // The transfer has been aborted.
+        assert_eq!(chunks.is_empty(), true);
+        assert_eq!(chunks.in_progress(), false);
+    }
+
+    #[test]
+    fn chunks_mismatched_ins() {
+        let mut buf = [0u8; 8];
+        let mut chunks = ChunkReassembler::new(&mut buf);
+        let _ = chunks.push(&header(0, P2_MORE), &[1]);
+        let mut other = header(1, P2_LAST);
+        other.ins = 0x06;
+        assert_eq!(
+            matches!(chunks.push(&other, &[2]), Err(StatusWords::BadIns)),
+            true
+        );
+    }
+
+    #[test]
+    fn chunks_overflow() {
+        let mut buf = [0u8; 4];
+        let mut chunks = ChunkReassembler::new(&mut buf);
+        let _ = chunks.push(&header(0, P2_MORE), &[1, 2, 3]);
+        assert_eq!(
+            matches!(
+                chunks.push(&header(1, P2_LAST), &[4, 5]),
+                Err(StatusWords::BadLen)
+            ),
+            true
+        );
+        assert_eq!(chunks.is_empty(), true);
+    }
+}
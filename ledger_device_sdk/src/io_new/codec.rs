@@ -0,0 +1,324 @@
+//! Typed reading of command payloads and writing of responses.
+//!
+//! [`ApduReader`] is a cursor over command data: every read checks the length of
+//! the remaining input and fails with [`ApduError::BadLen`] instead of panicking,
+//! so that handlers can use `?` and reply with `StatusWords::BadLen`:
+//!
+//! ```ignore
+//! fn handle_get_pubkey(command: Command) -> Result<(), Reply> {
+//!     let mut reader = command.reader();
+//!     let mut path = [0u32; MAX_BIP32_PATH_LEN];
+//!     let path = reader.read_bip32_path(&mut path)?;
+//!     let display = reader.read_u8()? != 0;
+//!     reader.finish()?;
+//!     // ...
+//!     let mut response = command.into_response();
+//!     response
+//!         .append_length_prefixed(&public_key)
+//!         .and_then(|r| r.append_u32_be(index))
+//!         .unwrap();
+//!     response.send(StatusWords::Ok).unwrap();
+//!     Ok(())
+//! }
+//! ```
+//!
+//! Variable-length integers use the Bitcoin `CompactSize` encoding: values below
+//! `0xfd` on one byte, otherwise a `0xfd`, `0xfe` or `0xff` prefix followed by
+//! a little-endian `u16`, `u32` or `u64`.
+
+use super::{ApduError, CommError, CommandResponse, Transport};
+
+/// Maximum number of components in a BIP32 path read by [`ApduReader::read_bip32_path`].
+pub const MAX_BIP32_PATH_LEN: usize = 10;
+
+macro_rules! impl_read_int {
+    ($name:ident, $t:ty, $from:ident) => {
+        pub fn $name(&mut self) -> Result<$t, ApduError> {
+            Ok(<$t>::$from(self.read_array()?))
+        }
+    };
+}
+
+macro_rules! impl_append_int {
+    ($name:ident, $t:ty, $to:ident) => {
+        pub fn $name(&mut self, value: $t) -> Result<&mut Self, CommError> {
+            self.append(&value.$to())
+        }
+    };
+}
+
+/// Cursor over the payload of a command.
+pub struct ApduReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ApduReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    /// Number of bytes read so far.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// Number of bytes left to read.
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    /// Fails with [`ApduError::BadLen`] if some bytes are left unread.
+    pub fn finish(&self) -> Result<(), ApduError> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(ApduError::BadLen)
+        }
+    }
+
+    /// Reads the next `len` bytes.
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], ApduError> {
+        if len > self.remaining() {
+            return Err(ApduError::BadLen);
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    /// Reads all the remaining bytes.
+    pub fn read_rest(&mut self) -> &'a [u8] {
+        let bytes = &self.data[self.pos..];
+        self.pos = self.data.len();
+        bytes
+    }
+
+    /// Reads the next `L` bytes into an array.
+    pub fn read_array<const L: usize>(&mut self) -> Result<[u8; L], ApduError> {
+        let mut array = [0u8; L];
+        array.copy_from_slice(self.read_bytes(L)?);
+        Ok(array)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, ApduError> {
+        Ok(self.read_array::<1>()?[0])
+    }
+
+    impl_read_int!(read_u16_be, u16, from_be_bytes);
+    impl_read_int!(read_u16_le, u16, from_le_bytes);
+    impl_read_int!(read_u32_be, u32, from_be_bytes);
+    impl_read_int!(read_u32_le, u32, from_le_bytes);
+    impl_read_int!(read_u64_be, u64, from_be_bytes);
+    impl_read_int!(read_u64_le, u64, from_le_bytes);
+
+    /// Reads a `CompactSize` variable-length integer. Non-minimal encodings are rejected.
+    pub fn read_varint(&mut self) -> Result<u64, ApduError> {
+        let (value, min) = match self.read_u8()? {
+            0xfd => (self.read_u16_le()? as u64, 0xfd),
+            0xfe => (self.read_u32_le()? as u64, 0x1_0000),
+            0xff => (self.read_u64_le()?, 0x1_0000_0000),
+            b => return Ok(b as u64),
+        };
+        if value < min {
+            return Err(ApduError::BadLen);
+        }
+        Ok(value)
+    }
+
+    /// Reads a byte string prefixed with its length on one byte.
+    pub fn read_length_prefixed(&mut self) -> Result<&'a [u8], ApduError> {
+        let len = self.read_u8()?;
+        self.read_bytes(len as usize)
+    }
+
+    /// Reads a BIP32 path encoded as the number of components on one byte,
+    /// followed by the big-endian `u32` components, into `path`.
+    ///
+    /// Fails with [`ApduError::BadLen`] if the path has more components than `path` can hold.
+    pub fn read_bip32_path<'p>(&mut self, path: &'p mut [u32]) -> Result<&'p [u32], ApduError> {
+        let count = self.read_u8()? as usize;
+        if count > path.len() {
+            return Err(ApduError::BadLen);
+        }
+        for component in path[..count].iter_mut() {
+            *component = self.read_u32_be()?;
+        }
+        Ok(&path[..count])
+    }
+}
+
+impl<'a, const N: usize, T: Transport> CommandResponse<'a, N, T> {
+    pub fn append_u8(&mut self, value: u8) -> Result<&mut Self, CommError> {
+        self.append(&[value])
+    }
+
+    impl_append_int!(append_u16_be, u16, to_be_bytes);
+    impl_append_int!(append_u16_le, u16, to_le_bytes);
+    impl_append_int!(append_u32_be, u32, to_be_bytes);
+    impl_append_int!(append_u32_le, u32, to_le_bytes);
+    impl_append_int!(append_u64_be, u64, to_be_bytes);
+    impl_append_int!(append_u64_le, u64, to_le_bytes);
+
+    /// Appends a `CompactSize` variable-length integer.
+    pub fn append_varint(&mut self, value: u64) -> Result<&mut Self, CommError> {
+        match value {
+            0..0xfd => self.append_u8(value as u8),
+            0xfd..=0xffff => self.append_u8(0xfd)?.append_u16_le(value as u16),
+            0x1_0000..=0xffff_ffff => self.append_u8(0xfe)?.append_u32_le(value as u32),
+            _ => self.append_u8(0xff)?.append_u64_le(value),
+        }
+    }
+
+    /// Appends a byte string prefixed with its length on one byte.
+    ///
+    /// Fails with [`CommError::Overflow`] if `bytes` is longer than 255 bytes.
+    pub fn append_length_prefixed(&mut self, bytes: &[u8]) -> Result<&mut Self, CommError> {
+        let len = u8::try_from(bytes.len()).map_err(|_| CommError::Overflow)?;
+        self.append_u8(len)?.append(bytes)
+    }
+
+    /// Appends a BIP32 path, with the same encoding as [`ApduReader::read_bip32_path`].
+    pub fn append_bip32_path(&mut self, path: &[u32]) -> Result<&mut Self, CommError> {
+        let count = u8::try_from(path.len()).map_err(|_| CommError::Overflow)?;
+        self.append_u8(count)?;
+        for component in path {
+            self.append_u32_be(*component)?;
+        }
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_eq_err as assert_eq;
+    use crate::io_new::{Comm, DEFAULT_BUF_SIZE, MockTransport, StatusWords};
+    use crate::testing::TestType;
+    use testmacro::test_item as test;
+
+    #[test]
+    fn read_integers() {
+        let data = [
+            0x01, 0x02, 0x03, 0x02, 0x03, 0x01, 0x02, 0x03, 0x04, 0x01, 0x02, 0x03, 0x04, 0x05,
+            0x06, 0x07, 0x08,
+        ];
+        let mut reader = ApduReader::new(&data);
+        assert_eq!(reader.read_u8(), Ok(0x01));
+        assert_eq!(reader.read_u16_be(), Ok(0x0203));
+        assert_eq!(reader.read_u16_le(), Ok(0x0302));
+        assert_eq!(reader.read_u32_be(), Ok(0x01020304));
+        assert_eq!(reader.read_u64_le(), Ok(0x0807060504030201));
+        assert_eq!(reader.finish(), Ok(()));
+        assert_eq!(reader.read_u8(), Err(ApduError::BadLen));
+    }
+
+    #[test]
+    fn read_varint() {
+        let data = [
+            0xfc, 0xfd, 0x00, 0x01, 0xfe, 0x00, 0x00, 0x01, 0x00, 0xfd, 0x10, 0x00,
+        ];
+        let mut reader = ApduReader::new(&data);
+        assert_eq!(reader.read_varint(), Ok(0xfc));
+        assert_eq!(reader.read_varint(), Ok(0x100));
+        assert_eq!(reader.read_varint(), Ok(0x10000));
+        // Non-minimal encoding of 0x10
+        assert_eq!(reader.read_varint(), Err(ApduError::BadLen));
+    }
+
+    #[test]
+    fn read_length_prefixed() {
+        let data = [0x02, 0xaa, 0xbb, 0x03, 0xcc];
+        let mut reader = ApduReader::new(&data);
+        assert_eq!(reader.read_length_prefixed(), Ok(&[0xaa, 0xbb][..]));
+        assert_eq!(reader.read_length_prefixed(), Err(ApduError::BadLen));
+    }
+
+    #[test]
+    fn read_bip32_path() {
+        let data = [
+            0x03, 0x80, 0x00, 0x00, 0x2c, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+        ];
+        let mut path = [0u32; MAX_BIP32_PATH_LEN];
+        let mut reader = ApduReader::new(&data);
+        assert_eq!(
+            reader.read_bip32_path(&mut path),
+            Ok(&[0x8000002c, 0x80000000, 1][..])
+        );
+
+        let mut short_path = [0u32; 2];
+        let mut reader = ApduReader::new(&data);
+        assert_eq!(
+            reader.read_bip32_path(&mut short_path),
+            Err(ApduError::BadLen)
+        );
+    }
+
+    #[test]
+    fn append_varint() {
+        let mut comm: Comm<DEFAULT_BUF_SIZE, MockTransport> =
+            Comm::with_transport(MockTransport::new(&[]));
+        let mut response = comm.begin_response();
+        for (value, len) in [
+            (0xfc, 1),
+            (0xfd, 3),
+            (0xffff, 3),
+            (0x1_0000, 5),
+            (0xffff_ffff, 5),
+            (0x1_0000_0000, 9),
+        ] {
+            let start = response.len();
+            assert_eq!(response.append_varint(value).map(|_| ()), Ok(()));
+            assert_eq!(response.len() - start, len);
+        }
+        let comm = response.send(StatusWords::Ok).map_err(|_| ())?;
+        let mut reader = ApduReader::new(comm.transport().last_reply());
+        for value in [0xfc, 0xfd, 0xffff, 0x1_0000, 0xffff_ffff, 0x1_0000_0000] {
+            assert_eq!(reader.read_varint(), Ok(value));
+        }
+        assert_eq!(reader.finish(), Ok(()));
+    }
+
+    #[test]
+    fn append_length_prefixed() {
+        let mut comm: Comm<DEFAULT_BUF_SIZE, MockTransport> =
+            Comm::with_transport(MockTransport::new(&[]));
+        let mut response = comm.begin_response();
+        assert_eq!(
+            response.append_length_prefixed(&[0xaa, 0xbb]).map(|_| ()),
+            Ok(())
+        );
+        // Nothing is written when the length does not fit in a byte
+        assert_eq!(
+            response.append_length_prefixed(&[0; 256]).map(|_| ()),
+            Err(CommError::Overflow)
+        );
+        assert_eq!(response.len(), 3);
+        let comm = response.send(StatusWords::Ok).map_err(|_| ())?;
+        assert_eq!(comm.transport().last_reply(), &[0x02, 0xaa, 0xbb]);
+    }
+
+    #[test]
+    fn append_bip32_path() {
+        let mut comm: Comm<DEFAULT_BUF_SIZE, MockTransport> =
+            Comm::with_transport(MockTransport::new(&[]));
+        let mut response = comm.begin_response();
+        assert_eq!(
+            response
+                .append_bip32_path(&[0x8000002c, 0x80000000, 1])
+                .map(|_| ()),
+            Ok(())
+        );
+        let comm = response.send(StatusWords::Ok).map_err(|_| ())?;
+        assert_eq!(
+            comm.transport().last_reply(),
+            &[
+                0x03, 0x80, 0x00, 0x00, 0x2c, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+            ]
+        );
+    }
+}
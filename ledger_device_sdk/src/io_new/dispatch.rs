@@ -0,0 +1,350 @@
+//! Dispatch of commands to per-instruction handlers.
+//!
+//! Instead of matching on the decoded instruction and repeating the same checks
+//! in every handler, applications declare a table of [`Route`]s, each one with
+//! a handler and the conditions the command must satisfy to reach it:
+//!
+//! ```ignore
+//! const ROUTES: &[Route<App, AppSW>] = &[
+//!     Route::new(INS_GET_VERSION, handle_get_version).swap_allowed(),
+//!     Route::new(INS_GET_PUBKEY, handle_get_pubkey)
+//!         .p1(0, 1)
+//!         .data_len(1, 1 + 4 * MAX_BIP32_PATH_LEN)
+//!         .requires_unlocked(),
+//!     Route::new(INS_SIGN_TX, handle_sign_tx).requires_unlocked(),
+//! ];
+//!
+//! fn handle_get_pubkey(app: &mut App, command: Command) -> Result<(), AppSW> {
+//!     let mut reader = command.reader();
+//!     // ...
+//!     command.reply(&public_key, StatusWords::Ok).map_err(|_| AppSW::IoError)
+//! }
+//!
+//! let mut dispatcher = Dispatcher::new(ROUTES);
+//! dispatcher.run(&mut comm, &mut app);
+//! ```
+//!
+//! A handler replies to the command itself when it succeeds. When it returns an
+//! error, it must not have replied: the dispatcher replies with the status word
+//! the error converts to, so that the mapping of errors to status words is done
+//! in a single place, the `From<E> for Reply` implementation.
+//!
+//! Commands which do not satisfy the conditions of their route are rejected
+//! without calling the handler, with:
+//! * [`StatusWords::BadIns`] if no route matches the INS, or if the route is not
+//!   allowed in swap mode (see [`Dispatcher::set_swap_mode`]),
+//! * [`StatusWords::BadCla`] if the CLA does not match,
+//! * [`StatusWords::DeviceLocked`] if the route requires the device to be
+//!   unlocked and it is not,
+//! * [`StatusWords::BadP1P2`] if P1 or P2 is out of range,
+//! * [`StatusWords::BadLen`] if the data length is out of range.
+
+use super::{
+    ApduHeader, Comm, Command, DEFAULT_BUF_SIZE, Reply, SephTransport, StatusWords, Transport,
+};
+use ledger_secure_sdk_sys::{BOLOS_TRUE, os_global_pin_is_validated, os_perso_is_pin_set};
+
+/// Handler of the commands of a [`Route`].
+pub type Handler<C, E, const N: usize, T> = fn(&mut C, Command<'_, N, T>) -> Result<(), E>;
+
+/// Conditions a command must satisfy to be dispatched to a handler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Guard {
+    pub cla: Option<u8>,
+    pub p1: (u8, u8),
+    pub p2: (u8, u8),
+    pub min_data_len: usize,
+    pub max_data_len: usize,
+    pub requires_unlocked: bool,
+    pub swap_allowed: bool,
+}
+
+impl Default for Guard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Guard {
+    /// Guard accepting any CLA, P1, P2 and data length, in any state except swap mode.
+    pub const fn new() -> Self {
+        Self {
+            cla: None,
+            p1: (0, u8::MAX),
+            p2: (0, u8::MAX),
+            min_data_len: 0,
+            max_data_len: usize::MAX,
+            requires_unlocked: false,
+            swap_allowed: false,
+        }
+    }
+
+    /// Checks a command with the given header and data length against this guard.
+    pub fn check(
+        &self,
+        header: &ApduHeader,
+        data_len: usize,
+        swap_mode: bool,
+    ) -> Result<(), StatusWords> {
+        if swap_mode && !self.swap_allowed {
+            return Err(StatusWords::BadIns);
+        }
+        if self.cla.is_some_and(|cla| cla != header.cla) {
+            return Err(StatusWords::BadCla);
+        }
+        if self.requires_unlocked && is_device_locked() {
+            return Err(StatusWords::DeviceLocked);
+        }
+        if !(self.p1.0..=self.p1.1).contains(&header.p1)
+            || !(self.p2.0..=self.p2.1).contains(&header.p2)
+        {
+            return Err(StatusWords::BadP1P2);
+        }
+        if !(self.min_data_len..=self.max_data_len).contains(&data_len) {
+            return Err(StatusWords::BadLen);
+        }
+        Ok(())
+    }
+}
+
+/// Returns true if a PIN is set and has not been validated.
+fn is_device_locked() -> bool {
+    unsafe {
+        os_perso_is_pin_set() == BOLOS_TRUE.try_into().unwrap()
+            && os_global_pin_is_validated() != BOLOS_TRUE.try_into().unwrap()
+    }
+}
+
+/// Handler of the commands with a given INS, along with its [`Guard`].
+///
+/// `C` is the application context passed to handlers, and `E` the error type
+/// they return.
+pub struct Route<C, E = Reply, const N: usize = DEFAULT_BUF_SIZE, T: Transport = SephTransport> {
+    ins: u8,
+    guard: Guard,
+    handler: Handler<C, E, N, T>,
+}
+
+impl<C, E, const N: usize, T: Transport> Route<C, E, N, T> {
+    pub const fn new(ins: u8, handler: Handler<C, E, N, T>) -> Self {
+        Self {
+            ins,
+            guard: Guard::new(),
+            handler,
+        }
+    }
+
+    pub fn ins(&self) -> u8 {
+        self.ins
+    }
+
+    pub fn guard(&self) -> &Guard {
+        &self.guard
+    }
+
+    /// Only accepts commands with the given CLA.
+    pub const fn cla(mut self, cla: u8) -> Self {
+        self.guard.cla = Some(cla);
+        self
+    }
+
+    /// Only accepts commands with `min <= P1 <= max`.
+    pub const fn p1(mut self, min: u8, max: u8) -> Self {
+        self.guard.p1 = (min, max);
+        self
+    }
+
+    /// Only accepts commands with `min <= P2 <= max`.
+    pub const fn p2(mut self, min: u8, max: u8) -> Self {
+        self.guard.p2 = (min, max);
+        self
+    }
+
+    /// Only accepts commands with `min <= data length <= max`.
+    pub const fn data_len(mut self, min: usize, max: usize) -> Self {
+        self.guard.min_data_len = min;
+        self.guard.max_data_len = max;
+        self
+    }
+
+    /// Rejects commands while the device is locked.
+    pub const fn requires_unlocked(mut self) -> Self {
+        self.guard.requires_unlocked = true;
+        self
+    }
+
+    /// Accepts commands in swap mode.
+    pub const fn swap_allowed(mut self) -> Self {
+        self.guard.swap_allowed = true;
+        self
+    }
+}
+
+/// Dispatches received commands to the handlers of a table of [`Route`]s.
+pub struct Dispatcher<
+    'r,
+    C,
+    E = Reply,
+    const N: usize = DEFAULT_BUF_SIZE,
+    T: Transport = SephTransport,
+> {
+    routes: &'r [Route<C, E, N, T>],
+    swap_mode: bool,
+}
+
+impl<'r, C, E, const N: usize, T: Transport> Dispatcher<'r, C, E, N, T>
+where
+    Reply: From<E>,
+{
+    /// Creates a dispatcher for the given routes. When several routes have the
+    /// same INS, the first one is used.
+    pub const fn new(routes: &'r [Route<C, E, N, T>]) -> Self {
+        Self {
+            routes,
+            swap_mode: false,
+        }
+    }
+
+    /// In swap mode, only routes declared with [`Route::swap_allowed`] are dispatched.
+    pub fn set_swap_mode(&mut self, swap_mode: bool) {
+        self.swap_mode = swap_mode;
+    }
+
+    pub fn swap_mode(&self) -> bool {
+        self.swap_mode
+    }
+
+    /// Receives the next command and dispatches it.
+    ///
+    /// # Returns
+    /// `Ok(())` if the handler succeeded, otherwise the status word the command
+    /// has been rejected with.
+    pub fn dispatch_next(&self, comm: &mut Comm<N, T>, ctx: &mut C) -> Result<(), Reply> {
+        let command = comm.next_command();
+        let header = command.header;
+        let result = match self.routes.iter().find(|r| r.ins == header.ins) {
+            None => Err(Reply::from(StatusWords::BadIns)),
+            Some(route) => {
+                match route
+                    .guard
+                    .check(&header, command.get_data().len(), self.swap_mode)
+                {
+                    Err(sw) => Err(Reply::from(sw)),
+                    Ok(()) => (route.handler)(ctx, command).map_err(Reply::from),
+                }
+            }
+        };
+        if let Err(reply) = &result {
+            let _ = comm.begin_response().send(Reply(reply.0));
+        }
+        result
+    }
+
+    /// Dispatches received commands forever.
+    pub fn run(&self, comm: &mut Comm<N, T>, ctx: &mut C) -> ! {
+        loop {
+            let _ = self.dispatch_next(comm, ctx);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_eq_err as assert_eq;
+    use crate::io_new::MockTransport;
+    use crate::testing::TestType;
+    use testmacro::test_item as test;
+
+    type TestCommand<'a, 'b> = Command<'a, DEFAULT_BUF_SIZE, MockTransport<'b>>;
+
+    fn handle_echo(count: &mut u32, command: TestCommand) -> Result<(), Reply> {
+        *count += 1;
+        let data = [command.get_data().len() as u8];
+        let _ = command.reply(&data, StatusWords::Ok);
+        Ok(())
+    }
+
+    fn handle_fail(count: &mut u32, _command: TestCommand) -> Result<(), Reply> {
+        *count += 1;
+        Err(Reply(0x6a80))
+    }
+
+    #[test]
+    fn dispatch_routes() {
+        let apdus: &[&[u8]] = &[
+            &[0xe0, 0x01, 0x00, 0x00, 0x02, 0xaa, 0xbb],
+            &[0xe0, 0x02, 0x00, 0x00],
+            &[0xe0, 0x03, 0x00, 0x00],
+        ];
+        let mut comm: Comm<DEFAULT_BUF_SIZE, MockTransport> =
+            Comm::with_transport(MockTransport::new(apdus));
+        let routes = [Route::new(0x01, handle_echo), Route::new(0x02, handle_fail)];
+        let dispatcher = Dispatcher::new(&routes);
+        let mut count = 0;
+
+        assert_eq!(
+            dispatcher.dispatch_next(&mut comm, &mut count).is_ok(),
+            true
+        );
+        assert_eq!(comm.transport().last_reply(), &[0x02]);
+
+        assert_eq!(
+            dispatcher
+                .dispatch_next(&mut comm, &mut count)
+                .map_err(|r| r.0),
+            Err(0x6a80)
+        );
+        assert_eq!(comm.transport().last_status_word(), Some(0x6a80));
+
+        assert_eq!(
+            dispatcher
+                .dispatch_next(&mut comm, &mut count)
+                .map_err(|r| r.0),
+            Err(StatusWords::BadIns as u16)
+        );
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn dispatch_guards() {
+        let apdus: &[&[u8]] = &[
+            &[0xe1, 0x01, 0x00, 0x00, 0x01, 0xaa],
+            &[0xe0, 0x01, 0x02, 0x00, 0x01, 0xaa],
+            &[0xe0, 0x01, 0x00, 0x00],
+            &[0xe0, 0x01, 0x00, 0x00, 0x01, 0xaa],
+        ];
+        let mut comm: Comm<DEFAULT_BUF_SIZE, MockTransport> =
+            Comm::with_transport(MockTransport::new(apdus));
+        let routes = [Route::new(0x01, handle_echo)
+            .cla(0xe0)
+            .p1(0, 1)
+            .data_len(1, 4)];
+        let mut dispatcher = Dispatcher::new(&routes);
+        let mut count = 0;
+
+        for sw in [
+            StatusWords::BadCla,
+            StatusWords::BadP1P2,
+            StatusWords::BadLen,
+        ] {
+            assert_eq!(
+                dispatcher
+                    .dispatch_next(&mut comm, &mut count)
+                    .map_err(|r| r.0),
+                Err(sw as u16)
+            );
+        }
+        assert_eq!(count, 0);
+
+        // Not allowed in swap mode
+        dispatcher.set_swap_mode(true);
+        assert_eq!(
+            dispatcher
+                .dispatch_next(&mut comm, &mut count)
+                .map_err(|r| r.0),
+            Err(StatusWords::BadIns as u16)
+        );
+        assert_eq!(count, 0);
+    }
+}
@@ -1,4 +1,4 @@
-use super::{ApduError, ApduHeader, Comm};
+use super::{ApduError, ApduHeader, Comm, Transport};
 use crate::seph;
 
 #[cfg(any(target_os = "nanosplus", target_os = "nanox"))]
@@ -22,7 +22,7 @@ pub struct DecodedEvent<const N: usize> {
 }
 
 impl<const N: usize> DecodedEvent<N> {
-    pub fn new(comm: &mut Comm<N>, len: usize) -> Self {
+    pub fn new<T: Transport>(comm: &mut Comm<N, T>, len: usize) -> Self {
         // If no data was received, return Ignored to avoid reading stale buffer data
         if len == 0 {
             return Self {
@@ -57,7 +57,7 @@ impl<const N: usize> DecodedEvent<N> {
         Self { event_type }
     }
 
-    fn decode_seph_event(comm: &mut Comm<N>, offset: usize) -> DecodedEventType {
+    fn decode_seph_event<T: Transport>(comm: &mut Comm<N, T>, offset: usize) -> DecodedEventType {
         use crate::seph::Events;
         let seph_buffer = &comm.buf[offset..];
         let tag = seph_buffer[0];
@@ -165,53 +165,138 @@ impl<const N: usize> DecodedEvent<N> {
         }
     }
 
-    fn decode_apdu(
-        comm: &mut Comm<N>,
+    fn decode_apdu<T: Transport>(
+        comm: &mut Comm<N, T>,
         packet_type: u8,
         offset: usize,
         io_len: usize,
     ) -> DecodedEventType {
-        use ApduError::*;
-
         comm.apdu_type = packet_type;
-
-        let apdu_buffer = &comm.buf[offset..];
+        comm.le = None;
+        comm.extended_apdu = false;
 
         if io_len < 5 {
-            return DecodedEventType::ApduError(BadLen);
+            return DecodedEventType::ApduError(ApduError::BadLen);
         }
 
-        let rx_len = io_len - 1;
-
+        let apdu_buffer = &comm.buf[offset..offset + io_len - 1];
         let header = ApduHeader {
             cla: apdu_buffer[0],
             ins: apdu_buffer[1],
             p1: apdu_buffer[2],
             p2: apdu_buffer[3],
         };
-        if rx_len == 4 {
-            return DecodedEventType::new_apdu(header, 4, 0);
+        let body = match comm.apdu_parsing {
+            ApduParsing::Legacy => parse_legacy_body(&apdu_buffer[4..]),
+            ApduParsing::Iso7816 => parse_iso7816_body(&apdu_buffer[4..]),
+        };
+        match body {
+            Ok(body) => {
+                comm.le = body.le;
+                comm.extended_apdu = body.extended;
+                DecodedEventType::new_apdu(header, offset + 4 + body.offset, body.length)
+            }
+            Err(e) => DecodedEventType::ApduError(e),
+        }
+    }
+}
+
+/// How the body of incoming APDUs (everything after P2) is parsed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ApduParsing {
+    /// Historical Ledger encoding: an optional short or extended Lc followed by
+    /// the data, without Le. A single `Lc = 0` byte is read as an empty payload.
+    #[default]
+    Legacy,
+    /// ISO 7816-4 encoding, covering cases 1 to 4 with short and extended
+    /// lengths. A short `Le` of 0 stands for 256 bytes, an extended `Le` of 0
+    /// for 65536 bytes: a single `00` byte after P2 is thus read as `Le = 256`.
+    Iso7816,
+}
+
+/// Location of the data within an APDU body, and expected response length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ApduBody {
+    offset: usize,
+    length: usize,
+    le: Option<usize>,
+    extended: bool,
+}
+
+impl ApduBody {
+    fn new(offset: usize, length: usize, le: Option<usize>, extended: bool) -> Self {
+        Self {
+            offset,
+            length,
+            le,
+            extended,
         }
-        let first_len_byte = apdu_buffer[4];
+    }
+}
 
-        match (first_len_byte, rx_len) {
-            (0, 5) => {
-                // Non-conforming zero-data APDU (TODO: per the standard, this should actually be read as a 256-byte long APDU; but that's likely to break things as lots)
-                DecodedEventType::new_apdu(header, 4, 0)
+fn parse_legacy_body(body: &[u8]) -> Result<ApduBody, ApduError> {
+    match body {
+        [] => Ok(ApduBody::new(0, 0, None, false)),
+        // Non-conforming zero-data APDU, see `ApduParsing::Iso7816` for the standard behaviour.
+        [0] => Ok(ApduBody::new(0, 0, None, false)),
+        [0, _] => Err(ApduError::BadLen),
+        [0, hi, lo, data @ ..] => {
+            let len = u16::from_be_bytes([*hi, *lo]) as usize;
+            if data.len() != len {
+                return Err(ApduError::BadLen);
             }
-            (0, 6) => DecodedEventType::ApduError(BadLen),
-            (0, _) => {
-                let len = u16::from_be_bytes([apdu_buffer[5], apdu_buffer[6]]) as usize;
-                if rx_len != len + 7 {
-                    return DecodedEventType::ApduError(BadLen);
-                }
-                DecodedEventType::new_apdu(header, 1 + 7, len)
+            Ok(ApduBody::new(3, len, None, true))
+        }
+        [lc, data @ ..] => {
+            if data.len() != *lc as usize {
+                return Err(ApduError::BadLen);
             }
-            (len, _) => {
-                if rx_len != len as usize + 5 {
-                    return DecodedEventType::ApduError(BadLen);
-                }
-                DecodedEventType::new_apdu(header, 1 + 5, len as usize)
+            Ok(ApduBody::new(1, data.len(), None, false))
+        }
+    }
+}
+
+fn parse_iso7816_body(body: &[u8]) -> Result<ApduBody, ApduError> {
+    let short_le = |le: u8| if le == 0 { 256 } else { le as usize };
+    let extended_le = |hi: u8, lo: u8| match u16::from_be_bytes([hi, lo]) {
+        0 => 65536,
+        le => le as usize,
+    };
+
+    match body {
+        // Case 1: no data, no Le
+        [] => Ok(ApduBody::new(0, 0, None, false)),
+        // Case 2S: short Le
+        [le] => Ok(ApduBody::new(0, 0, Some(short_le(*le)), false)),
+        // Case 2E: extended Le
+        [0, hi, lo] => Ok(ApduBody::new(0, 0, Some(extended_le(*hi, *lo)), true)),
+        // Cases 3E and 4E: extended Lc, optionally followed by an extended Le
+        [0, hi, lo, rest @ ..] => {
+            let lc = u16::from_be_bytes([*hi, *lo]) as usize;
+            if lc == 0 {
+                return Err(ApduError::BadLen);
+            }
+            match rest.len().checked_sub(lc) {
+                Some(0) => Ok(ApduBody::new(3, lc, None, true)),
+                Some(2) => Ok(ApduBody::new(
+                    3,
+                    lc,
+                    Some(extended_le(rest[lc], rest[lc + 1])),
+                    true,
+                )),
+                _ => Err(ApduError::BadLen),
+            }
+        }
+        [0, _] => Err(ApduError::BadLen),
+        // Cases 3S and 4S: short Lc, optionally followed by a short Le
+        [lc, rest @ ..] => {
+            let lc = *lc as usize;
+            if rest.len() == lc {
+                Ok(ApduBody::new(1, lc, None, false))
+            } else if rest.len() == lc + 1 {
+                Ok(ApduBody::new(1, lc, Some(short_le(rest[lc])), false))
+            } else {
+                Err(ApduError::BadLen)
             }
         }
     }
@@ -243,3 +328,80 @@ impl DecodedEventType {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_eq_err as assert_eq;
+    use crate::testing::TestType;
+    use testmacro::test_item as test;
+
+    #[test]
+    fn legacy_body() {
+        assert_eq!(
+            parse_legacy_body(&[]).ok(),
+            Some(ApduBody::new(0, 0, None, false))
+        );
+        assert_eq!(
+            parse_legacy_body(&[0]).ok(),
+            Some(ApduBody::new(0, 0, None, false))
+        );
+        assert_eq!(
+            parse_legacy_body(&[2, 0xaa, 0xbb]).ok(),
+            Some(ApduBody::new(1, 2, None, false))
+        );
+        assert_eq!(
+            parse_legacy_body(&[0, 0, 1, 0xaa]).ok(),
+            Some(ApduBody::new(3, 1, None, true))
+        );
+        assert_eq!(parse_legacy_body(&[2, 0xaa]).is_err(), true);
+        assert_eq!(parse_legacy_body(&[0, 1]).is_err(), true);
+    }
+
+    #[test]
+    fn iso7816_short_body() {
+        // Case 2S, Le = 0 means 256 bytes
+        assert_eq!(
+            parse_iso7816_body(&[0]).ok(),
+            Some(ApduBody::new(0, 0, Some(256), false))
+        );
+        assert_eq!(
+            parse_iso7816_body(&[0x20]).ok(),
+            Some(ApduBody::new(0, 0, Some(0x20), false))
+        );
+        // Case 3S
+        assert_eq!(
+            parse_iso7816_body(&[2, 0xaa, 0xbb]).ok(),
+            Some(ApduBody::new(1, 2, None, false))
+        );
+        // Case 4S
+        assert_eq!(
+            parse_iso7816_body(&[2, 0xaa, 0xbb, 0x10]).ok(),
+            Some(ApduBody::new(1, 2, Some(0x10), false))
+        );
+        assert_eq!(parse_iso7816_body(&[2, 0xaa, 0xbb, 0x10, 0]).is_err(), true);
+        assert_eq!(parse_iso7816_body(&[0, 1]).is_err(), true);
+    }
+
+    #[test]
+    fn iso7816_extended_body() {
+        // Case 2E, Le = 0 means 65536 bytes
+        assert_eq!(
+            parse_iso7816_body(&[0, 0, 0]).ok(),
+            Some(ApduBody::new(0, 0, Some(65536), true))
+        );
+        // Case 3E
+        assert_eq!(
+            parse_iso7816_body(&[0, 0, 2, 0xaa, 0xbb]).ok(),
+            Some(ApduBody::new(3, 2, None, true))
+        );
+        // Case 4E
+        assert_eq!(
+            parse_iso7816_body(&[0, 0, 2, 0xaa, 0xbb, 0x01, 0x00]).ok(),
+            Some(ApduBody::new(3, 2, Some(0x100), true))
+        );
+        // Extended Lc of 0 is invalid
+        assert_eq!(parse_iso7816_body(&[0, 0, 0, 0xaa]).is_err(), true);
+        assert_eq!(parse_iso7816_body(&[0, 0, 3, 0xaa]).is_err(), true);
+    }
+}
@@ -0,0 +1,625 @@
+//! Encrypted and authenticated session between the host and the application.
+//!
+//! A [`SecureSession`] protects command payloads and response data against the
+//! host transport stack (USB, BLE), on top of a regular `Comm`.
+//!
+//! # Handshake
+//!
+//! The host sends an ephemeral public key: a 65-byte uncompressed point with
+//! [`SessionCurve::Secp256k1`], or a 32-byte u-coordinate with
+//! [`SessionCurve::Curve25519`]. The application answers with its own ephemeral
+//! public key, and both sides derive the session keys from the ECDH shared secret
+//! `Z` with HMAC-SHA256:
+//!
+//! ```text
+//! PRK = HMAC(b"Ledger secure session", Z)
+//! key = HMAC(PRK, label || host public key || device public key)
+//! ```
+//!
+//! `label` being `b"host enc"`, `b"host mac"`, `b"device enc"` and `b"device mac"`.
+//!
+//! # Messages
+//!
+//! Command data and response data are sent as:
+//!
+//! ```text
+//! counter (u32, big-endian) || ciphertext || tag (16 bytes)
+//! ```
+//!
+//! The ciphertext is the plaintext XORed with the keystream
+//! `HMAC(enc key, counter || block index)`, block index being a big-endian `u32`,
+//! and the tag is the HMAC of `counter || header || ciphertext` truncated to 16
+//! bytes, `header` being CLA, INS, P1 and P2 for commands and the status word for
+//! responses. Counters of host messages must be strictly increasing, and the
+//! application numbers its responses from 0.
+//!
+//! ```ignore
+//! let mut session = SecureSession::new(SessionCurve::Curve25519);
+//! let mut buf = [0u8; 256];
+//! loop {
+//!     let command = comm.next_command();
+//!     match command.decode::<Instruction>() {
+//!         Ok(Instruction::OpenSession) => {
+//!             let _ = session.handshake(command);
+//!         }
+//!         Ok(Instruction::ExportBackup) => match session.open_command(&command, &mut buf) {
+//!             Ok(data) => {
+//!                 // ...
+//!                 let _ = session.reply(command, &backup, StatusWords::Ok);
+//!             }
+//!             Err(e) => {
+//!                 let _ = command.reply(&[], e);
+//!             }
+//!         },
+//!         // ...
+//!     }
+//! }
+//! ```
+
+use super::CommandResponse;
+use super::{ApduHeader, CommError, Command, Reply, StatusWords, Transport};
+use crate::cipher::apply_hmac_keystream;
+use crate::ecc::{Curve25519, CxError, Secp256k1, Secret};
+use crate::hmac::{HMACError, HMACInit, mac_eq, sha2::Sha2_256};
+use crate::random::rand_bytes;
+use zeroize::Zeroize;
+
+/// Length of the message counter.
+pub const SESSION_COUNTER_LEN: usize = 4;
+/// Length of the authentication tag.
+pub const SESSION_TAG_LEN: usize = 16;
+/// Number of bytes added to a plaintext by the session encoding.
+pub const SESSION_OVERHEAD: usize = SESSION_COUNTER_LEN + SESSION_TAG_LEN;
+
+const KEY_LEN: usize = 32;
+const SALT: &[u8] = b"Ledger secure session";
+
+/// Curve used for the ECDH handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionCurve {
+    Secp256k1,
+    Curve25519,
+}
+
+impl SessionCurve {
+    /// Length of an ephemeral public key exchanged during the handshake.
+    pub const fn public_key_len(self) -> usize {
+        match self {
+            SessionCurve::Secp256k1 => 65,
+            SessionCurve::Curve25519 => 32,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionError {
+    /// No handshake has been completed.
+    NotEstablished,
+    /// Message or public key of invalid length, or output buffer too small.
+    BadLength,
+    /// Invalid authentication tag.
+    BadMac,
+    /// Counter not greater than the one of the last accepted message.
+    Replay,
+    /// All the counter values have been used, a new handshake is required.
+    CounterExhausted,
+    /// Invalid public key or failure of a cryptographic primitive.
+    Crypto,
+}
+
+impl From<CxError> for SessionError {
+    fn from(_: CxError) -> SessionError {
+        SessionError::Crypto
+    }
+}
+
+impl From<HMACError> for SessionError {
+    fn from(_: HMACError) -> SessionError {
+        SessionError::Crypto
+    }
+}
+
+impl From<CommError> for SessionError {
+    fn from(_: CommError) -> SessionError {
+        SessionError::BadLength
+    }
+}
+
+impl From<SessionError> for Reply {
+    fn from(e: SessionError) -> Reply {
+        match e {
+            SessionError::BadLength => StatusWords::BadLen.into(),
+            // Security status not satisfied
+            SessionError::BadMac | SessionError::Replay => Reply(0x6982),
+            // Conditions of use not satisfied
+            SessionError::NotEstablished | SessionError::CounterExhausted => Reply(0x6985),
+            // Incorrect parameters in the data field
+            SessionError::Crypto => Reply(0x6A80),
+        }
+    }
+}
+
+/// Encryption and MAC keys protecting the messages sent in one direction.
+struct DirectionKeys {
+    enc: [u8; KEY_LEN],
+    mac: [u8; KEY_LEN],
+}
+
+impl Drop for DirectionKeys {
+    fn drop(&mut self) {
+        self.enc.zeroize();
+        self.mac.zeroize();
+    }
+}
+
+impl DirectionKeys {
+    /// XORs `data` with the keystream of message `counter`, starting at block `block`.
+    fn apply_keystream(&self, counter: u32, block: u32, data: &mut [u8]) -> Result<(), HMACError> {
+        apply_hmac_keystream(&self.enc, counter, block, data)
+    }
+
+    fn mac(&self, counter: u32, header: &[u8]) -> Result<Sha2_256, HMACError> {
+        let mut mac = Sha2_256::new(&self.mac);
+        mac.update(&counter.to_be_bytes())?;
+        mac.update(header)?;
+        Ok(mac)
+    }
+
+    fn tag(mut mac: Sha2_256) -> Result<[u8; SESSION_TAG_LEN], HMACError> {
+        let mut full = [0u8; 32];
+        mac.finalize(&mut full)?;
+        let mut tag = [0u8; SESSION_TAG_LEN];
+        tag.copy_from_slice(&full[..SESSION_TAG_LEN]);
+        Ok(tag)
+    }
+
+    /// Encodes `plaintext` into `out`, returning the encoded length.
+    fn seal(
+        &self,
+        counter: u32,
+        header: &[u8],
+        plaintext: &[u8],
+        out: &mut [u8],
+    ) -> Result<usize, SessionError> {
+        let len = plaintext.len() + SESSION_OVERHEAD;
+        if out.len() < len {
+            return Err(SessionError::BadLength);
+        }
+        let (counter_bytes, rest) = out.split_at_mut(SESSION_COUNTER_LEN);
+        let (ciphertext, rest) = rest.split_at_mut(plaintext.len());
+        counter_bytes.copy_from_slice(&counter.to_be_bytes());
+        ciphertext.copy_from_slice(plaintext);
+        self.apply_keystream(counter, 0, ciphertext)?;
+        let mut mac = self.mac(counter, header)?;
+        mac.update(ciphertext)?;
+        rest[..SESSION_TAG_LEN].copy_from_slice(&Self::tag(mac)?);
+        Ok(len)
+    }
+
+    /// Authenticates and decodes `message` into `out`, returning the message
+    /// counter and the plaintext length.
+    fn open(
+        &self,
+        header: &[u8],
+        message: &[u8],
+        out: &mut [u8],
+    ) -> Result<(u32, usize), SessionError> {
+        let len = message
+            .len()
+            .checked_sub(SESSION_OVERHEAD)
+            .ok_or(SessionError::BadLength)?;
+        if out.len() < len {
+            return Err(SessionError::BadLength);
+        }
+        let (counter_bytes, rest) = message.split_at(SESSION_COUNTER_LEN);
+        let (ciphertext, tag) = rest.split_at(len);
+        let counter = u32::from_be_bytes(counter_bytes.try_into().unwrap());
+
+        let mut mac = self.mac(counter, header)?;
+        mac.update(ciphertext)?;
+        let expected = Self::tag(mac)?;
+        if !mac_eq(&expected, tag) {
+            return Err(SessionError::BadMac);
+        }
+
+        out[..len].copy_from_slice(ciphertext);
+        self.apply_keystream(counter, 0, &mut out[..len])?;
+        Ok((counter, len))
+    }
+}
+
+struct SessionKeys {
+    host: DirectionKeys,
+    device: DirectionKeys,
+}
+
+impl SessionKeys {
+    /// Derives the session keys from the ECDH shared secret and both public keys.
+    fn derive(shared: &[u8], host_key: &[u8], device_key: &[u8]) -> Result<Self, HMACError> {
+        let mut prk = [0u8; KEY_LEN];
+        Sha2_256::new(SALT).hmac(shared, &mut prk)?;
+        let expand = |label: &[u8]| -> Result<[u8; KEY_LEN], HMACError> {
+            let mut key = [0u8; KEY_LEN];
+            let mut mac = Sha2_256::new(&prk);
+            mac.update(label)?;
+            mac.update(host_key)?;
+            mac.update(device_key)?;
+            mac.finalize(&mut key)?;
+            Ok(key)
+        };
+        let keys = SessionKeys {
+            host: DirectionKeys {
+                enc: expand(b"host enc")?,
+                mac: expand(b"host mac")?,
+            },
+            device: DirectionKeys {
+                enc: expand(b"device enc")?,
+                mac: expand(b"device mac")?,
+            },
+        };
+        prk.zeroize();
+        Ok(keys)
+    }
+}
+
+/// Encrypted session with the host. See the [module documentation](self) for
+/// the protocol.
+///
+/// Keys are erased when the session is closed or dropped.
+pub struct SecureSession {
+    curve: SessionCurve,
+    keys: Option<SessionKeys>,
+    // Smallest counter accepted for the next host message.
+    rx_counter: u64,
+    // Counter of the next response.
+    tx_counter: u64,
+}
+
+impl SecureSession {
+    pub fn new(curve: SessionCurve) -> Self {
+        Self {
+            curve,
+            keys: None,
+            rx_counter: 0,
+            tx_counter: 0,
+        }
+    }
+
+    pub fn curve(&self) -> SessionCurve {
+        self.curve
+    }
+
+    /// Returns true if a handshake has been completed and the session has not been closed.
+    pub fn is_established(&self) -> bool {
+        self.keys.is_some()
+    }
+
+    /// Erases the session keys. A new handshake is required to exchange messages.
+    pub fn close(&mut self) {
+        self.keys = None;
+        self.rx_counter = 0;
+        self.tx_counter = 0;
+    }
+
+    /// Performs the device side of the handshake: generates an ephemeral key pair,
+    /// writes its public key into `device_key` and derives the session keys.
+    ///
+    /// Any previous session is closed, even if the handshake fails.
+    ///
+    /// # Arguments
+    /// * `host_key` - The ephemeral public key of the host
+    /// * `device_key` - Output buffer for the ephemeral public key of the device
+    ///
+    /// # Returns
+    /// The length of the public key written into `device_key`.
+    pub fn accept(
+        &mut self,
+        host_key: &[u8],
+        device_key: &mut [u8],
+    ) -> Result<usize, SessionError> {
+        self.close();
+        let key_len = self.curve.public_key_len();
+        if host_key.len() != key_len || device_key.len() < key_len {
+            return Err(SessionError::BadLength);
+        }
+
+        let mut private_key = Secret::<32>::new();
+        rand_bytes(private_key.as_mut());
+        let mut shared = [0u8; 32];
+        let result = match self.curve {
+            SessionCurve::Secp256k1 => {
+                let sk = Secp256k1::from(private_key.as_ref());
+                sk.public_key().and_then(|pk| {
+                    device_key[..key_len].copy_from_slice(&pk.pubkey);
+                    shared = sk.ecdh(host_key)?;
+                    Ok(())
+                })
+            }
+            SessionCurve::Curve25519 => {
+                device_key[..key_len].fill(0);
+                device_key[0] = 9;
+                shared.copy_from_slice(host_key);
+                Curve25519::scalar_mul(&mut device_key[..key_len], private_key.as_ref())
+                    .and_then(|_| Curve25519::scalar_mul(&mut shared, private_key.as_ref()))
+            }
+        };
+        // An all-zero secret results from a low-order host point.
+        let result = result.map_err(SessionError::from).and_then(|_| {
+            if shared.iter().all(|&b| b == 0) {
+                Err(SessionError::Crypto)
+            } else {
+                SessionKeys::derive(&shared, host_key, &device_key[..key_len])
+                    .map_err(SessionError::from)
+            }
+        });
+        shared.zeroize();
+        self.keys = Some(result?);
+        Ok(key_len)
+    }
+
+    /// Handles a handshake command carrying the host public key, and replies
+    /// with the device public key, or with the error status word on failure.
+    pub fn handshake<const N: usize, T: Transport>(
+        &mut self,
+        command: Command<'_, N, T>,
+    ) -> Result<(), SessionError> {
+        let mut device_key = [0u8; 65];
+        match self.accept(command.get_data(), &mut device_key) {
+            Ok(len) => {
+                command.reply(&device_key[..len], StatusWords::Ok)?;
+                Ok(())
+            }
+            Err(e) => {
+                let _ = command.reply(&[], e);
+                Err(e)
+            }
+        }
+    }
+
+    /// Authenticates and decrypts the payload of a command sent with header
+    /// `header`, writing the plaintext into `out`.
+    ///
+    /// Messages with an invalid tag or a replayed counter are rejected.
+    pub fn open<'b>(
+        &mut self,
+        header: &ApduHeader,
+        message: &[u8],
+        out: &'b mut [u8],
+    ) -> Result<&'b [u8], SessionError> {
+        let keys = self.keys.as_ref().ok_or(SessionError::NotEstablished)?;
+        let header = [header.cla, header.ins, header.p1, header.p2];
+        let (counter, len) = keys.host.open(&header, message, out)?;
+        if (counter as u64) < self.rx_counter {
+            out[..len].zeroize();
+            return Err(SessionError::Replay);
+        }
+        self.rx_counter = counter as u64 + 1;
+        Ok(&out[..len])
+    }
+
+    /// Authenticates and decrypts the payload of `command` into `out`.
+    pub fn open_command<'b, const N: usize, T: Transport>(
+        &mut self,
+        command: &Command<'_, N, T>,
+        out: &'b mut [u8],
+    ) -> Result<&'b [u8], SessionError> {
+        self.open(&command.header, command.get_data(), out)
+    }
+
+    fn next_tx_counter(&mut self) -> Result<u32, SessionError> {
+        let counter = u32::try_from(self.tx_counter).map_err(|_| SessionError::CounterExhausted)?;
+        self.tx_counter += 1;
+        Ok(counter)
+    }
+
+    /// Encrypts and authenticates response `data`, sent with status word `sw`,
+    /// into `out`.
+    ///
+    /// # Returns
+    /// The length of the encoded response, i.e. `data.len() + SESSION_OVERHEAD`.
+    pub fn seal(&mut self, sw: u16, data: &[u8], out: &mut [u8]) -> Result<usize, SessionError> {
+        if self.keys.is_none() {
+            return Err(SessionError::NotEstablished);
+        }
+        let counter = self.next_tx_counter()?;
+        let keys = self.keys.as_ref().unwrap();
+        keys.device.seal(counter, &sw.to_be_bytes(), data, out)
+    }
+
+    /// Replies to `command` with encrypted `data`.
+    ///
+    /// The data is encrypted block by block directly into the response, so no
+    /// additional buffer is needed.
+    ///
+    /// On failure, e.g. when the encoded response does not fit in the `Comm`
+    /// buffer, the error is replied instead, with the status word of the
+    /// [`SessionError`].
+    pub fn reply<const N: usize, T: Transport, R: Into<Reply>>(
+        &mut self,
+        command: Command<'_, N, T>,
+        data: &[u8],
+        reply: R,
+    ) -> Result<(), SessionError> {
+        let sw = reply.into().0;
+        let mut response = command.into_response();
+        match self.seal_response(&mut response, sw, data) {
+            Ok(()) => {
+                response.send(Reply(sw))?;
+                Ok(())
+            }
+            Err(e) => {
+                response.clear();
+                response.send(e)?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Appends the encoding of `data`, sent with status word `sw`, to `response`.
+    fn seal_response<const N: usize, T: Transport>(
+        &mut self,
+        response: &mut CommandResponse<'_, N, T>,
+        sw: u16,
+        data: &[u8],
+    ) -> Result<(), SessionError> {
+        if self.keys.is_none() {
+            return Err(SessionError::NotEstablished);
+        }
+        // Checked before taking a counter, which is never reused
+        if response.remaining() < data.len() + SESSION_OVERHEAD {
+            return Err(SessionError::BadLength);
+        }
+        let counter = self.next_tx_counter()?;
+        let keys = &self.keys.as_ref().unwrap().device;
+
+        response.append(&counter.to_be_bytes())?;
+        let mut mac = keys.mac(counter, &sw.to_be_bytes())?;
+        let mut block = [0u8; KEY_LEN];
+        for (i, chunk) in data.chunks(KEY_LEN).enumerate() {
+            let block = &mut block[..chunk.len()];
+            block.copy_from_slice(chunk);
+            keys.apply_keystream(counter, i as u32, block)?;
+            mac.update(block)?;
+            response.append(block)?;
+        }
+        block.zeroize();
+        response.append(&DirectionKeys::tag(mac)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_eq_err as assert_eq;
+    use crate::io_new::{Comm, MockTransport};
+    use crate::testing::TestType;
+    use testmacro::test_item as test;
+
+    const HEADER: ApduHeader = ApduHeader {
+        cla: 0xe0,
+        ins: 0x10,
+        p1: 0x00,
+        p2: 0x00,
+    };
+
+    /// Performs the host side of a Curve25519 handshake, returning the session keys.
+    fn host_handshake(session: &mut SecureSession) -> SessionKeys {
+        let host_secret = [0x42u8; 32];
+        let mut host_key = [0u8; 32];
+        host_key[0] = 9;
+        Curve25519::scalar_mul(&mut host_key, &host_secret).unwrap();
+
+        let mut device_key = [0u8; 32];
+        let len = session.accept(&host_key, &mut device_key).unwrap();
+        let mut shared = device_key;
+        Curve25519::scalar_mul(&mut shared, &host_secret).unwrap();
+        SessionKeys::derive(&shared, &host_key, &device_key[..len]).unwrap()
+    }
+
+    #[test]
+    fn session_roundtrip() {
+        let mut session = SecureSession::new(SessionCurve::Curve25519);
+        let keys = host_handshake(&mut session);
+        assert_eq!(session.is_established(), true);
+
+        let mut message = [0u8; 5 + SESSION_OVERHEAD];
+        let header = [HEADER.cla, HEADER.ins, HEADER.p1, HEADER.p2];
+        let len = keys.host.seal(7, &header, b"hello", &mut message).unwrap();
+        let mut out = [0u8; 16];
+        assert_eq!(
+            session.open(&HEADER, &message[..len], &mut out),
+            Ok(&b"hello"[..])
+        );
+
+        let mut response = [0u8; 3 + SESSION_OVERHEAD];
+        let len = session.seal(0x9000, &[1, 2, 3], &mut response).unwrap();
+        assert_eq!(
+            keys.device.open(&[0x90, 0x00], &response[..len], &mut out),
+            Ok((0, 3))
+        );
+        assert_eq!(&out[..3], &[1, 2, 3]);
+    }
+
+    #[test]
+    fn session_rejects_replay_and_tampering() {
+        let mut session = SecureSession::new(SessionCurve::Curve25519);
+        let keys = host_handshake(&mut session);
+        let header = [HEADER.cla, HEADER.ins, HEADER.p1, HEADER.p2];
+        let mut message = [0u8; 4 + SESSION_OVERHEAD];
+        let len = keys.host.seal(1, &header, &[0; 4], &mut message).unwrap();
+        let mut out = [0u8; 4];
+
+        message[5] ^= 1;
+        assert_eq!(
+            session.open(&HEADER, &message[..len], &mut out),
+            Err(SessionError::BadMac)
+        );
+        message[5] ^= 1;
+        let mut other = HEADER;
+        other.p1 = 1;
+        assert_eq!(
+            session.open(&other, &message[..len], &mut out),
+            Err(SessionError::BadMac)
+        );
+
+        assert_eq!(
+            session.open(&HEADER, &message[..len], &mut out).is_ok(),
+            true
+        );
+        assert_eq!(
+            session.open(&HEADER, &message[..len], &mut out),
+            Err(SessionError::Replay)
+        );
+    }
+
+    #[test]
+    fn session_reply_too_long() {
+        let apdus: &[&[u8]] = &[&[0xe0, 0x10, 0x00, 0x00], &[0xe0, 0x10, 0x00, 0x00]];
+        let mut comm: Comm<64, MockTransport> = Comm::with_transport(MockTransport::new(apdus));
+        let mut session = SecureSession::new(SessionCurve::Curve25519);
+        let keys = host_handshake(&mut session);
+
+        let command = comm.next_command();
+        assert_eq!(
+            session.reply(
+                command,
+                &[0; 64 - 2 - SESSION_OVERHEAD + 1],
+                StatusWords::Ok
+            ),
+            Err(SessionError::BadLength)
+        );
+        assert_eq!(comm.transport().last_reply().len(), 0);
+        assert_eq!(
+            comm.transport().last_status_word(),
+            Some(StatusWords::BadLen as u16)
+        );
+
+        // The counter of the failed reply has not been used
+        let command = comm.next_command();
+        let data = [0x42; 64 - 2 - SESSION_OVERHEAD];
+        assert_eq!(session.reply(command, &data, StatusWords::Ok), Ok(()));
+        let mut out = [0u8; 64];
+        assert_eq!(
+            keys.device
+                .open(&[0x90, 0x00], comm.transport().last_reply(), &mut out),
+            Ok((0, data.len()))
+        );
+        assert_eq!(&out[..data.len()], &data);
+    }
+
+    #[test]
+    fn session_not_established() {
+        let mut session = SecureSession::new(SessionCurve::Secp256k1);
+        let mut out = [0u8; 32];
+        assert_eq!(
+            session.open(&HEADER, &[0; SESSION_OVERHEAD], &mut out),
+            Err(SessionError::NotEstablished)
+        );
+        assert_eq!(
+            session.accept(&[0x04; 33], &mut out),
+            Err(SessionError::BadLength)
+        );
+        assert_eq!(session.is_established(), false);
+    }
+}
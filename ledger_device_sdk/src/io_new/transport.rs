@@ -0,0 +1,212 @@
+//! Pluggable transport used by `Comm` to receive and send packets.
+//!
+//! On device, [`SephTransport`] forwards to the OS through the SEPH syscalls.
+//! [`MockTransport`] is an in-memory implementation which feeds scripted APDUs
+//! and captures replies, allowing APDU handlers to be exercised without going
+//! through the SEPH:
+//!
+//! ```ignore
+//! let apdus: &[&[u8]] = &[&[0xe0, 0x01, 0x00, 0x00, 0x00]];
+//! let mut comm: Comm<DEFAULT_BUF_SIZE, MockTransport> =
+//!     Comm::with_transport(MockTransport::new(apdus));
+//! handle_apdu(comm.next_command());
+//! assert_eq!(comm.transport().last_status_word(), Some(0x9000));
+//! ```
+//!
+//! The mock only replaces the SEPH: `Comm` and the rest of the SDK still depend
+//! on the C SDK and build for device targets only. Tests using the mock thus
+//! run with the rest of the SDK tests (`cargo test` on Speculos); running them
+//! with a plain host `cargo test` is not supported.
+
+use super::DEFAULT_BUF_SIZE;
+use crate::seph::PacketTypes;
+
+use ledger_secure_sdk_sys::seph as sys_seph;
+
+/// Low-level packet I/O backing a `Comm`.
+///
+/// Received packets follow the SEPH layout: a packet type byte (see
+/// [`PacketTypes`]) followed by the payload.
+pub trait Transport {
+    /// Receive the next packet into `buf`. Returns the number of bytes written,
+    /// or a negative value on error.
+    fn rx(&mut self, buf: &mut [u8], check_se_event: bool) -> i32;
+
+    /// Send the first `len` bytes of `buf` as a response to a packet of type
+    /// `packet_type`. Returns a negative value on error.
+    fn tx(&mut self, packet_type: u8, buf: &[u8], len: usize) -> i32;
+}
+
+/// Transport relying on the OS SEPH syscalls. This is the default transport of `Comm`.
+///
+/// Only the `Comm` using this transport is registered with NBGL: dropping it
+/// releases the global state set up by [`init_comm`](super::init_comm).
+pub struct SephTransport(());
+
+impl SephTransport {
+    pub(super) const fn new() -> Self {
+        Self(())
+    }
+}
+
+impl Drop for SephTransport {
+    fn drop(&mut self) {
+        super::release_comm();
+    }
+}
+
+impl Transport for SephTransport {
+    fn rx(&mut self, buf: &mut [u8], check_se_event: bool) -> i32 {
+        sys_seph::io_rx(buf, check_se_event)
+    }
+
+    fn tx(&mut self, packet_type: u8, buf: &[u8], len: usize) -> i32 {
+        sys_seph::io_tx(packet_type, buf, len)
+    }
+}
+
+/// In-memory transport replaying a script of raw APDUs and recording replies.
+///
+/// The last reply is kept in a buffer of `R` bytes (status word included).
+pub struct MockTransport<'a, const R: usize = DEFAULT_BUF_SIZE> {
+    apdus: &'a [&'a [u8]],
+    next: usize,
+    reply: [u8; R],
+    reply_len: usize,
+    reply_count: usize,
+}
+
+impl<'a, const R: usize> MockTransport<'a, R> {
+    pub fn new(apdus: &'a [&'a [u8]]) -> Self {
+        Self {
+            apdus,
+            next: 0,
+            reply: [0; R],
+            reply_len: 0,
+            reply_count: 0,
+        }
+    }
+
+    /// Number of scripted APDUs not yet received.
+    pub fn remaining(&self) -> usize {
+        self.apdus.len() - self.next
+    }
+
+    /// Number of replies sent so far.
+    pub fn reply_count(&self) -> usize {
+        self.reply_count
+    }
+
+    /// Data of the last reply, without the status word.
+    pub fn last_reply(&self) -> &[u8] {
+        &self.reply[..self.reply_len.saturating_sub(2)]
+    }
+
+    /// Status word of the last reply, or `None` if nothing has been sent yet.
+    pub fn last_status_word(&self) -> Option<u16> {
+        if self.reply_len < 2 {
+            return None;
+        }
+        Some(u16::from_be_bytes([
+            self.reply[self.reply_len - 2],
+            self.reply[self.reply_len - 1],
+        ]))
+    }
+}
+
+impl<'a, const R: usize> Transport for MockTransport<'a, R> {
+    /// # Panics
+    ///
+    /// Panics when the script is exhausted, as no further event could ever be received.
+    fn rx(&mut self, buf: &mut [u8], _check_se_event: bool) -> i32 {
+        let apdu = match self.apdus.get(self.next) {
+            Some(apdu) => *apdu,
+            None => panic!("MockTransport: no more scripted APDUs"),
+        };
+        self.next += 1;
+        let len = apdu.len() + 1;
+        if len > buf.len() {
+            return -1;
+        }
+        buf[0] = PacketTypes::PacketTypeRawApdu as u8;
+        buf[1..len].copy_from_slice(apdu);
+        len as i32
+    }
+
+    fn tx(&mut self, _packet_type: u8, buf: &[u8], len: usize) -> i32 {
+        if len > R || len > buf.len() {
+            return -1;
+        }
+        self.reply[..len].copy_from_slice(&buf[..len]);
+        self.reply_len = len;
+        self.reply_count += 1;
+        len as i32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_eq_err as assert_eq;
+    use crate::io_new::{ApduParsing, Comm, StatusWords, callbacks};
+    use crate::testing::TestType;
+    use testmacro::test_item as test;
+
+    #[test]
+    fn mock_transport_reply() {
+        let apdus: &[&[u8]] = &[&[0xe0, 0x02, 0x00, 0x00, 0x02, 0xab, 0xcd]];
+        let mut comm: Comm<DEFAULT_BUF_SIZE, MockTransport> =
+            Comm::with_transport(MockTransport::new(apdus));
+        let command = comm.next_command();
+        assert_eq!(command.get_data(), &[0xab, 0xcd]);
+        let _ = command.reply(&[0x01], StatusWords::Ok);
+        assert_eq!(comm.transport().remaining(), 0);
+        assert_eq!(comm.transport().reply_count(), 1);
+        assert_eq!(comm.transport().last_reply(), &[0x01]);
+        assert_eq!(comm.transport().last_status_word(), Some(0x9000));
+    }
+
+    #[test]
+    fn mock_transport_expected_cla() {
+        let apdus: &[&[u8]] = &[&[0xe1, 0x02, 0x00, 0x00], &[0xe0, 0x03, 0x00, 0x00]];
+        let mut comm: Comm<DEFAULT_BUF_SIZE, MockTransport> =
+            Comm::with_transport(MockTransport::new(apdus));
+        comm.set_expected_cla(0xe0);
+        let command = comm.next_command();
+        assert_eq!(command.get_data().len(), 0);
+        let comm = command.into_comm();
+        // The first APDU has been rejected without reaching the application.
+        assert_eq!(comm.transport().reply_count(), 1);
+        assert_eq!(
+            comm.transport().last_status_word(),
+            Some(StatusWords::BadCla as u16)
+        );
+    }
+
+    #[test]
+    fn mock_transport_le() {
+        // Case 4S with Le = 1
+        let apdus: &[&[u8]] = &[&[0xe0, 0x02, 0x00, 0x00, 0x01, 0xab, 0x01]];
+        let mut comm: Comm<DEFAULT_BUF_SIZE, MockTransport> =
+            Comm::with_transport(MockTransport::new(apdus));
+        comm.set_apdu_parsing(ApduParsing::Iso7816);
+        let command = comm.next_command();
+        assert_eq!(command.le(), Some(1));
+        let _ = command.reply(&[0x01, 0x02], StatusWords::Ok);
+        assert_eq!(comm.transport().last_reply().len(), 0);
+        assert_eq!(comm.transport().last_status_word(), Some(0x6C02));
+    }
+
+    #[test]
+    fn mock_transport_drop() {
+        let mut comm: Comm<DEFAULT_BUF_SIZE> = Comm::new();
+        callbacks::set_comm(&mut comm);
+        // Dropping a Comm using a mock leaves the registered one in place
+        drop(Comm::<DEFAULT_BUF_SIZE, MockTransport>::with_transport(
+            MockTransport::new(&[]),
+        ));
+        assert_eq!(callbacks::is_comm_null(), false);
+        drop(comm);
+        assert_eq!(callbacks::is_comm_null(), true);
+    }
+}
@@ -0,0 +1,289 @@
+//! Key derivation functions: HKDF (RFC 5869), PBKDF2 (RFC 8018) and SLIP-21.
+//!
+//! HKDF and PBKDF2 are generic over the HMAC algorithm, and can be used with
+//! any type implementing [`HMACInit`]:
+//!
+//! ```
+//! use ledger_device_sdk::hmac::sha2::Sha2_256;
+//! use ledger_device_sdk::kdf::{hkdf, pbkdf2_sha512, slip21_derive};
+//!
+//! let mut okm = [0u8; 42];
+//! hkdf::<Sha2_256>(&salt, &ikm, b"context", &mut okm)?;
+//!
+//! // BIP39 seed from a mnemonic and a passphrase
+//! let mut seed = [0u8; 64];
+//! pbkdf2_sha512(mnemonic, b"mnemonicTREZOR", 2048, &mut seed)?;
+//!
+//! let key = slip21_derive(&[b"SLIP-0021", b"Master encryption key"])?;
+//! ```
+
+use crate::ecc::{CurvesId, CxError, HDKeyDeriveMode, Secret};
+use crate::hmac::{HMACError, HMACInit, sha2::Sha2_512};
+use ledger_secure_sdk_sys::{CX_OK, cx_curve_t, sys_hdkey_derive};
+use zeroize::Zeroize;
+
+/// Largest digest size of the supported HMAC algorithms.
+const MAX_HASH_LEN: usize = 64;
+
+/// Maximum length of each label of a SLIP-21 path.
+pub const MAX_SLIP21_LABEL_LEN: usize = 63;
+
+/// Computes a MAC over the concatenation of `inputs`, and returns its length.
+fn mac<H: HMACInit>(key: &[u8], inputs: &[&[u8]], output: &mut [u8]) -> Result<usize, HMACError> {
+    let mut mac = H::new(key);
+    for input in inputs {
+        mac.update(input)?;
+    }
+    mac.finalize(output)
+}
+
+/// HKDF-Extract: computes the pseudorandom key from the input keying material
+/// `ikm` and an optional `salt` (which may be empty), and returns its length,
+/// which is the digest size of `H`.
+pub fn hkdf_extract<H: HMACInit>(
+    salt: &[u8],
+    ikm: &[u8],
+    prk: &mut [u8],
+) -> Result<usize, HMACError> {
+    mac::<H>(salt, &[ikm], prk)
+}
+
+/// HKDF-Expand: fills `okm` with output keying material derived from the
+/// pseudorandom key `prk` and the context `info`.
+///
+/// # Errors
+///
+/// Fails with [`HMACError::InvalidOutputLength`] if `okm` is longer than 255
+/// times the digest size of `H`. `okm` is erased on failure.
+pub fn hkdf_expand<H: HMACInit>(prk: &[u8], info: &[u8], okm: &mut [u8]) -> Result<(), HMACError> {
+    let mut block = [0u8; MAX_HASH_LEN];
+    let mut block_len = 0;
+    let mut counter = 0u8;
+    let mut written = 0;
+    let res = loop {
+        if written == okm.len() {
+            break Ok(());
+        }
+        counter = match counter.checked_add(1) {
+            Some(c) => c,
+            None => break Err(HMACError::InvalidOutputLength),
+        };
+        block_len = match mac::<H>(prk, &[&block[..block_len], info, &[counter]], &mut block) {
+            Ok(len) => len,
+            Err(e) => break Err(e),
+        };
+        let n = block_len.min(okm.len() - written);
+        okm[written..written + n].copy_from_slice(&block[..n]);
+        written += n;
+    };
+    block.zeroize();
+    res.inspect_err(|_| okm.zeroize())
+}
+
+/// HKDF: extracts a pseudorandom key from `salt` and `ikm`, then expands it
+/// into `okm` for the context `info`.
+pub fn hkdf<H: HMACInit>(
+    salt: &[u8],
+    ikm: &[u8],
+    info: &[u8],
+    okm: &mut [u8],
+) -> Result<(), HMACError> {
+    let mut prk = Secret::<MAX_HASH_LEN>::new();
+    let prk_len = hkdf_extract::<H>(salt, ikm, prk.as_mut())?;
+    hkdf_expand::<H>(&prk.as_ref()[..prk_len], info, okm)
+}
+
+/// PBKDF2: fills `output` with a key derived from `password` and `salt`,
+/// using `iterations` rounds of HMAC.
+///
+/// # Errors
+///
+/// Fails with [`HMACError::InvalidParameter`] if `iterations` is zero.
+/// `output` is erased on failure.
+pub fn pbkdf2<H: HMACInit>(
+    password: &[u8],
+    salt: &[u8],
+    iterations: u32,
+    output: &mut [u8],
+) -> Result<(), HMACError> {
+    if iterations == 0 {
+        return Err(HMACError::InvalidParameter);
+    }
+    let mut u = [0u8; MAX_HASH_LEN];
+    let mut prev = [0u8; MAX_HASH_LEN];
+    let mut t = [0u8; MAX_HASH_LEN];
+    let mut index = 0u32;
+    let mut written = 0;
+    let res = 'blocks: loop {
+        if written == output.len() {
+            break Ok(());
+        }
+        index += 1;
+        let mut len = match mac::<H>(password, &[salt, &index.to_be_bytes()], &mut u) {
+            Ok(len) => len,
+            Err(e) => break Err(e),
+        };
+        t[..len].copy_from_slice(&u[..len]);
+        for _ in 1..iterations {
+            prev[..len].copy_from_slice(&u[..len]);
+            len = match mac::<H>(password, &[&prev[..len]], &mut u) {
+                Ok(len) => len,
+                Err(e) => break 'blocks Err(e),
+            };
+            t.iter_mut().zip(u[..len].iter()).for_each(|(t, u)| *t ^= u);
+        }
+        let n = len.min(output.len() - written);
+        output[written..written + n].copy_from_slice(&t[..n]);
+        written += n;
+    };
+    u.zeroize();
+    prev.zeroize();
+    t.zeroize();
+    res.inspect_err(|_| output.zeroize())
+}
+
+/// PBKDF2-HMAC-SHA512, as used to compute BIP39 seeds.
+pub fn pbkdf2_sha512(
+    password: &[u8],
+    salt: &[u8],
+    iterations: u32,
+    output: &mut [u8],
+) -> Result<(), HMACError> {
+    pbkdf2::<Sha2_512>(password, salt, iterations, output)
+}
+
+/// Derives the key of the SLIP-21 node at `label_path` from the seed.
+///
+/// The node of the first label is derived by the OS, and the following ones
+/// are derived from it as specified by SLIP-21. The returned key is the right
+/// half of the node, and is erased from memory when dropped.
+///
+/// # Errors
+///
+/// Fails with [`CxError::InvalidParameter`] if `label_path` is empty, or if
+/// a label is longer than [`MAX_SLIP21_LABEL_LEN`].
+pub fn slip21_derive(label_path: &[&[u8]]) -> Result<Secret<32>, CxError> {
+    let (first, children) = label_path.split_first().ok_or(CxError::InvalidParameter)?;
+    if label_path
+        .iter()
+        .any(|label| label.len() > MAX_SLIP21_LABEL_LEN)
+    {
+        return Err(CxError::InvalidParameter);
+    }
+    // The label is given to the OS in place of the path, prefixed with a null byte
+    #[repr(align(4))]
+    struct Path([u8; MAX_SLIP21_LABEL_LEN + 1]);
+    let mut path = Path([0; MAX_SLIP21_LABEL_LEN + 1]);
+    path.0[1..=first.len()].copy_from_slice(first);
+
+    let mut node = Secret::<64>::new();
+    let err = unsafe {
+        sys_hdkey_derive(
+            HDKeyDeriveMode::Slip21 as u8,
+            CurvesId::Secp256k1 as cx_curve_t,
+            path.0.as_ptr() as *const u32,
+            first.len() + 1,
+            node.as_mut().as_mut_ptr(),
+            64,
+            core::ptr::null_mut(),
+            0,
+            core::ptr::null_mut(),
+            0,
+        )
+    };
+    if err != CX_OK {
+        return Err(err.into());
+    }
+    // child = HMAC-SHA512(parent[0..32], 0x00 || label)
+    for label in children {
+        let mut child = Secret::<64>::new();
+        mac::<Sha2_512>(&node.as_ref()[..32], &[&[0], label], child.as_mut())
+            .map_err(|_| CxError::GenericError)?;
+        node = child;
+    }
+    let mut key = Secret::<32>::new();
+    key.as_mut().copy_from_slice(&node.as_ref()[32..]);
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_eq_err as assert_eq;
+    use crate::hmac::sha2::Sha2_256;
+    use crate::testing::{TestType, hex};
+    use testmacro::test_item as test;
+
+    #[test]
+    fn hkdf_sha256() {
+        // RFC 5869, test case 1
+        let ikm = [0x0b; 22];
+        let salt = hex::<13>("000102030405060708090a0b0c");
+        let info = hex::<10>("f0f1f2f3f4f5f6f7f8f9");
+
+        let mut prk = [0u8; 32];
+        assert_eq!(hkdf_extract::<Sha2_256>(&salt, &ikm, &mut prk), Ok(32));
+        assert_eq!(
+            prk,
+            hex::<32>("077709362c2e32df0ddc3f0dc47bba6390b6c73bb50f9c3122ec844ad7c2b3e5")
+        );
+
+        let expected = hex::<42>(
+            "3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf34007208d5b887185865",
+        );
+        let mut okm = [0u8; 42];
+        assert_eq!(hkdf_expand::<Sha2_256>(&prk, &info, &mut okm), Ok(()));
+        assert_eq!(okm, expected);
+        okm = [0u8; 42];
+        assert_eq!(hkdf::<Sha2_256>(&salt, &ikm, &info, &mut okm), Ok(()));
+        assert_eq!(okm, expected);
+    }
+
+    #[test]
+    fn pbkdf2_hmac_sha512() {
+        let mut output = [0u8; 64];
+        assert_eq!(pbkdf2_sha512(b"password", b"salt", 2, &mut output), Ok(()));
+        assert_eq!(
+            output,
+            hex::<64>(
+                "e1d9c16aa681708a45f5c7c4e215ceb66e011a2e9f0040713f18aefdb866d53cf76cab2868a39b9f7840edce4fef5a82be67335c77a6068e04112754f27ccf4e"
+            )
+        );
+
+        // Output longer than a digest
+        let mut output = [0u8; 80];
+        assert_eq!(pbkdf2_sha512(b"password", b"salt", 1, &mut output), Ok(()));
+        assert_eq!(
+            output,
+            hex::<80>(
+                "867f70cf1ade02cff3752599a3a53dc4af34c7a669815ae5d513554e1c8cf252c02d470a285a0501bad999bfe943c08f050235d7d68b1da55e63f73b60a57fce7b532e206c2967d4c7d2ffa460539fc4"
+            )
+        );
+
+        assert_eq!(
+            pbkdf2_sha512(b"password", b"salt", 0, &mut output),
+            Err(HMACError::InvalidParameter)
+        );
+    }
+
+    #[test]
+    fn slip21() {
+        let key = slip21_derive(&[b"SLIP-0021"]).unwrap();
+        let child = slip21_derive(&[b"SLIP-0021", b"Master encryption key"]).unwrap();
+        let other = slip21_derive(&[b"SLIP-0021", b"Authentication key"]).unwrap();
+        assert_eq!(
+            child.as_ref(),
+            slip21_derive(&[b"SLIP-0021", b"Master encryption key"])
+                .unwrap()
+                .as_ref()
+        );
+        assert_eq!(key.as_ref() != child.as_ref(), true);
+        assert_eq!(child.as_ref() != other.as_ref(), true);
+
+        assert_eq!(slip21_derive(&[]).err(), Some(CxError::InvalidParameter));
+        assert_eq!(
+            slip21_derive(&[b"SLIP-0021", &[0x41; MAX_SLIP21_LABEL_LEN + 1]]).err(),
+            Some(CxError::InvalidParameter)
+        );
+    }
+}
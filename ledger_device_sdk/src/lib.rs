@@ -12,22 +12,26 @@
 
 mod app_info;
 pub mod bn;
+pub mod cipher;
 pub mod ecc;
 pub mod hash;
 pub mod hmac;
 pub(crate) mod io_callbacks;
+pub(crate) mod io_common;
 pub(crate) mod io_legacy;
 #[cfg(feature = "io_new")]
 pub(crate) mod io_new;
 
 // Only re-export the selected module as `io`
 pub mod io {
+    pub use super::io_common::*;
     #[cfg(not(feature = "io_new"))]
     pub use super::io_legacy::*;
     #[cfg(feature = "io_new")]
     pub use super::io_new::*;
 }
 
+pub mod kdf;
 pub mod libcall;
 pub mod log;
 pub mod math;
@@ -36,6 +40,7 @@ pub mod pki;
 pub mod random;
 pub mod screen;
 pub mod seph;
+pub mod settings;
 pub mod testing;
 pub mod tlv;
 
@@ -79,6 +84,8 @@ pub use ledger_secure_sdk_sys::exit_app;
 
 // Re-export include_gif macro
 pub use include_gif::include_gif;
+// Re-export the ApduCommand derive macro
+pub use apdu_command::ApduCommand;
 
 // Re-export the underlying Ledger C SDK bindings when the `sys` feature is enabled.
 // This provides direct (unsafe) FFI access. Prefer the safe abstractions in this crate
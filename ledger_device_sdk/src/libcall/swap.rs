@@ -37,10 +37,9 @@ use ledger_secure_sdk_sys::{
     get_printable_amount_parameters_t, libargs_s__bindgen_ty_1, libargs_t,
 };
 
+use crate::io::{CommError, ResponseWriter};
 #[cfg(feature = "io_new")]
-use crate::io::CommError;
-#[cfg(feature = "io_new")]
-use crate::io::CommandResponse;
+use crate::io::{CommandResponse, Transport};
 
 extern crate alloc;
 
@@ -197,55 +196,60 @@ impl<T: SwapAppErrorCodeTrait> SwapError<T> {
         }
     }
 
-    /// Append this swap error to the communication buffer in the standard format.
+    /// Append this swap error to a response in the standard format.
     ///
     /// Appends the 2-byte error code followed by the optional message string.
     /// This ensures all applications format swap errors consistently.
     ///
     /// # Format
     ///
-    /// The data appended to the communication buffer:
+    /// The data appended to the response:
     /// - Byte 0: Common error code from [`SwapErrorCommonCode`]
     /// - Byte 1: Application-specific error code
     /// - Bytes 2+: Optional UTF-8 encoded error message (if present)
     ///
     /// # Arguments
     ///
-    /// * `comm` - Mutable reference to the communication buffer
+    /// * `writer` - The response being built: a legacy `Comm`, or an io_new `CommandResponse`
     ///
     /// # Returns
     ///
-    /// The 2-byte error code as `[u8; 2]` for logging/debugging purposes.
+    /// The 2-byte error code as `[u8; 2]` for logging/debugging purposes,
+    /// or `CommError::Overflow` if the error does not fit in the response.
     ///
     /// # Example
     ///
     /// ```rust,ignore
     /// if let Err(error) = check_swap_params(params, &tx) {
-    ///     error.append_to_comm(comm);
+    ///     error.append_to(&mut response)?;
     ///     return Err(AppSW::SwapFail);
     /// }
     /// ```
-    #[cfg(not(feature = "io_new"))]
-    pub fn append_to_comm(&self, comm: &mut crate::io::Comm) -> [u8; 2] {
+    pub fn append_to<W: ResponseWriter + ?Sized>(
+        &self,
+        writer: &mut W,
+    ) -> Result<[u8; 2], CommError> {
         let error_bytes = [self.common_code as u8, self.app_code.as_u8()];
-        comm.append(&error_bytes);
+        writer.append_data(&error_bytes)?;
         if let Some(ref msg) = self.message {
-            comm.append(msg.as_bytes());
+            writer.append_data(msg.as_bytes())?;
         }
-        error_bytes
+        Ok(error_bytes)
+    }
+
+    #[cfg(not(feature = "io_new"))]
+    #[deprecated(note = "use `append_to`, which works with both io implementations")]
+    pub fn append_to_comm(&self, comm: &mut crate::io::Comm) -> [u8; 2] {
+        self.append_to(comm).unwrap()
     }
 
     #[cfg(feature = "io_new")]
-    pub fn append_to_response<const N: usize>(
+    #[deprecated(note = "use `append_to`, which works with both io implementations")]
+    pub fn append_to_response<const N: usize, T: Transport>(
         &self,
-        response: &mut CommandResponse<'_, N>,
+        response: &mut CommandResponse<'_, N, T>,
     ) -> Result<[u8; 2], CommError> {
-        let error_bytes = [self.common_code as u8, self.app_code.as_u8()];
-        response.append(&error_bytes)?;
-        if let Some(ref msg) = self.message {
-            response.append(msg.as_bytes())?;
-        }
-        Ok(error_bytes)
+        self.append_to(response)
     }
 }
 //  --8<-- [end:error_code_api]
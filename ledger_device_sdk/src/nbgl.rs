@@ -4,7 +4,7 @@
 //! It includes functions and structures to create and manage UI elements,
 //! handle user interactions, and display information on Ledger devices.
 
-use crate::io::{ApduHeader, Event};
+use crate::io::{ApduHeader, Event, IoComm};
 use crate::io_callbacks::nbgl_next_event_ahead;
 use crate::nvm::*;
 use const_zero::const_zero;
@@ -63,7 +63,6 @@ pub use nbgl_status::*;
 #[doc(inline)]
 pub use nbgl_streaming_review::*;
 
-#[cfg(not(feature = "io_new"))]
 #[derive(Copy, Clone, PartialEq, Eq)]
 pub enum SyncNbgl {
     UxSyncRetApproved = 0x00,
@@ -77,20 +76,6 @@ pub enum SyncNbgl {
     UxSyncRetError = 0xFF,
 }
 
-#[cfg(feature = "io_new")]
-#[derive(Copy, Clone, PartialEq, Eq)]
-pub(crate) enum SyncNbgl {
-    UxSyncRetApproved = 0x00,
-    UxSyncRetRejected = 0x01,
-    UxSyncRetQuitted = 0x02,
-    UxSyncRetApduReceived = 0x03,
-    UxSyncRetSkipped = 0x04,
-    UxSyncRetContinue = 0x05,
-    UxSyncRetPinValidated = 0x06,
-    UxSyncRetPinRejected = 0x07,
-    UxSyncRetError = 0xFF,
-}
-
 impl From<u8> for SyncNbgl {
     fn from(val: u8) -> SyncNbgl {
         match val {
@@ -409,14 +394,6 @@ impl ToMessage for StatusType {
     }
 }
 
-#[cfg(not(feature = "io_new"))]
-/// Initialize the global reference to the Comm instance used by Nbgl.
-/// This function should be called from the main function of the application.
-pub fn init_comm(comm: &mut crate::io::Comm) {
-    comm.nbgl_register_comm();
-}
-
-#[cfg(feature = "io_new")]
 pub use crate::io::init_comm;
 
 #[derive(Copy, Clone)]
@@ -80,8 +80,7 @@ impl<'a> NbglAction<'a> {
     /// # Returns
     /// Returns `Ok(())` when the action button is pressed,
     /// or `Err(u8)` with the error code in case of an error.
-    #[cfg(feature = "io_new")]
-    pub fn show<const N: usize>(self, _comm: &mut crate::io::Comm<N>) -> Result<(), u8> {
+    pub fn show_with_comm<C: IoComm + ?Sized>(self, _comm: &mut C) -> Result<(), u8> {
         let ret = self.show_internal();
         match ret {
             SyncNbgl::UxSyncRetContinue => Ok(()),
@@ -89,8 +88,15 @@ impl<'a> NbglAction<'a> {
         }
     }
 
+    /// Same as `show_with_comm`.
+    #[cfg(feature = "io_new")]
+    pub fn show<C: IoComm + ?Sized>(self, comm: &mut C) -> Result<(), u8> {
+        self.show_with_comm(comm)
+    }
+
     /// Shows the action page.
     #[cfg(not(feature = "io_new"))]
+    #[deprecated(note = "use `show_with_comm`, which works with both io implementations")]
     pub fn show(self) -> SyncNbgl {
         self.show_internal()
     }
@@ -141,17 +141,23 @@ impl<'a> NbglAddressReview<'a> {
     /// * `address` - The address to review.
     /// # Returns
     /// Returns true if the user approved the address, false otherwise.
-    #[cfg(feature = "io_new")]
-    pub fn show<const N: usize>(&self, _comm: &mut crate::io::Comm<N>, address: &str) -> bool {
+    pub fn show_with_comm<C: IoComm + ?Sized>(&self, _comm: &mut C, address: &str) -> bool {
         self.show_internal(address)
     }
 
+    /// Same as `show_with_comm`.
+    #[cfg(feature = "io_new")]
+    pub fn show<C: IoComm + ?Sized>(&self, comm: &mut C, address: &str) -> bool {
+        self.show_with_comm(comm, address)
+    }
+
     /// Shows the address review flow.
     /// # Arguments
     /// * `address` - The address to review.
     /// # Returns
     /// Returns true if the user approved the address, false otherwise.
     #[cfg(not(feature = "io_new"))]
+    #[deprecated(note = "use `show_with_comm`, which works with both io implementations")]
     pub fn show(&self, address: &str) -> bool {
         self.show_internal(address)
     }
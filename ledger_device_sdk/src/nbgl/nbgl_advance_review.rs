@@ -190,10 +190,9 @@ impl<'a> NbglAdvanceReview<'a> {
     /// Returns `Ok(true)` if the user accepts the review,
     /// `Ok(false)` if the user rejects it,
     /// or `Err(u8)` with the error code in case of an error.
-    #[cfg(feature = "io_new")]
-    pub fn show<const N: usize>(
+    pub fn show_with_comm<C: IoComm + ?Sized>(
         &self,
-        _comm: &mut crate::io::Comm<N>,
+        _comm: &mut C,
         fields: &[Field],
     ) -> Result<bool, u8> {
         let ret = self.show_internal(fields);
@@ -204,12 +203,19 @@ impl<'a> NbglAdvanceReview<'a> {
         }
     }
 
+    /// Same as `show_with_comm`.
+    #[cfg(feature = "io_new")]
+    pub fn show<C: IoComm + ?Sized>(&self, comm: &mut C, fields: &[Field]) -> Result<bool, u8> {
+        self.show_with_comm(comm, fields)
+    }
+
     /// Shows the advanced review flow.
     /// # Arguments
     /// * `fields` - A slice of `Field` representing the tag/value pairs to display.
     /// # Returns
     /// Returns a `SyncNbgl` instance to manage the synchronous NBGL flow.
     #[cfg(not(feature = "io_new"))]
+    #[deprecated(note = "use `show_with_comm`, which works with both io implementations")]
     pub fn show(&self, fields: &[Field]) -> SyncNbgl {
         self.show_internal(fields)
     }
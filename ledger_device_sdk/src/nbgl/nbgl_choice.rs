@@ -142,10 +142,9 @@ impl<'a> NbglChoice<'a> {
     /// * `cancel_text` - The text to display on the cancellation button.
     /// # Returns
     /// Returns `true` if the user confirmed the choice, `false` otherwise.
-    #[cfg(feature = "io_new")]
-    pub fn show<const N: usize>(
+    pub fn show_with_comm<C: IoComm + ?Sized>(
         &self,
-        _comm: &mut crate::io::Comm<N>,
+        _comm: &mut C,
         message: &str,
         sub_message: &str,
         confirm_text: &str,
@@ -154,6 +153,19 @@ impl<'a> NbglChoice<'a> {
         self.show_internal(message, sub_message, confirm_text, cancel_text)
     }
 
+    /// Same as `show_with_comm`.
+    #[cfg(feature = "io_new")]
+    pub fn show<C: IoComm + ?Sized>(
+        &self,
+        comm: &mut C,
+        message: &str,
+        sub_message: &str,
+        confirm_text: &str,
+        cancel_text: &str,
+    ) -> bool {
+        self.show_with_comm(comm, message, sub_message, confirm_text, cancel_text)
+    }
+
     /// Shows the choice flow.
     /// # Arguments
     /// * `message` - The main message to display in the center of the page.
@@ -163,6 +175,7 @@ impl<'a> NbglChoice<'a> {
     /// # Returns
     /// Returns `true` if the user confirmed the choice, `false` otherwise.
     #[cfg(not(feature = "io_new"))]
+    #[deprecated(note = "use `show_with_comm`, which works with both io implementations")]
     pub fn show(
         &self,
         message: &str,
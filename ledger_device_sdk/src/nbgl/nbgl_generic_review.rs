@@ -601,15 +601,20 @@ impl NbglGenericReview {
     /// * `_comm` - Mutable reference to Comm.
     /// * `reject_button_str` — Text for the reject/cancel button displayed
     ///   at the end of the review flow (e.g. `"Reject transaction"`).
-    #[cfg(feature = "io_new")]
-    pub fn show<const N: usize>(
+    pub fn show_with_comm<C: IoComm + ?Sized>(
         &self,
-        _comm: &mut crate::io::Comm<N>,
+        _comm: &mut C,
         reject_button_str: &str,
     ) -> bool {
         self.show_internal(reject_button_str)
     }
 
+    /// Same as `show_with_comm`.
+    #[cfg(feature = "io_new")]
+    pub fn show<C: IoComm + ?Sized>(&self, comm: &mut C, reject_button_str: &str) -> bool {
+        self.show_with_comm(comm, reject_button_str)
+    }
+
     /// Displays the review to the user and blocks until a decision is made.
     ///
     /// A reject button labelled with `reject_button_str` is shown on the
@@ -621,6 +626,7 @@ impl NbglGenericReview {
     /// * `reject_button_str` — Text for the reject/cancel button displayed
     ///   at the end of the review flow (e.g. `"Reject transaction"`).
     #[cfg(not(feature = "io_new"))]
+    #[deprecated(note = "use `show_with_comm`, which works with both io implementations")]
     pub fn show(&self, reject_button_str: &str) -> bool {
         self.show_internal(reject_button_str)
     }
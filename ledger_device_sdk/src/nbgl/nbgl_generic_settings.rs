@@ -171,20 +171,26 @@ impl NbglGenericSettings {
         self.ux_sync_wait(false)
     }
 
-    #[cfg(not(feature = "io_new"))]
-    pub fn show(&mut self) -> SyncNbgl {
-        self.show_internal()
-    }
-
     /// # Returns
     /// Returns `Ok(())` once the user exits the settings screen,
     /// or `Err(u8)` with the error code in case of an error.
-    #[cfg(feature = "io_new")]
-    pub fn show<const N: usize>(&mut self, _comm: &mut crate::io::Comm<N>) -> Result<(), u8> {
+    pub fn show_with_comm<C: IoComm + ?Sized>(&mut self, _comm: &mut C) -> Result<(), u8> {
         let ret = self.show_internal();
         match ret {
             SyncNbgl::UxSyncRetQuitted => Ok(()),
             _ => Err(u8::from(ret)),
         }
     }
+
+    /// Same as `show_with_comm`.
+    #[cfg(feature = "io_new")]
+    pub fn show<C: IoComm + ?Sized>(&mut self, comm: &mut C) -> Result<(), u8> {
+        self.show_with_comm(comm)
+    }
+
+    #[cfg(not(feature = "io_new"))]
+    #[deprecated(note = "use `show_with_comm`, which works with both io implementations")]
+    pub fn show(&mut self) -> SyncNbgl {
+        self.show_internal()
+    }
 }
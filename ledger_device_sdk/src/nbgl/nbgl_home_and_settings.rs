@@ -6,6 +6,7 @@
 use super::*;
 use crate::io::{Reply, StatusWords};
 use crate::io_callbacks::{nbgl_fetch_apdu_header, nbgl_reply_status};
+use crate::settings::{SETTING_LABEL_LEN, Setting, SettingKind, SettingValues, SettingsStorage};
 
 pub const SETTINGS_SIZE: usize = 10;
 static mut NVM_REF: Option<&mut AtomicStorage<[u8; SETTINGS_SIZE]>> = None;
@@ -40,6 +41,235 @@ unsafe extern "C" fn settings_callback(token: c_int, _index: u8, _page: c_int) {
     }
 }
 
+/// Typed settings set with [`NbglHomeAndSettings::typed_settings`], and the
+/// NBGL contents displaying them.
+struct TypedSettings {
+    settings: &'static [Setting],
+    values: &'static mut dyn SettingValues,
+    texts: Vec<[CString; 2]>,
+    /// Labels of the values of choices and ranges.
+    labels: Vec<CString>,
+    label_ptrs: Vec<*const c_char>,
+    switches: Vec<nbgl_contentSwitch_t>,
+    /// Names and tokens of the bars leading to choices and ranges.
+    bar_texts: Vec<*const c_char>,
+    bar_tokens: Vec<u8>,
+    /// Pages of the choices and ranges, opened from their bar.
+    choices: Vec<nbgl_content_t>,
+    choice_page: nbgl_genericContents_t,
+    contents: Vec<nbgl_content_t>,
+    /// Home and settings screen to go back to from a choice page, and its
+    /// quit callback.
+    home: *mut NbglHomeAndSettings,
+    quit: unsafe extern "C" fn(),
+}
+
+static mut TYPED_SETTINGS: Option<TypedSettings> = None;
+
+impl TypedSettings {
+    /// Builds the contents from the current values: one page with a bar per
+    /// choice or range, leading to the list of its values, then one page
+    /// with all the switches.
+    fn build(&mut self) {
+        self.texts = self
+            .settings
+            .iter()
+            .map(|s| {
+                [
+                    CString::new(s.name).unwrap(),
+                    CString::new(s.description).unwrap(),
+                ]
+            })
+            .collect();
+
+        self.labels.clear();
+        for setting in self.settings.iter() {
+            if !matches!(setting.kind, SettingKind::Switch { .. }) {
+                for position in 0..setting.value_count() {
+                    let mut buf = [0u8; SETTING_LABEL_LEN];
+                    let label = setting.label(setting.value_at(position), &mut buf);
+                    self.labels.push(CString::new(label).unwrap());
+                }
+            }
+        }
+        self.label_ptrs = self.labels.iter().map(|l| l.as_ptr()).collect();
+
+        self.switches.clear();
+        self.bar_texts.clear();
+        self.bar_tokens.clear();
+        self.choices.clear();
+        self.contents.clear();
+        let mut first_label = 0;
+        for (i, setting) in self.settings.iter().enumerate() {
+            let token = (FIRST_USER_TOKEN + i as u32) as u8;
+            let value = self.values.value(i);
+            if let SettingKind::Switch { .. } = setting.kind {
+                #[allow(unused_mut)]
+                let mut switch = nbgl_contentSwitch_t {
+                    text: self.texts[i][0].as_ptr(),
+                    subText: self.texts[i][1].as_ptr(),
+                    initState: if value != 0 { ON_STATE } else { OFF_STATE },
+                    token,
+                    ..Default::default()
+                };
+                #[cfg(any(target_os = "stax", target_os = "flex", target_os = "apex_p"))]
+                {
+                    switch.tuneId = TuneIndex::TapCasual as u8;
+                }
+                self.switches.push(switch);
+            } else {
+                let count = setting.value_count();
+                self.bar_texts.push(self.texts[i][0].as_ptr());
+                self.bar_tokens.push(token);
+                self.choices.push(nbgl_content_t {
+                    content: nbgl_content_u {
+                        choicesList: nbgl_contentRadioChoice_t {
+                            __bindgen_anon_1: nbgl_contentRadioChoice_t__bindgen_ty_1 {
+                                names: self.label_ptrs[first_label..].as_ptr(),
+                            },
+                            token,
+                            nbChoices: count as u8,
+                            initChoice: setting.position_of(value).unwrap_or(0) as u8,
+                            ..Default::default()
+                        },
+                    },
+                    contentActionCallback: Some(typed_settings_callback),
+                    type_: CHOICES_LIST,
+                });
+                first_label += count;
+            }
+        }
+        if !self.bar_texts.is_empty() {
+            self.contents.push(nbgl_content_t {
+                content: nbgl_content_u {
+                    barsList: nbgl_contentBarsList_t {
+                        barTexts: self.bar_texts.as_ptr(),
+                        tokens: self.bar_tokens.as_ptr(),
+                        nbBars: self.bar_texts.len() as u8,
+                        ..Default::default()
+                    },
+                },
+                contentActionCallback: Some(typed_choice_open_callback),
+                type_: BARS_LIST,
+            });
+        }
+        if !self.switches.is_empty() {
+            self.contents.push(nbgl_content_t {
+                content: nbgl_content_u {
+                    switchesList: nbgl_pageSwitchesList_s {
+                        switches: self.switches.as_ptr(),
+                        nbSwitches: self.switches.len() as u8,
+                    },
+                },
+                contentActionCallback: Some(typed_settings_callback),
+                type_: SWITCHES_LIST,
+            });
+        }
+    }
+}
+
+/// Returns the index of the typed setting of `token`.
+fn typed_setting_index(typed: &TypedSettings, token: c_int) -> usize {
+    let idx = token - FIRST_USER_TOKEN as i32;
+    if idx < 0 || idx >= typed.settings.len() as i32 {
+        panic!("Invalid token.");
+    }
+    idx as usize
+}
+
+/// Callback triggered by the NBGL API when a typed setting is changed.
+unsafe extern "C" fn typed_settings_callback(token: c_int, index: u8, _page: c_int) {
+    unsafe {
+        let Some(typed) = (*(&raw mut TYPED_SETTINGS)).as_mut() else {
+            return;
+        };
+        let setting_idx = typed_setting_index(typed, token);
+
+        match typed.settings[setting_idx].kind {
+            SettingKind::Switch { .. } => {
+                let value = (typed.values.value(setting_idx) == 0) as u16;
+                if typed
+                    .values
+                    .set_value(typed.settings, setting_idx, value)
+                    .is_err()
+                {
+                    return;
+                }
+                if let Some(switch) = typed
+                    .switches
+                    .iter_mut()
+                    .find(|s| s.token as c_int == token)
+                {
+                    switch.initState = if value != 0 { ON_STATE } else { OFF_STATE };
+                }
+            }
+            _ => {
+                let value = typed.settings[setting_idx].value_at(index as usize);
+                if typed
+                    .values
+                    .set_value(typed.settings, setting_idx, value)
+                    .is_err()
+                {
+                    return;
+                }
+                if let Some(content) = typed
+                    .choices
+                    .iter_mut()
+                    .find(|c| c.content.choicesList.token as c_int == token)
+                {
+                    content.content.choicesList.initChoice = index;
+                }
+            }
+        }
+    }
+}
+
+/// Callback triggered by the NBGL API when the bar of a choice or range is
+/// tapped: shows the list of its values, titled with its name.
+unsafe extern "C" fn typed_choice_open_callback(token: c_int, _index: u8, _page: c_int) {
+    unsafe {
+        let Some(typed) = (*(&raw mut TYPED_SETTINGS)).as_mut() else {
+            return;
+        };
+        let setting_idx = typed_setting_index(typed, token);
+        let Some(content) = typed
+            .choices
+            .iter()
+            .find(|c| c.content.choicesList.token as c_int == token)
+        else {
+            return;
+        };
+        typed.choice_page = nbgl_genericContents_t {
+            callbackCallNeeded: false,
+            __bindgen_anon_1: nbgl_genericContents_t__bindgen_ty_1 {
+                contentsList: content as *const nbgl_content_t,
+            },
+            nbContents: 1,
+        };
+        nbgl_useCaseGenericSettings(
+            typed.texts[setting_idx][0].as_ptr(),
+            0,
+            &typed.choice_page as *const nbgl_genericContents_t,
+            core::ptr::null(),
+            Some(typed_choice_back_callback),
+        );
+    }
+}
+
+/// Callback triggered by the NBGL API when leaving the page of a choice or
+/// range: shows the settings again, on the page of the bars.
+unsafe extern "C" fn typed_choice_back_callback() {
+    unsafe {
+        let Some(typed) = (*(&raw mut TYPED_SETTINGS)).as_mut() else {
+            return;
+        };
+        let (home, quit) = (typed.home, typed.quit);
+        if !home.is_null() {
+            (*home).display(0, quit);
+        }
+    }
+}
+
 /// Informations fields name to display in the dedicated
 /// page of the home screen.
 const INFO_FIELDS: [*const c_char; 2] = [
@@ -163,6 +393,7 @@ impl<'a> NbglHomeAndSettings {
     ) -> NbglHomeAndSettings {
         unsafe {
             NVM_REF = Some(transmute(nvm_data));
+            TYPED_SETTINGS = None;
         }
 
         if settings_strings.len() > SETTINGS_SIZE {
@@ -181,6 +412,45 @@ impl<'a> NbglHomeAndSettings {
         }
     }
 
+    /// Sets typed settings to display in the settings page.
+    ///
+    /// Choices and ranges are listed on the first page, each leading to the
+    /// list of its values, and switches are displayed on the next one.
+    /// Changes are written to `storage`.
+    /// # Arguments
+    /// * `settings` - The declaration of the settings.
+    /// * `storage` - A mutable reference to the storage of their values.
+    /// # Returns
+    /// Returns the builder itself to allow method chaining.
+    pub fn typed_settings<const N: usize>(
+        self,
+        settings: &'static [Setting; N],
+        storage: &'static mut SettingsStorage<N>,
+    ) -> NbglHomeAndSettings {
+        unsafe {
+            TYPED_SETTINGS = Some(TypedSettings {
+                settings,
+                values: storage,
+                texts: Vec::new(),
+                labels: Vec::new(),
+                label_ptrs: Vec::new(),
+                switches: Vec::new(),
+                bar_texts: Vec::new(),
+                bar_tokens: Vec::new(),
+                choices: Vec::new(),
+                choice_page: nbgl_genericContents_t::default(),
+                contents: Vec::new(),
+                home: core::ptr::null_mut(),
+                quit: quit_cb,
+            });
+        }
+
+        NbglHomeAndSettings {
+            nb_settings: N as u8,
+            ..self
+        }
+    }
+
     /// Sets the initial page to display when showing the home and settings screen.
     /// # Arguments
     /// * `page` - The initial page to display.
@@ -190,83 +460,120 @@ impl<'a> NbglHomeAndSettings {
         self.start_page = page;
     }
 
-    /// Show the home screen and settings page (internal implementation).
-    fn show_internal<T: TryFrom<ApduHeader>>(&mut self) -> Event<T>
-    where
-        Reply: From<<T as TryFrom<ApduHeader>>::Error>,
-    {
+    /// Fills the settings contents, from the typed settings if any, otherwise
+    /// from the switches set with `settings`.
+    unsafe fn prepare_settings(&mut self) {
         unsafe {
-            loop {
-                self.info_contents_ptr = self
-                    .info_contents
-                    .iter()
-                    .map(|s| s.as_ptr())
-                    .collect::<Vec<_>>();
-
-                self.info_list = nbgl_contentInfoList_t {
-                    infoTypes: INFO_FIELDS.as_ptr(),
-                    infoContents: self.info_contents_ptr[..].as_ptr(),
-                    nbInfos: INFO_FIELDS.len() as u8,
-                    infoExtensions: core::ptr::null(),
-                    token: 0,
-                    withExtensions: false,
+            if let Some(typed) = (*(&raw mut TYPED_SETTINGS)).as_mut() {
+                typed.build();
+                self.generic_contents = nbgl_genericContents_t {
+                    callbackCallNeeded: false,
+                    __bindgen_anon_1: nbgl_genericContents_t__bindgen_ty_1 {
+                        contentsList: typed.contents.as_ptr(),
+                    },
+                    nbContents: typed.contents.len() as u8,
                 };
+                return;
+            }
 
-                for (i, setting) in self.setting_contents.iter().enumerate() {
-                    SWITCH_ARRAY[i].text = setting[0].as_ptr();
-                    SWITCH_ARRAY[i].subText = setting[1].as_ptr();
-                    let state = if let Some(data) = (*(&raw mut NVM_REF)).as_mut() {
-                        data.get_ref()[i]
-                    } else {
-                        OFF_STATE
-                    };
-                    SWITCH_ARRAY[i].initState = state;
-                    SWITCH_ARRAY[i].token = (FIRST_USER_TOKEN + i as u32) as u8;
-                    #[cfg(any(target_os = "stax", target_os = "flex", target_os = "apex_p"))]
-                    {
-                        SWITCH_ARRAY[i].tuneId = TuneIndex::TapCasual as u8;
-                    }
+            for (i, setting) in self.setting_contents.iter().enumerate() {
+                SWITCH_ARRAY[i].text = setting[0].as_ptr();
+                SWITCH_ARRAY[i].subText = setting[1].as_ptr();
+                let state = if let Some(data) = (*(&raw mut NVM_REF)).as_mut() {
+                    data.get_ref()[i]
+                } else {
+                    OFF_STATE
+                };
+                SWITCH_ARRAY[i].initState = state;
+                SWITCH_ARRAY[i].token = (FIRST_USER_TOKEN + i as u32) as u8;
+                #[cfg(any(target_os = "stax", target_os = "flex", target_os = "apex_p"))]
+                {
+                    SWITCH_ARRAY[i].tuneId = TuneIndex::TapCasual as u8;
                 }
+            }
 
-                self.content = nbgl_content_t {
-                    content: nbgl_content_u {
-                        switchesList: nbgl_pageSwitchesList_s {
-                            switches: &raw const SWITCH_ARRAY as *const nbgl_contentSwitch_t,
-                            nbSwitches: self.nb_settings,
-                        },
+            self.content = nbgl_content_t {
+                content: nbgl_content_u {
+                    switchesList: nbgl_pageSwitchesList_s {
+                        switches: &raw const SWITCH_ARRAY as *const nbgl_contentSwitch_t,
+                        nbSwitches: self.nb_settings,
                     },
-                    contentActionCallback: Some(settings_callback),
-                    type_: SWITCHES_LIST,
-                };
+                },
+                contentActionCallback: Some(settings_callback),
+                type_: SWITCHES_LIST,
+            };
 
-                self.generic_contents = nbgl_genericContents_t {
-                    callbackCallNeeded: false,
-                    __bindgen_anon_1: nbgl_genericContents_t__bindgen_ty_1 {
-                        contentsList: &self.content as *const nbgl_content_t,
-                    },
-                    nbContents: 1,
-                };
+            self.generic_contents = nbgl_genericContents_t {
+                callbackCallNeeded: false,
+                __bindgen_anon_1: nbgl_genericContents_t__bindgen_ty_1 {
+                    contentsList: &self.content as *const nbgl_content_t,
+                },
+                nbContents: 1,
+            };
+        }
+    }
+
+    /// Displays the home screen, or the settings page `page` if it
+    /// is not [`INIT_HOME_PAGE`], with `quit` as quit callback.
+    unsafe fn display(&mut self, page: u8, quit: unsafe extern "C" fn()) {
+        unsafe {
+            self.info_contents_ptr = self
+                .info_contents
+                .iter()
+                .map(|s| s.as_ptr())
+                .collect::<Vec<_>>();
 
+            self.info_list = nbgl_contentInfoList_t {
+                infoTypes: INFO_FIELDS.as_ptr(),
+                infoContents: self.info_contents_ptr[..].as_ptr(),
+                nbInfos: INFO_FIELDS.len() as u8,
+                infoExtensions: core::ptr::null(),
+                token: 0,
+                withExtensions: false,
+            };
+
+            self.prepare_settings();
+            if let Some(typed) = (*(&raw mut TYPED_SETTINGS)).as_mut() {
+                typed.home = self as *mut NbglHomeAndSettings;
+                typed.quit = quit;
+            }
+
+            nbgl_useCaseHomeAndSettings(
+                self.app_name.as_ptr() as *const c_char,
+                &self.icon as *const nbgl_icon_details_t,
+                match self.tag_line {
+                    None => core::ptr::null(),
+                    Some(ref tag) => tag.as_ptr() as *const c_char,
+                },
+                page,
+                match self.nb_settings {
+                    0 => core::ptr::null(),
+                    _ => &self.generic_contents as *const nbgl_genericContents_t,
+                },
+                &self.info_list as *const nbgl_contentInfoList_t,
+                core::ptr::null(),
+                Some(quit),
+            );
+        }
+    }
+
+    /// Returns the page to display first.
+    fn start_page_index(&self) -> u8 {
+        match self.start_page {
+            PageIndex::Home => INIT_HOME_PAGE as u8,
+            PageIndex::Settings(idx) => idx,
+        }
+    }
+
+    /// Show the home screen and settings page (internal implementation).
+    fn show_internal<T: TryFrom<ApduHeader>>(&mut self) -> Event<T>
+    where
+        Reply: From<<T as TryFrom<ApduHeader>>::Error>,
+    {
+        unsafe {
+            loop {
                 self.ux_sync_init();
-                nbgl_useCaseHomeAndSettings(
-                    self.app_name.as_ptr() as *const c_char,
-                    &self.icon as *const nbgl_icon_details_t,
-                    match self.tag_line {
-                        None => core::ptr::null(),
-                        Some(ref tag) => tag.as_ptr() as *const c_char,
-                    },
-                    match self.start_page {
-                        PageIndex::Home => INIT_HOME_PAGE as u8,
-                        PageIndex::Settings(idx) => idx,
-                    },
-                    match self.nb_settings {
-                        0 => core::ptr::null(),
-                        _ => &self.generic_contents as *const nbgl_genericContents_t,
-                    },
-                    &self.info_list as *const nbgl_contentInfoList_t,
-                    core::ptr::null(),
-                    Some(quit_callback),
-                );
+                self.display(self.start_page_index(), quit_callback);
                 match self.ux_sync_wait(true) {
                     SyncNbgl::UxSyncRetApduReceived => {
                         if let Some(hdr) = nbgl_fetch_apdu_header() {
@@ -300,10 +607,9 @@ impl<'a> NbglHomeAndSettings {
     /// Use `show_and_return` instead.
     /// # Arguments
     /// * `_comm` - Mutable reference to Comm.
-    #[cfg(feature = "io_new")]
-    pub fn show<T: TryFrom<ApduHeader>, const N: usize>(
+    pub fn show_with_comm<T: TryFrom<ApduHeader>, C: IoComm + ?Sized>(
         &mut self,
-        _comm: &mut crate::io::Comm<N>,
+        _comm: &mut C,
     ) -> Event<T>
     where
         Reply: From<<T as TryFrom<ApduHeader>>::Error>,
@@ -311,11 +617,21 @@ impl<'a> NbglHomeAndSettings {
         self.show_internal()
     }
 
+    /// Same as `show_with_comm`.
+    #[cfg(feature = "io_new")]
+    pub fn show<T: TryFrom<ApduHeader>, C: IoComm + ?Sized>(&mut self, comm: &mut C) -> Event<T>
+    where
+        Reply: From<<T as TryFrom<ApduHeader>>::Error>,
+    {
+        self.show_with_comm::<T, C>(comm)
+    }
+
     /// Show the home screen and settings page.
     /// This function will block until an APDU is received or the user quits the app.
     /// DEPRECATED as it constraints to refresh screen for every received APDU.
     /// Use `show_and_return` instead.
     #[cfg(not(feature = "io_new"))]
+    #[deprecated(note = "use `show_with_comm`, which works with both io implementations")]
     pub fn show<T: TryFrom<ApduHeader>>(&mut self) -> Event<T>
     where
         Reply: From<<T as TryFrom<ApduHeader>>::Error>,
@@ -327,75 +643,7 @@ impl<'a> NbglHomeAndSettings {
     /// This function returns immediately after the screen is displayed.
     pub fn show_and_return(&mut self) {
         unsafe {
-            self.info_contents_ptr = self
-                .info_contents
-                .iter()
-                .map(|s| s.as_ptr())
-                .collect::<Vec<_>>();
-
-            self.info_list = nbgl_contentInfoList_t {
-                infoTypes: INFO_FIELDS.as_ptr(),
-                infoContents: self.info_contents_ptr[..].as_ptr(),
-                nbInfos: INFO_FIELDS.len() as u8,
-                infoExtensions: core::ptr::null(),
-                token: 0,
-                withExtensions: false,
-            };
-
-            for (i, setting) in self.setting_contents.iter().enumerate() {
-                SWITCH_ARRAY[i].text = setting[0].as_ptr();
-                SWITCH_ARRAY[i].subText = setting[1].as_ptr();
-                let state = if let Some(data) = (*(&raw mut NVM_REF)).as_mut() {
-                    data.get_ref()[i]
-                } else {
-                    OFF_STATE
-                };
-                SWITCH_ARRAY[i].initState = state;
-                SWITCH_ARRAY[i].token = (FIRST_USER_TOKEN + i as u32) as u8;
-                #[cfg(any(target_os = "stax", target_os = "flex", target_os = "apex_p"))]
-                {
-                    SWITCH_ARRAY[i].tuneId = TuneIndex::TapCasual as u8;
-                }
-            }
-
-            self.content = nbgl_content_t {
-                content: nbgl_content_u {
-                    switchesList: nbgl_pageSwitchesList_s {
-                        switches: &raw const SWITCH_ARRAY as *const nbgl_contentSwitch_t,
-                        nbSwitches: self.nb_settings,
-                    },
-                },
-                contentActionCallback: Some(settings_callback),
-                type_: SWITCHES_LIST,
-            };
-
-            self.generic_contents = nbgl_genericContents_t {
-                callbackCallNeeded: false,
-                __bindgen_anon_1: nbgl_genericContents_t__bindgen_ty_1 {
-                    contentsList: &self.content as *const nbgl_content_t,
-                },
-                nbContents: 1,
-            };
-
-            nbgl_useCaseHomeAndSettings(
-                self.app_name.as_ptr() as *const c_char,
-                &self.icon as *const nbgl_icon_details_t,
-                match self.tag_line {
-                    None => core::ptr::null(),
-                    Some(ref tag) => tag.as_ptr() as *const c_char,
-                },
-                match self.start_page {
-                    PageIndex::Home => INIT_HOME_PAGE as u8,
-                    PageIndex::Settings(idx) => idx,
-                },
-                match self.nb_settings {
-                    0 => core::ptr::null(),
-                    _ => &self.generic_contents as *const nbgl_genericContents_t,
-                },
-                &self.info_list as *const nbgl_contentInfoList_t,
-                core::ptr::null(),
-                Some(quit_cb),
-            );
+            self.display(self.start_page_index(), quit_cb);
         }
     }
 }
@@ -134,11 +134,16 @@ impl NbglKeypad {
     /// # Returns
     /// Returns `true` if the entered PIN matches the expected PIN,
     /// otherwise returns `false`.
-    #[cfg(feature = "io_new")]
-    pub fn ask<const N: usize>(self, _comm: &mut crate::io::Comm<N>, pin: &[u8]) -> bool {
+    pub fn ask_with_comm<C: IoComm + ?Sized>(self, _comm: &mut C, pin: &[u8]) -> bool {
         self.ask_internal(pin) == SyncNbgl::UxSyncRetPinValidated
     }
 
+    /// Same as `ask_with_comm`.
+    #[cfg(feature = "io_new")]
+    pub fn ask<C: IoComm + ?Sized>(self, comm: &mut C, pin: &[u8]) -> bool {
+        self.ask_with_comm(comm, pin)
+    }
+
     /// Shows the keypad and waits for user input.
     /// # Arguments
     /// * `pin` - A slice containing the expected PIN for validation.
@@ -146,6 +151,7 @@ impl NbglKeypad {
     /// Returns `SyncNbgl::UxSyncRetPinValidated` if the entered PIN matches the expected PIN,
     /// otherwise returns `SyncNbgl::UxSyncRetPinRejected`.
     #[cfg(not(feature = "io_new"))]
+    #[deprecated(note = "use `ask_with_comm`, which works with both io implementations")]
     pub fn ask(self, pin: &[u8]) -> SyncNbgl {
         self.ask_internal(pin)
     }
@@ -83,12 +83,18 @@ impl NbglNavigableContent {
         }
     }
 
-    #[cfg(feature = "io_new")]
-    pub fn show<const N: usize>(&self, _comm: &mut crate::io::Comm<N>) {
+    pub fn show_with_comm<C: IoComm + ?Sized>(&self, _comm: &mut C) {
         self.show_internal()
     }
 
+    /// Same as `show_with_comm`.
+    #[cfg(feature = "io_new")]
+    pub fn show<C: IoComm + ?Sized>(&self, comm: &mut C) {
+        self.show_with_comm(comm)
+    }
+
     #[cfg(not(feature = "io_new"))]
+    #[deprecated(note = "use `show_with_comm`, which works with both io implementations")]
     pub fn show(&self) {
         self.show_internal()
     }
@@ -213,17 +213,23 @@ impl<'a> NbglReview<'a> {
     /// * `fields` - A slice of `Field` representing the tag/value pairs to display.
     /// # Returns
     /// Returns `true` if the user approved the transaction, `false` otherwise.
-    #[cfg(feature = "io_new")]
-    pub fn show<const N: usize>(&self, _comm: &mut crate::io::Comm<N>, fields: &[Field]) -> bool {
+    pub fn show_with_comm<C: IoComm + ?Sized>(&self, _comm: &mut C, fields: &[Field]) -> bool {
         self.show_internal(fields)
     }
 
+    /// Same as `show_with_comm`.
+    #[cfg(feature = "io_new")]
+    pub fn show<C: IoComm + ?Sized>(&self, comm: &mut C, fields: &[Field]) -> bool {
+        self.show_with_comm(comm, fields)
+    }
+
     /// Shows the review flow with the provided fields on the review pages.
     /// # Arguments
     /// * `fields` - A slice of `Field` representing the tag/value pairs to display.
     /// # Returns
     /// Returns `true` if the user approved the transaction, `false` otherwise.
     #[cfg(not(feature = "io_new"))]
+    #[deprecated(note = "use `show_with_comm`, which works with both io implementations")]
     pub fn show(&self, fields: &[Field]) -> bool {
         self.show_internal(fields)
     }
@@ -108,8 +108,7 @@ impl<'a> NbglReviewExtended<'a> {
     /// Returns `Ok(true)` if the user accepts the review,
     /// `Ok(false)` if the user rejects it,
     /// or `Err(u8)` with the error code in case of an error.
-    #[cfg(feature = "io_new")]
-    pub fn start<const N: usize>(&self, _comm: &mut crate::io::Comm<N>) -> Result<bool, u8> {
+    pub fn start_with_comm<C: IoComm + ?Sized>(&self, _comm: &mut C) -> Result<bool, u8> {
         let ret = self.start_internal();
         match ret {
             SyncNbgl::UxSyncRetContinue => Ok(true),
@@ -118,12 +117,19 @@ impl<'a> NbglReviewExtended<'a> {
         }
     }
 
+    /// Same as `start_with_comm`.
+    #[cfg(feature = "io_new")]
+    pub fn start<C: IoComm + ?Sized>(&self, comm: &mut C) -> Result<bool, u8> {
+        self.start_with_comm(comm)
+    }
+
     /// Starts the review flow by displaying the first page.
     /// # Returns
     /// Returns `SyncNbgl::UxSyncRetContinue` if the user accepts the review,
     /// `SyncNbgl::UxSyncRetRejected` if the user rejects it,
     /// or another `SyncNbgl` variant in case of an error.
     #[cfg(not(feature = "io_new"))]
+    #[deprecated(note = "use `start_with_comm`, which works with both io implementations")]
     pub fn start(&self) -> SyncNbgl {
         self.start_internal()
     }
@@ -184,10 +190,9 @@ impl<'a> NbglReviewExtended<'a> {
     /// Returns `Ok(true)` if the user accepts the review,
     /// `Ok(false)` if the user rejects it,
     /// or `Err(u8)` with the error code in case of an error.
-    #[cfg(feature = "io_new")]
-    pub fn show<const N: usize>(
+    pub fn show_with_comm<C: IoComm + ?Sized>(
         &self,
-        _comm: &mut crate::io::Comm<N>,
+        _comm: &mut C,
         fields: &[Field],
     ) -> Result<bool, u8> {
         let ret = self.show_internal(fields);
@@ -198,6 +203,12 @@ impl<'a> NbglReviewExtended<'a> {
         }
     }
 
+    /// Same as `show_with_comm`.
+    #[cfg(feature = "io_new")]
+    pub fn show<C: IoComm + ?Sized>(&self, comm: &mut C, fields: &[Field]) -> Result<bool, u8> {
+        self.show_with_comm(comm, fields)
+    }
+
     /// Shows the extended review flow with the provided fields on the review pages.
     /// # Arguments
     /// * `fields` - A slice of `Field` representing the tag/value pairs to display.
@@ -206,6 +217,7 @@ impl<'a> NbglReviewExtended<'a> {
     /// `SyncNbgl::UxSyncRetRejected` if the user rejects it,
     /// or another `SyncNbgl` variant in case of an error.
     #[cfg(not(feature = "io_new"))]
+    #[deprecated(note = "use `show_with_comm`, which works with both io implementations")]
     pub fn show(&self, fields: &[Field]) -> SyncNbgl {
         self.show_internal(fields)
     }
@@ -51,11 +51,16 @@ impl NbglStatus {
     /// # Returns
     /// This function does not return any value.
     /// The status page is displayed for 3 seconds before automatically disappearing.
-    #[cfg(feature = "io_new")]
-    pub fn show<const N: usize>(&self, _comm: &mut crate::io::Comm<N>, success: bool) {
+    pub fn show_with_comm<C: IoComm + ?Sized>(&self, _comm: &mut C, success: bool) {
         self.show_internal(success)
     }
 
+    /// Same as `show_with_comm`.
+    #[cfg(feature = "io_new")]
+    pub fn show<C: IoComm + ?Sized>(&self, comm: &mut C, success: bool) {
+        self.show_with_comm(comm, success)
+    }
+
     /// Shows the status page with the provided text.
     /// # Arguments
     /// * `success` - If `true`, shows a success status; otherwise, shows a failure status.
@@ -63,6 +68,7 @@ impl NbglStatus {
     /// This function does not return any value.
     /// The status page is displayed for 3 seconds before automatically disappearing.
     #[cfg(not(feature = "io_new"))]
+    #[deprecated(note = "use `show_with_comm`, which works with both io implementations")]
     pub fn show(&self, success: bool) {
         self.show_internal(success)
     }
@@ -39,17 +39,128 @@
 //! counter.update(&(*counter.get_ref() - 1));
 //! println!("counter value is {}", *counter.get_ref());
 //! ```
+//!
+//! `get_ref` panics if the stored object cannot be read. This should not happen
+//! with atomic storages, but an application can call
+//! [`repair`](SingleStorage::repair) at boot to restore a consistent state in
+//! any case, and find out whether the value has been lost:
+//!
+//! ```
+//! if counter.repair(&3) == RepairStatus::Reset {
+//!     // The counter has been reset to its default value
+//! }
+//! ```
+//!
+//! # NVM usage
+//!
+//! Storages take more space than the stored value: each `AlignedStorage` is
+//! rounded up to 64 bytes, and an `AtomicStorage` keeps two copies of the value
+//! and is rounded up to a page ([`ATOMIC_STORAGE_ALIGN`]). The space used by
+//! each storage type is given by its `NVM_SIZE` constant, e.g.
+//! `AtomicStorage::<[u8; 100]>::NVM_SIZE`.
+//!
+//! The build script of the SDK also reports the size of the `.nvm_data`
+//! statics of the application, when their type is made of the storage types
+//! of this module and of primitive types. The statics of the dependencies of
+//! the application are not counted. Only the total and the statics of unknown
+//! size are displayed, unless the `NVM_DATA_REPORT` environment variable is
+//! set. As Cargo hides the warnings of dependencies fetched from a registry,
+//! the full list is also written to `nvm_usage.txt`, next to the application
+//! binary. The build fails if the `.nvm_data` section exceeds the limit set
+//! with the `NVM_DATA_LIMIT` environment variable, either as a number of bytes
+//! or as a list of `target:bytes` pairs (e.g. `"nanox: 4096, stax: 8192"`),
+//! or if the limit is set and the size of some statics is unknown.
 
 use AtomicStorageElem::{StorageA, StorageB};
+#[cfg(not(any(test, feature = "nvm_sim")))]
 use ledger_secure_sdk_sys::nvm_write;
+#[cfg(any(test, feature = "nvm_sim"))]
+use sim::nvm_write;
+
+mod kv;
+mod secure;
+#[cfg(any(test, feature = "nvm_sim"))]
+pub mod sim;
+pub use kv::{KV_BLOCK_SIZE, KV_MAX_KEY_LEN, Key, KvError, KvIterator, KvStore};
+pub use secure::{MAX_STORAGE_LABEL_LEN, Sealed, SecureStorage, SecureStorageError, StorageKey};
 
 // Warning: currently alignment is fixed by magic values everywhere, since
 // rust does not allow using a constant in repr(align(...))
 // This code will work correctly only for the currently set page size of 64.
 
+/// Alignment of [`AlignedStorage`], and so the granularity of the space used
+/// in NVM by all the storage types.
+pub const ALIGNED_STORAGE_ALIGN: usize = 64;
+
+/// Alignment of [`AtomicStorage`].
+#[cfg(target_os = "nanox")]
+pub const ATOMIC_STORAGE_ALIGN: usize = 256;
+#[cfg(not(target_os = "nanox"))]
+pub const ATOMIC_STORAGE_ALIGN: usize = 512;
+
+/// Rounds `size` up to a multiple of `align`.
+pub const fn nvm_round_up(size: usize, align: usize) -> usize {
+    size.div_ceil(align) * align
+}
+
 /// Returned when trying to insert data when no more space is available
 pub struct StorageFullError;
 
+/// Returned when reading a storage whose content is not valid, e.g. because
+/// an update has been interrupted.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CorruptedStorageError;
+
+/// Outcome of [`SingleStorage::repair`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RepairStatus {
+    /// The storage was consistent, nothing has been written.
+    Healthy,
+    /// Leftovers of an interrupted update have been cleaned up. The value is
+    /// unchanged.
+    Repaired,
+    /// The value could not be read, and has been reset to the default one.
+    Reset,
+}
+
+/// Plain data types, whose bytes can be read and written directly: integers,
+/// and arrays of plain data.
+///
+/// Storages which handle the raw bytes of the values, such as [`SecureStorage`]
+/// and [`VersionedStorage`], only accept plain data.
+///
+/// # Safety
+///
+/// Implementors must not have padding bytes, and every bit pattern of
+/// `size_of::<Self>()` bytes must be a valid value.
+pub unsafe trait PlainData: Copy {}
+
+macro_rules! impl_plain_data {
+    ($($t:ty),*) => {
+        $(unsafe impl PlainData for $t {})*
+    };
+}
+
+impl_plain_data!(
+    u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize
+);
+
+unsafe impl<T: PlainData, const N: usize> PlainData for [T; N] {}
+
+/// Returns the value whose bytes are all zero.
+pub(crate) const fn plain_zeroed<T: PlainData>() -> T {
+    // Safety: any bit pattern is a valid `T`
+    unsafe { core::mem::zeroed() }
+}
+
+/// Returns the bytes of `value`.
+pub(crate) fn plain_bytes_mut<T: PlainData>(value: &mut T) -> &mut [u8] {
+    // Safety: `T` has no padding, and any bit pattern is a valid `T`
+    unsafe {
+        core::slice::from_raw_parts_mut(value as *mut T as *mut u8, core::mem::size_of::<T>())
+    }
+}
+
 /// What storage of single element should implement
 ///
 /// The address of the stored object, returned with get_ref, MUST remain the
@@ -63,6 +174,35 @@ pub trait SingleStorage<T> {
     /// Returns a non-mutable reference to the stored object.
     fn get_ref(&self) -> &T;
     fn update(&mut self, value: &T);
+
+    /// Returns a non-mutable reference to the stored object, or an error if
+    /// it cannot be read, instead of panicking as `get_ref` does.
+    fn try_get_ref(&self) -> Result<&T, CorruptedStorageError> {
+        Ok(self.get_ref())
+    }
+
+    /// Returns true if the stored object can be read.
+    fn is_healthy(&self) -> bool {
+        self.try_get_ref().is_ok()
+    }
+
+    /// Overwrites the stored object with `value`, even if the storage is
+    /// corrupted.
+    fn reset_to(&mut self, value: &T) {
+        self.update(value);
+    }
+
+    /// Restores a consistent state, resetting the stored object to `default`
+    /// if it cannot be read. This is meant to be called at boot, before
+    /// accessing the storage.
+    fn repair(&mut self, default: &T) -> RepairStatus {
+        if self.is_healthy() {
+            RepairStatus::Healthy
+        } else {
+            self.reset_to(default);
+            RepairStatus::Reset
+        }
+    }
 }
 
 /// Wraps a variable stored in Non-Volatile Memory to provide read and update
@@ -84,6 +224,10 @@ pub struct AlignedStorage<T> {
 }
 
 impl<T> AlignedStorage<T> {
+    /// Space used in NVM: the size of `T` rounded up to
+    /// [`ALIGNED_STORAGE_ALIGN`].
+    pub const NVM_SIZE: usize = nvm_round_up(core::mem::size_of::<T>(), ALIGNED_STORAGE_ALIGN);
+
     /// Create a `AlignedStorage<T>` initialized with a given value.
     /// This is to set the initial value of static `AlignedStorage<T>`, as the value
     /// member is private.
@@ -130,6 +274,10 @@ pub struct SafeStorage<T> {
 }
 
 impl<T> SafeStorage<T> {
+    /// Space used in NVM: an `AlignedStorage` for the flag, and one for the
+    /// value.
+    pub const NVM_SIZE: usize = AlignedStorage::<u8>::NVM_SIZE + AlignedStorage::<T>::NVM_SIZE;
+
     pub const fn new(value: T) -> SafeStorage<T> {
         SafeStorage {
             flag: AlignedStorage::new(STORAGE_VALID),
@@ -163,6 +311,14 @@ impl<T> SingleStorage<T> for SafeStorage<T> {
         self.value.update(value);
         self.flag.update(&STORAGE_VALID);
     }
+
+    fn try_get_ref(&self) -> Result<&T, CorruptedStorageError> {
+        if self.is_valid() {
+            Ok(self.value.get_ref())
+        } else {
+            Err(CorruptedStorageError)
+        }
+    }
 }
 
 /// Non-Volatile data storage with atomic update support.
@@ -194,6 +350,12 @@ atomic_storage!(256);
 ))]
 atomic_storage!(512);
 
+impl<T> AtomicStorage<T> {
+    /// Space used in NVM: two `SafeStorage`, rounded up to
+    /// [`ATOMIC_STORAGE_ALIGN`]. Even a single byte takes a whole page.
+    pub const NVM_SIZE: usize = nvm_round_up(2 * SafeStorage::<T>::NVM_SIZE, ATOMIC_STORAGE_ALIGN);
+}
+
 pub enum AtomicStorageElem {
     StorageA,
     StorageB,
@@ -211,19 +373,16 @@ where
         }
     }
 
-    /// Returns which storage contains the latest valid data.
-    ///
-    /// # Panics
-    ///
-    /// Panics if both storage elements are invalid (data corrupton),
-    /// although data corruption shall not be possible with tearing.
-    fn which(&self) -> AtomicStorageElem {
+    /// Returns which storage contains the latest valid data, or None if both
+    /// storage elements are invalid (data corruption), although data
+    /// corruption shall not be possible with tearing.
+    fn which(&self) -> Option<AtomicStorageElem> {
         if self.storage_a.is_valid() {
-            StorageA
+            Some(StorageA)
         } else if self.storage_b.is_valid() {
-            StorageB
+            Some(StorageB)
         } else {
-            panic!("invalidated atomic storage");
+            None
         }
     }
 }
@@ -233,10 +392,16 @@ where
     T: Copy,
 {
     /// Return reference to the stored value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if both storage elements are invalid. Use
+    /// [`try_get_ref`](SingleStorage::try_get_ref) or
+    /// [`repair`](SingleStorage::repair) to recover from this state.
     fn get_ref(&self) -> &T {
-        match self.which() {
-            StorageA => self.storage_a.get_ref(),
-            StorageB => self.storage_b.get_ref(),
+        match self.try_get_ref() {
+            Ok(value) => value,
+            Err(_) => panic!("invalidated atomic storage"),
         }
     }
 
@@ -244,41 +409,382 @@ where
     /// Warning: this can be vulnerable to tearing - leading to partial write.
     fn update(&mut self, value: &T) {
         match self.which() {
-            StorageA => {
+            Some(StorageA) => {
                 self.storage_b.update(value);
                 self.storage_a.invalidate();
             }
-            StorageB => {
+            Some(StorageB) => {
                 self.storage_a.update(value);
                 self.storage_b.invalidate();
             }
+            None => self.reset_to(value),
+        }
+    }
+
+    fn try_get_ref(&self) -> Result<&T, CorruptedStorageError> {
+        match self.which() {
+            Some(StorageA) => self.storage_a.try_get_ref(),
+            Some(StorageB) => self.storage_b.try_get_ref(),
+            None => Err(CorruptedStorageError),
+        }
+    }
+
+    /// Writes `value` to the first storage element, then invalidates the
+    /// second one.
+    fn reset_to(&mut self, value: &T) {
+        self.storage_a.update(value);
+        self.storage_b.invalidate();
+    }
+
+    /// Both storage elements are valid when an update has been interrupted
+    /// before invalidating the previous one: the second one is then
+    /// invalidated, keeping the value of the first one.
+    fn repair(&mut self, default: &T) -> RepairStatus {
+        match (self.storage_a.is_valid(), self.storage_b.is_valid()) {
+            (true, true) => {
+                self.storage_b.invalidate();
+                RepairStatus::Repaired
+            }
+            (false, false) => {
+                self.reset_to(default);
+                RepairStatus::Reset
+            }
+            _ => RepairStatus::Healthy,
+        }
+    }
+}
+/// Length of the header of [`Versioned`], which is also the alignment of the
+/// stored value.
+const VERSIONED_HEADER_LEN: usize = 8;
+
+/// A value stored as raw bytes, along with the version of its schema and its
+/// size.
+///
+/// This is the layout used by [`VersionedStorage`] in NVM. It only depends on
+/// the capacity `CAP`, so that values of any schema are found at the same
+/// place, whatever their type.
+#[repr(C, align(8))]
+#[derive(Copy, Clone)]
+pub struct Versioned<const CAP: usize> {
+    version: u16,
+    size: u16,
+    _reserved: u32,
+    bytes: [u8; CAP],
+}
+
+impl<const CAP: usize> Versioned<CAP> {
+    /// Stores the bytes of `value`, with schema `version`.
+    ///
+    /// Fails to compile if `T` is larger than `CAP`, if its alignment is
+    /// greater than 8 bytes, or if `CAP` does not fit in a `u16`.
+    pub const fn new<T: PlainData>(version: u16, value: &T) -> Versioned<CAP> {
+        const {
+            assert!(CAP <= u16::MAX as usize, "capacity does not fit in a u16");
+            assert!(
+                core::mem::size_of::<T>() <= CAP,
+                "value larger than capacity"
+            );
+            assert!(core::mem::align_of::<T>() <= VERSIONED_HEADER_LEN);
+        }
+        let mut bytes = [0u8; CAP];
+        // Safety: `T` fits in `bytes`, and has no padding
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                value as *const T as *const u8,
+                bytes.as_mut_ptr(),
+                core::mem::size_of::<T>(),
+            )
+        };
+        Versioned {
+            version,
+            size: core::mem::size_of::<T>() as u16,
+            _reserved: 0,
+            bytes,
         }
     }
+
+    /// Returns true if the value has been stored with schema `version` and
+    /// has the size of `T`.
+    fn is_current<T>(&self, version: u16) -> bool {
+        self.version == version && self.size as usize == core::mem::size_of::<T>()
+    }
+
+    /// Stored bytes of the value.
+    fn payload(&self) -> &[u8] {
+        &self.bytes[..(self.size as usize).min(CAP)]
+    }
+
+    /// # Safety
+    ///
+    /// The value must have been stored as a `T` ([`is_current`](Self::is_current)).
+    unsafe fn value<T: PlainData>(&self) -> &T {
+        // Safety: the bytes are aligned on 8 bytes, and hold a `T`, which is
+        // valid for any bit pattern
+        unsafe { &*(self.bytes.as_ptr() as *const T) }
+    }
+}
+
+/// Conversion of a value stored with an older schema version.
+pub struct Migration<T> {
+    /// Schema version this migration converts from.
+    pub from_version: u16,
+    /// Converts the stored bytes into a value of the current schema, or returns
+    /// `None` if they cannot be converted.
+    ///
+    /// The bytes are the whole old value, with the size it had when stored.
+    pub migrate: fn(&[u8]) -> Option<T>,
+}
+
+/// Outcome of [`VersionedStorage::migrate`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MigrationStatus {
+    /// The stored value already had the current schema.
+    UpToDate,
+    /// The stored value has been converted by the migration registered for its version.
+    Migrated { from_version: u16 },
+    /// No migration succeeded for the stored value, which has been reset to the default one.
+    Reset { from_version: u16 },
+    /// The stored value could not be read, and has been reset to the default one.
+    Corrupted,
 }
+
+/// Non-Volatile data storage tagged with a schema version.
+///
+/// The version `VERSION` and the size of `T` are stored along with the value,
+/// so that content written by a previous release of the application, with
+/// another layout of `T`, is detected instead of being silently reinterpreted.
+/// Such content is converted by [`migrate`](Self::migrate), which must be
+/// called before the value is accessed, typically when the application starts:
+///
+/// ```
+/// #[link_section = ".nvm_data"]
+/// static mut SETTINGS: NVMData<VersionedStorage<Settings, 2, 64>> =
+///     NVMData::new(VersionedStorage::new(&Settings::DEFAULT));
+///
+/// fn from_v1(old: &[u8]) -> Option<Settings> {
+///     Some(Settings { blind_signing: *old.first()? != 0, ..Settings::DEFAULT })
+/// }
+///
+/// let settings = unsafe { SETTINGS.get_mut() };
+/// settings.migrate(
+///     &[Migration { from_version: 1, migrate: from_v1 }],
+///     &Settings::DEFAULT,
+/// );
+/// ```
+///
+/// The value is stored as raw bytes in an area of `CAP` bytes, so that the
+/// layout of the storage does not depend on `T`. `CAP` must not change between
+/// releases of the application, and should leave room for future schemas.
+///
+/// Values are stored in an [`AtomicStorage`] by default, or in a [`SafeStorage`]
+/// when created with [`new_safe`](VersionedStorage::new_safe).
+///
+/// `T` must be [`PlainData`], as any bit pattern may be found in NVM after an
+/// upgrade.
+pub struct VersionedStorage<
+    T,
+    const VERSION: u16,
+    const CAP: usize,
+    S = AtomicStorage<Versioned<CAP>>,
+> {
+    storage: S,
+    _value: core::marker::PhantomData<T>,
+}
+
+impl<T, const VERSION: u16, const CAP: usize> VersionedStorage<T, VERSION, CAP>
+where
+    T: PlainData,
+{
+    /// Space used in NVM: an `AtomicStorage` of the [`Versioned`] value.
+    pub const NVM_SIZE: usize = AtomicStorage::<Versioned<CAP>>::NVM_SIZE;
+
+    /// Create a `VersionedStorage<T>`, backed by an [`AtomicStorage`],
+    /// initialized with a given value.
+    pub const fn new(value: &T) -> Self {
+        VersionedStorage {
+            storage: AtomicStorage::new(&Versioned::new(VERSION, value)),
+            _value: core::marker::PhantomData,
+        }
+    }
+}
+
+impl<T, const VERSION: u16, const CAP: usize>
+    VersionedStorage<T, VERSION, CAP, SafeStorage<Versioned<CAP>>>
+where
+    T: PlainData,
+{
+    /// Space used in NVM: a `SafeStorage` of the [`Versioned`] value.
+    pub const NVM_SIZE: usize = SafeStorage::<Versioned<CAP>>::NVM_SIZE;
+
+    /// Create a `VersionedStorage<T>`, backed by a [`SafeStorage`], initialized
+    /// with a given value.
+    pub const fn new_safe(value: &T) -> Self {
+        VersionedStorage {
+            storage: SafeStorage::new(Versioned::new(VERSION, value)),
+            _value: core::marker::PhantomData,
+        }
+    }
+}
+
+impl<T, const VERSION: u16, const CAP: usize, S> VersionedStorage<T, VERSION, CAP, S>
+where
+    T: PlainData,
+    S: SingleStorage<Versioned<CAP>>,
+{
+    /// Returns the schema version of the stored value, or an error if the
+    /// storage is corrupted.
+    pub fn stored_version(&self) -> Result<u16, CorruptedStorageError> {
+        Ok(self.storage.try_get_ref()?.version)
+    }
+
+    /// Returns true if the stored value has the current schema, i.e. if it can
+    /// be accessed without calling [`migrate`](Self::migrate) first.
+    ///
+    /// Returns false if the storage is corrupted: `migrate` then resets it.
+    pub fn is_up_to_date(&self) -> bool {
+        self.storage
+            .try_get_ref()
+            .is_ok_and(|stored| stored.is_current::<T>(VERSION))
+    }
+
+    /// Converts the stored value to the current schema if needed.
+    ///
+    /// If the value has been stored with another version, the migration
+    /// registered for this version is run. When there is none, or when it fails,
+    /// the value is reset to `default`. A corrupted storage is reset to
+    /// `default` as well.
+    ///
+    /// # Arguments
+    ///
+    /// * `migrations` - Migrations from previous schema versions
+    /// * `default` - Value used when the stored one cannot be converted
+    pub fn migrate(&mut self, migrations: &[Migration<T>], default: &T) -> MigrationStatus {
+        let Ok(stored) = self.storage.try_get_ref() else {
+            self.storage.reset_to(&Versioned::new(VERSION, default));
+            return MigrationStatus::Corrupted;
+        };
+        if stored.is_current::<T>(VERSION) {
+            return MigrationStatus::UpToDate;
+        }
+        let from_version = stored.version;
+        let old = stored.payload();
+        let migrated = migrations
+            .iter()
+            .filter(|m| m.from_version == from_version)
+            .find_map(|m| (m.migrate)(old));
+        let (value, status) = match migrated {
+            Some(value) => (value, MigrationStatus::Migrated { from_version }),
+            None => (*default, MigrationStatus::Reset { from_version }),
+        };
+        self.storage.update(&Versioned::new(VERSION, &value));
+        status
+    }
+}
+
+impl<T, const VERSION: u16, const CAP: usize, S> SingleStorage<T>
+    for VersionedStorage<T, VERSION, CAP, S>
+where
+    T: PlainData,
+    S: SingleStorage<Versioned<CAP>>,
+{
+    /// Return reference to the stored value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the stored value does not have the current schema, i.e. if
+    /// [`migrate`](Self::migrate) has not been called after an upgrade.
+    fn get_ref(&self) -> &T {
+        let stored = self.storage.get_ref();
+        assert!(
+            stored.is_current::<T>(VERSION),
+            "NVM schema migration required"
+        );
+        // Safety: the value has been stored as a `T`
+        unsafe { stored.value() }
+    }
+
+    fn update(&mut self, value: &T) {
+        self.storage.update(&Versioned::new(VERSION, value));
+    }
+
+    /// Returns an error if the storage is corrupted, or if the stored value
+    /// does not have the current schema.
+    fn try_get_ref(&self) -> Result<&T, CorruptedStorageError> {
+        let stored = self.storage.try_get_ref()?;
+        if stored.is_current::<T>(VERSION) {
+            // Safety: the value has been stored as a `T`
+            Ok(unsafe { stored.value() })
+        } else {
+            Err(CorruptedStorageError)
+        }
+    }
+
+    fn reset_to(&mut self, value: &T) {
+        self.storage.reset_to(&Versioned::new(VERSION, value));
+    }
+
+    /// Repairs the underlying storage. A value with an outdated schema is kept,
+    /// to be converted by [`migrate`](Self::migrate).
+    fn repair(&mut self, default: &T) -> RepairStatus {
+        self.storage.repair(&Versioned::new(VERSION, default))
+    }
+}
+
 pub struct KeyOutOfRange;
 
+/// Keys of the allocated slots of a [`Collection`], in the order of the items.
+#[derive(Copy, Clone)]
+struct SlotOrder<const N: usize> {
+    len: usize,
+    keys: [u16; N],
+}
+
+impl<const N: usize> SlotOrder<N> {
+    fn keys(&self) -> &[u16] {
+        &self.keys[..self.len]
+    }
+}
+
 /// A Non-Volatile fixed-size collection of fixed-size items.
-/// Items insertion and deletion are atomic.
-/// Items update is not implemented because the atomicity of this operation
-/// cannot be guaranteed here.
+///
+/// Items insertion, update and deletion are atomic: values are only written
+/// to free slots, and the table of the allocated slots is then changed in a
+/// single `AtomicStorage` update. Items are kept in insertion order, which is
+/// also the iteration order.
+///
+/// `N` must not be greater than 65536.
 // We use the term `index` to represent the user-facing number of an element in the collection,
 // and the term `key` to represent the underlying offset at which the element is located in the collection.
-// e.g with `[0, 0, 1, 1, 0, 1, 0]` (with 0s representing free slots and 1s representing allocated slots)
-//            ↑  ↑  ↑  ↑  ↑  ↑  ↑
-// index:     -  -  0  1  -  2  -
+// e.g with `order.keys == [3, 0, 5]` and 7 slots:
+// index:     1  -  -  0  -  2  -
 // key:       0, 1, 2, 3, 4, 5, 6
 pub struct Collection<T, const N: usize> {
-    flags: AtomicStorage<[u8; N]>,
+    order: AtomicStorage<SlotOrder<N>>,
     slots: [AlignedStorage<T>; N],
 }
 
+impl<T, const N: usize> Collection<T, N> {
+    /// Space used in NVM: the `AtomicStorage` of the table of allocated
+    /// slots, and one `AlignedStorage` per slot, rounded up to
+    /// [`ATOMIC_STORAGE_ALIGN`].
+    pub const NVM_SIZE: usize = nvm_round_up(
+        AtomicStorage::<SlotOrder<N>>::NVM_SIZE + N * AlignedStorage::<T>::NVM_SIZE,
+        ATOMIC_STORAGE_ALIGN,
+    );
+}
+
 impl<T, const N: usize> Collection<T, N>
 where
     T: Copy,
 {
     pub const fn new(value: T) -> Collection<T, N> {
+        // Keys of the slots are stored as `u16`
+        const { assert!(N <= 65536, "too many slots in Collection") };
         Collection {
-            flags: AtomicStorage::new(&[0; N]),
+            order: AtomicStorage::new(&SlotOrder {
+                len: 0,
+                keys: [0; N],
+            }),
             slots: [AlignedStorage::new(value); N],
         }
     }
@@ -286,54 +792,37 @@ where
     /// Finds and returns a reference to a free slot, or returns None if
     /// all slots are allocated.
     fn find_free_slot(&self) -> Option<usize> {
-        self.flags
-            .get_ref()
-            .iter()
-            .position(|&e| e != STORAGE_VALID)
+        // Scan the order table for each slot rather than building an N-sized
+        // allocation map on the stack.
+        let keys = self.order.get_ref().keys();
+        (0..N).find(|&key| !keys.contains(&(key as u16)))
     }
 
-    /// Adds an item in the collection. Returns an error if there is not free
-    /// slots.
+    /// Adds an item at the end of the collection. Returns an error if there is
+    /// no free slot.
     /// This operation is atomic.
     pub fn add(&mut self, value: &T) -> Result<(), StorageFullError> {
         match self.find_free_slot() {
-            Some(i) => {
-                self.slots[i].update(value);
-                let mut new_flags = *self.flags.get_ref();
-                new_flags[i] = STORAGE_VALID;
-                self.flags.update(&new_flags);
+            Some(key) => {
+                self.slots[key].update(value);
+                let mut new_order = *self.order.get_ref();
+                new_order.keys[new_order.len] = key as u16;
+                new_order.len += 1;
+                self.order.update(&new_order);
                 Ok(())
             }
             None => Err(StorageFullError),
         }
     }
 
-    /// Returns a boolean representing whether the slot at `key` was allocated or not.
-    ///
-    /// # Errors
-    ///
-    /// Returns an error if the `key` is out of range.
-    fn is_allocated(&self, key: usize) -> Result<bool, KeyOutOfRange> {
-        match self.flags.get_ref().get(key) {
-            Some(&byte) => {
-                if byte == STORAGE_VALID {
-                    Ok(true)
-                } else {
-                    Ok(false)
-                }
-            }
-            None => Err(KeyOutOfRange),
-        }
-    }
-
     /// Returns the number of allocated slots.
     pub fn len(&self) -> usize {
-        self.count_allocated(N)
+        self.order.get_ref().len
     }
 
     /// Returns true if collection is empty
     pub fn is_empty(&self) -> bool {
-        !self.flags.get_ref().iter().any(|v| *v == STORAGE_VALID)
+        self.len() == 0
     }
 
     /// Returns the maximum number of items the collection can store.
@@ -347,15 +836,6 @@ where
         self.capacity() - self.len()
     }
 
-    /// Counts the number of allocated slots up until `len`.
-    fn count_allocated(&self, len: usize) -> usize {
-        self.flags
-            .get_ref()
-            .iter()
-            .take(len)
-            .fold(0, |acc, &byte| acc + (byte == STORAGE_VALID) as u32) as usize
-    }
-
     /// Returns the `key` of an item in the internal storage, given the `index`
     /// in the collection. If `index` is too big, None is returned.
     ///
@@ -363,22 +843,11 @@ where
     ///
     /// * `index` - Index in the collection
     fn index_to_key(&self, index: usize) -> Option<usize> {
-        // Neat optimization: start by setting `next` to index,
-        // because we know we could not have found `index` allocated slots beforehand.
-        let mut key = index;
-        // Now count the number of allocated slots we have found up
-        // until this `index` (without including the slot at `index` itself).
-        let mut allocated_count = self.count_allocated(index);
-        loop {
-            let is_allocated = self.is_allocated(key).ok()?;
-            if is_allocated {
-                if allocated_count == index {
-                    return Some(key);
-                }
-                allocated_count += 1;
-            }
-            key += 1;
-        }
+        self.order
+            .get_ref()
+            .keys()
+            .get(index)
+            .map(|&key| key as usize)
     }
 
     /// Returns reference to an item, or None if the index is out of bounds
@@ -393,7 +862,37 @@ where
         }
     }
 
-    /// Removes the item located at `index` from the collection.
+    /// Replaces the item located at `index`. Returns an error if there is no
+    /// free slot to write the new value to.
+    /// This operation is atomic: the item keeps either its previous value or
+    /// the new one.
+    ///
+    /// As the previous value is kept until the new one is written, updating
+    /// an item requires a free slot: it fails when the collection is full
+    /// (`len() == N`). Items have to be removed first, or the collection
+    /// sized with one more slot than the items it holds.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - Item index
+    /// * `value` - New value of the item
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn update(&mut self, index: usize, value: &T) -> Result<(), StorageFullError> {
+        assert!(index < self.len());
+        let key = self.find_free_slot().ok_or(StorageFullError)?;
+        self.slots[key].update(value);
+        let mut new_order = *self.order.get_ref();
+        new_order.keys[index] = key as u16;
+        self.order.update(&new_order);
+        Ok(())
+    }
+
+    /// Removes the item located at `index` from the collection, shifting the
+    /// items after it.
+    /// This operation is atomic.
     ///
     /// # Arguments
     ///
@@ -403,16 +902,68 @@ where
     ///
     /// Panics if `index` is out of bounds.
     pub fn remove(&mut self, index: usize) {
+        assert!(index < self.len());
+        let mut new_order = *self.order.get_ref();
+        new_order.keys.copy_within(index + 1..new_order.len, index);
+        new_order.len -= 1;
+        self.order.update(&new_order);
+    }
+
+    /// Removes the item located at `index` from the collection, and returns it.
+    /// The last item of the collection is moved to `index`.
+    /// This operation is atomic.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - Item index
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn swap_remove(&mut self, index: usize) -> T {
         let key = self.index_to_key(index).unwrap();
-        let mut new_flags = *self.flags.get_ref();
-        new_flags[key] = 0;
-        self.flags.update(&new_flags);
+        let value = *self.slots[key].get_ref();
+        let mut new_order = *self.order.get_ref();
+        new_order.len -= 1;
+        new_order.keys[index] = new_order.keys[new_order.len];
+        self.order.update(&new_order);
+        value
+    }
+
+    /// Retains only the items for which `f` returns true, keeping their order.
+    /// This operation is atomic.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let mut new_order = *self.order.get_ref();
+        new_order.len = 0;
+        for &key in self.order.get_ref().keys() {
+            if f(self.slots[key as usize].get_ref()) {
+                new_order.keys[new_order.len] = key;
+                new_order.len += 1;
+            }
+        }
+        if new_order.len != self.len() {
+            self.order.update(&new_order);
+        }
     }
 
     /// Removes all the items from the collection.
     /// This operation is atomic.
     pub fn clear(&mut self) {
-        self.flags.update(&[0; N]);
+        self.order.update(&SlotOrder {
+            len: 0,
+            keys: [0; N],
+        });
+    }
+
+    /// Returns an iterator over the items, in insertion order.
+    pub fn iter(&self) -> CollectionIterator<'_, T, N> {
+        CollectionIterator {
+            container: self,
+            next_index: 0,
+        }
     }
 }
 
@@ -424,10 +975,7 @@ where
     type IntoIter = CollectionIterator<'a, T, N>;
 
     fn into_iter(self) -> CollectionIterator<'a, T, N> {
-        CollectionIterator {
-            container: self,
-            next_key: 0,
-        }
+        self.iter()
     }
 }
 
@@ -436,7 +984,7 @@ where
     T: Copy,
 {
     container: &'a Collection<T, N>,
-    next_key: usize,
+    next_index: usize,
 }
 
 impl<'a, T, const N: usize> Iterator for CollectionIterator<'a, T, N>
@@ -446,12 +994,220 @@ where
     type Item = &'a T;
 
     fn next(&mut self) -> core::option::Option<&'a T> {
-        loop {
-            let is_allocated = self.container.is_allocated(self.next_key).ok()?;
-            self.next_key += 1;
-            if is_allocated {
-                return Some(self.container.slots[self.next_key - 1].get_ref());
-            }
+        let item = self.container.get(self.next_index)?;
+        self.next_index += 1;
+        Some(item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_eq_err as assert_eq;
+    use crate::testing::TestType;
+    use core::mem::size_of;
+    use testmacro::test_item as test;
+
+    #[test]
+    fn atomic_storage_repair() {
+        let mut storage = AtomicStorage::new(&1u32);
+        assert_eq!(storage.repair(&0), RepairStatus::Healthy);
+
+        // Interrupted before invalidating the previous element
+        storage.storage_b.update(&2);
+        assert_eq!(storage.repair(&0), RepairStatus::Repaired);
+        assert_eq!(*storage.get_ref(), 1);
+        assert_eq!(storage.storage_b.is_valid(), false);
+
+        storage.storage_a.invalidate();
+        assert_eq!(storage.is_healthy(), false);
+        assert_eq!(storage.try_get_ref(), Err(CorruptedStorageError));
+        assert_eq!(storage.repair(&7), RepairStatus::Reset);
+        assert_eq!(storage.try_get_ref(), Ok(&7));
+
+        storage.storage_a.invalidate();
+        storage.update(&3);
+        assert_eq!(storage.try_get_ref(), Ok(&3));
+    }
+
+    /// Release 2 stores two `u32`, release 1 stored 12 bytes.
+    fn from_v1(old: &[u8]) -> Option<[u32; 2]> {
+        match old {
+            [first, .., last] if old.len() == 12 => Some([*first as u32, *last as u32]),
+            _ => None,
+        }
+    }
+
+    const MIGRATIONS: [Migration<[u32; 2]>; 1] = [Migration {
+        from_version: 1,
+        migrate: from_v1,
+    }];
+
+    /// Reads a storage written by a previous release: only the capacity has to
+    /// be the same.
+    fn upgrade<O: Copy, const V: u16>(
+        old: VersionedStorage<O, V, 16>,
+    ) -> VersionedStorage<[u32; 2], 2, 16> {
+        unsafe { core::mem::transmute_copy(&old) }
+    }
+
+    #[test]
+    fn versioned_storage_migrate() {
+        let old = VersionedStorage::<[u8; 12], 1, 16>::new(&[7, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 9]);
+        let mut storage = upgrade(old);
+        assert_eq!(storage.stored_version(), Ok(1));
+        assert_eq!(storage.is_up_to_date(), false);
+        assert_eq!(storage.try_get_ref(), Err(CorruptedStorageError));
+        assert_eq!(
+            storage.migrate(&MIGRATIONS, &[0; 2]),
+            MigrationStatus::Migrated { from_version: 1 }
+        );
+        assert_eq!(storage.is_up_to_date(), true);
+        assert_eq!(storage.get_ref(), &[7, 9]);
+    }
+
+    #[test]
+    fn versioned_storage_reset() {
+        // No migration registered for this version
+        let mut storage = upgrade(VersionedStorage::<[u8; 12], 3, 16>::new(&[1; 12]));
+        assert_eq!(
+            storage.migrate(&MIGRATIONS, &[5, 6]),
+            MigrationStatus::Reset { from_version: 3 }
+        );
+        assert_eq!(storage.get_ref(), &[5, 6]);
+
+        // The migration rejects the stored value
+        let mut storage = upgrade(VersionedStorage::<[u8; 4], 1, 16>::new(&[1; 4]));
+        assert_eq!(
+            storage.migrate(&MIGRATIONS, &[5, 6]),
+            MigrationStatus::Reset { from_version: 1 }
+        );
+        assert_eq!(storage.get_ref(), &[5, 6]);
+    }
+
+    #[test]
+    fn versioned_storage_up_to_date() {
+        let mut storage = VersionedStorage::<[u32; 2], 2, 16>::new(&[1, 2]);
+        assert_eq!(storage.is_up_to_date(), true);
+        assert_eq!(
+            storage.migrate(&MIGRATIONS, &[0; 2]),
+            MigrationStatus::UpToDate
+        );
+        assert_eq!(storage.get_ref(), &[1, 2]);
+        storage.update(&[3, 4]);
+        assert_eq!(storage.try_get_ref(), Ok(&[3, 4]));
+        assert_eq!(storage.stored_version(), Ok(2));
+    }
+
+    #[test]
+    fn versioned_storage_corrupted() {
+        let mut storage = VersionedStorage::<[u32; 2], 2, 16>::new(&[1, 2]);
+        storage.storage.storage_a.invalidate();
+        assert_eq!(storage.stored_version(), Err(CorruptedStorageError));
+        assert_eq!(storage.is_up_to_date(), false);
+        assert_eq!(
+            storage.migrate(&MIGRATIONS, &[5, 6]),
+            MigrationStatus::Corrupted
+        );
+        assert_eq!(storage.try_get_ref(), Ok(&[5, 6]));
+    }
+
+    #[test]
+    fn collection_retain() {
+        let mut collection: Collection<u32, 4> = Collection::new(0);
+        for i in 1..=4 {
+            assert_eq!(collection.add(&i).is_ok(), true);
+        }
+        collection.retain(|value| value % 2 == 0);
+        assert_eq!(collection.len(), 2);
+        assert_eq!(collection.get(0), Some(&2));
+        assert_eq!(collection.get(1), Some(&4));
+        assert_eq!(collection.get(2), None);
+
+        // Nothing is written when all the items are kept
+        sim::reset();
+        collection.retain(|_| true);
+        assert_eq!(sim::bytes_written(), 0);
+        assert_eq!(collection.remaining(), 2);
+    }
+
+    #[test]
+    fn collection_swap_remove() {
+        let mut collection: Collection<u32, 3> = Collection::new(0);
+        for i in 1..=3 {
+            assert_eq!(collection.add(&i).is_ok(), true);
+        }
+        assert_eq!(collection.swap_remove(0), 1);
+        assert_eq!(collection.len(), 2);
+        assert_eq!(collection.get(0), Some(&3));
+        assert_eq!(collection.get(1), Some(&2));
+        assert_eq!(collection.swap_remove(1), 2);
+        assert_eq!(collection.get(0), Some(&3));
+        assert_eq!(collection.len(), 1);
+    }
+
+    #[test]
+    fn collection_update_full() {
+        let mut collection: Collection<u32, 2> = Collection::new(0);
+        assert_eq!(collection.add(&1).is_ok(), true);
+        assert_eq!(collection.add(&2).is_ok(), true);
+        assert_eq!(collection.add(&3).is_err(), true);
+        // The new value needs a free slot
+        assert_eq!(collection.update(0, &3).is_err(), true);
+        assert_eq!(collection.get(0), Some(&1));
+        assert_eq!(collection.get(1), Some(&2));
+
+        collection.remove(1);
+        assert_eq!(collection.update(0, &3).is_ok(), true);
+        assert_eq!(collection.get(0), Some(&3));
+        assert_eq!(collection.len(), 1);
+    }
+
+    #[test]
+    fn nvm_sizes() {
+        assert_eq!(AlignedStorage::<u8>::NVM_SIZE, 64);
+        assert_eq!(SafeStorage::<[u8; 100]>::NVM_SIZE, 64 + 128);
+        assert_eq!(AtomicStorage::<u8>::NVM_SIZE, ATOMIC_STORAGE_ALIGN);
+        assert_eq!(
+            AtomicStorage::<[u8; 100]>::NVM_SIZE,
+            size_of::<AtomicStorage<[u8; 100]>>()
+        );
+        assert_eq!(
+            AtomicStorage::<[u8; 1000]>::NVM_SIZE,
+            size_of::<AtomicStorage<[u8; 1000]>>()
+        );
+        assert_eq!(
+            Collection::<u32, 10>::NVM_SIZE,
+            size_of::<Collection<u32, 10>>()
+        );
+        assert_eq!(
+            Collection::<[u8; 100], 40>::NVM_SIZE,
+            size_of::<Collection<[u8; 100], 40>>()
+        );
+        assert_eq!(
+            VersionedStorage::<[u32; 2], 2, 16>::NVM_SIZE,
+            size_of::<VersionedStorage<[u32; 2], 2, 16>>()
+        );
+        type SafeVersioned = VersionedStorage<[u32; 2], 2, 100, SafeStorage<Versioned<100>>>;
+        assert_eq!(SafeVersioned::NVM_SIZE, size_of::<SafeVersioned>());
+        assert_eq!(
+            SecureStorage::<[u8; 100]>::NVM_SIZE,
+            size_of::<SecureStorage<[u8; 100]>>()
+        );
+        assert_eq!(KvStore::<8, 4>::NVM_SIZE, size_of::<KvStore<8, 4>>());
+        assert_eq!(
+            crate::settings::SettingsStorage::<3>::NVM_SIZE,
+            size_of::<crate::settings::SettingsStorage<3>>()
+        );
+    }
+
+    #[test]
+    fn nvm_layouts() {
+        // Sizes computed by the build script to report the `.nvm_data` usage
+        let layouts: &[(&str, usize, usize)] =
+            &include!(concat!(env!("OUT_DIR"), "/nvm_layouts.rs"));
+        for &(_, nvm_size, layout) in layouts {
+            assert_eq!(nvm_size, layout);
         }
     }
 }
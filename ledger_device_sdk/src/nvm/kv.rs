@@ -0,0 +1,561 @@
+//! Key-value store in NVM.
+//!
+//! [`KvStore`] maps keys, either numeric identifiers or short strings, to
+//! byte strings of variable length, e.g. registered wallet policies or address
+//! book entries:
+//!
+//! ```
+//! use ledger_device_sdk::NVMData;
+//! use ledger_device_sdk::nvm::KvStore;
+//!
+//! // Up to 8 entries, in 32 blocks of 64 bytes
+//! #[link_section=".nvm_data"]
+//! static mut POLICIES: NVMData<KvStore<8, 32>> = NVMData::new(KvStore::new());
+//!
+//! let policies = unsafe { POLICIES.get_mut() };
+//! policies.insert("vault", &policy).unwrap();
+//! policies.insert(42u16, &[1, 2, 3]).unwrap();
+//! assert_eq!(policies.get(42u16), Some(&[1, 2, 3][..]));
+//! policies.remove("vault").unwrap();
+//! ```
+//!
+//! Values are stored in a data area of fixed-size blocks, each value in
+//! consecutive blocks. The keys, along with the location of their values and
+//! the number of writes of each block, are kept in a directory stored in an
+//! [`AtomicStorage`]:
+//! * a value is always written to free blocks, and only becomes visible when
+//!   the directory is updated, so inserting, replacing or removing an entry is
+//!   atomic,
+//! * when several free areas can hold a value, the least written one is used,
+//!   to spread the wear of the Flash memory,
+//! * when the free blocks are too fragmented to hold a value, entries are moved
+//!   to the beginning of the data area (see [`KvStore::compact`]). Each move is
+//!   itself atomic.
+
+use super::{ATOMIC_STORAGE_ALIGN, AlignedStorage, AtomicStorage, SingleStorage, nvm_round_up};
+
+/// Size of the blocks of the data area of a [`KvStore`].
+pub const KV_BLOCK_SIZE: usize = 64;
+
+/// Maximum length of the string keys of a [`KvStore`].
+pub const KV_MAX_KEY_LEN: usize = 32;
+
+const ENTRY_FREE: u8 = 0;
+const ENTRY_ID: u8 = 1;
+const ENTRY_NAME: u8 = 2;
+
+/// Key of a [`KvStore`] entry.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Key<'a> {
+    Id(u16),
+    Name(&'a str),
+}
+
+impl From<u16> for Key<'_> {
+    fn from(id: u16) -> Self {
+        Key::Id(id)
+    }
+}
+
+impl<'a> From<&'a str> for Key<'a> {
+    fn from(name: &'a str) -> Self {
+        Key::Name(name)
+    }
+}
+
+/// Errors returned by [`KvStore`] operations.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum KvError {
+    /// String key empty or longer than [`KV_MAX_KEY_LEN`].
+    InvalidKey,
+    /// No entry with this key.
+    NotFound,
+    /// Not enough free entries or blocks left.
+    Full,
+}
+
+/// Directory entry: the key and the location of the value.
+#[derive(Copy, Clone)]
+struct Entry {
+    kind: u8,
+    key_len: u8,
+    key: [u8; KV_MAX_KEY_LEN],
+    start: u16,
+    len: u16,
+}
+
+impl Entry {
+    const FREE: Entry = Entry {
+        kind: ENTRY_FREE,
+        key_len: 0,
+        key: [0; KV_MAX_KEY_LEN],
+        start: 0,
+        len: 0,
+    };
+
+    fn new(key: Key, start: usize, len: usize) -> Entry {
+        let mut entry = Entry {
+            start: start as u16,
+            len: len as u16,
+            ..Entry::FREE
+        };
+        match key {
+            Key::Id(id) => {
+                entry.kind = ENTRY_ID;
+                entry.key_len = 2;
+                entry.key[..2].copy_from_slice(&id.to_be_bytes());
+            }
+            Key::Name(name) => {
+                entry.kind = ENTRY_NAME;
+                entry.key_len = name.len() as u8;
+                entry.key[..name.len()].copy_from_slice(name.as_bytes());
+            }
+        }
+        entry
+    }
+
+    fn is_free(&self) -> bool {
+        self.kind == ENTRY_FREE
+    }
+
+    fn key(&self) -> Key<'_> {
+        let bytes = &self.key[..self.key_len as usize];
+        match self.kind {
+            ENTRY_ID => Key::Id(u16::from_be_bytes([bytes[0], bytes[1]])),
+            _ => Key::Name(core::str::from_utf8(bytes).unwrap_or("")),
+        }
+    }
+
+    fn blocks(&self) -> core::ops::Range<usize> {
+        let start = self.start as usize;
+        start..start + (self.len as usize).div_ceil(KV_BLOCK_SIZE)
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Directory<const E: usize, const B: usize> {
+    entries: [Entry; E],
+    /// Number of writes of each block.
+    wear: [u16; B],
+}
+
+/// A Non-Volatile key-value store with up to `E` entries, whose values are
+/// stored in `B` blocks of [`KV_BLOCK_SIZE`] bytes.
+///
+/// Insertion, update and deletion of entries are atomic.
+pub struct KvStore<const E: usize, const B: usize> {
+    directory: AtomicStorage<Directory<E, B>>,
+    blocks: [AlignedStorage<[u8; KV_BLOCK_SIZE]>; B],
+}
+
+impl<const E: usize, const B: usize> Default for KvStore<E, B> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const E: usize, const B: usize> KvStore<E, B> {
+    /// Space used in NVM: the `AtomicStorage` of the directory, and the `B`
+    /// blocks, rounded up to [`ATOMIC_STORAGE_ALIGN`].
+    pub const NVM_SIZE: usize = nvm_round_up(
+        AtomicStorage::<Directory<E, B>>::NVM_SIZE + B * KV_BLOCK_SIZE,
+        ATOMIC_STORAGE_ALIGN,
+    );
+
+    pub const fn new() -> KvStore<E, B> {
+        KvStore {
+            directory: AtomicStorage::new(&Directory {
+                entries: [Entry::FREE; E],
+                wear: [0; B],
+            }),
+            blocks: [AlignedStorage::new([0; KV_BLOCK_SIZE]); B],
+        }
+    }
+
+    fn find(&self, key: Key) -> Option<usize> {
+        self.directory
+            .get_ref()
+            .entries
+            .iter()
+            .position(|e| !e.is_free() && e.key() == key)
+    }
+
+    fn value(&self, entry: &Entry) -> &[u8] {
+        if entry.len == 0 {
+            return &[];
+        }
+        // Safety: blocks are contiguous, and `insert` checks the value fits in
+        // the data area.
+        unsafe {
+            let first = self.blocks.as_ptr().add(entry.start as usize) as *const u8;
+            core::slice::from_raw_parts(first, entry.len as usize)
+        }
+    }
+
+    /// Returns the value associated with `key`, or None if there is none.
+    pub fn get<'k>(&self, key: impl Into<Key<'k>>) -> Option<&[u8]> {
+        let index = self.find(key.into())?;
+        Some(self.value(&self.directory.get_ref().entries[index]))
+    }
+
+    pub fn contains_key<'k>(&self, key: impl Into<Key<'k>>) -> bool {
+        self.find(key.into()).is_some()
+    }
+
+    /// Returns the number of entries.
+    pub fn len(&self) -> usize {
+        self.directory
+            .get_ref()
+            .entries
+            .iter()
+            .filter(|e| !e.is_free())
+            .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the maximum number of entries.
+    pub const fn capacity(&self) -> usize {
+        E
+    }
+
+    /// Returns the number of blocks not used by any value.
+    pub fn free_blocks(&self) -> usize {
+        self.used_blocks().iter().filter(|used| !**used).count()
+    }
+
+    /// Returns the blocks used by the values of the entries.
+    fn used_blocks(&self) -> [bool; B] {
+        let mut used = [false; B];
+        for entry in self.directory.get_ref().entries.iter() {
+            if !entry.is_free() {
+                used[entry.blocks()].fill(true);
+            }
+        }
+        used
+    }
+
+    /// Finds `count` consecutive free blocks, and returns the first one.
+    ///
+    /// Among the candidates, the one whose most written block has been written
+    /// the fewest times is returned, or the first one if `lowest` is set.
+    fn find_free_blocks(&self, used: &[bool; B], count: usize, lowest: bool) -> Option<usize> {
+        let wear = &self.directory.get_ref().wear;
+        let mut best: Option<(usize, u16)> = None;
+        for start in 0..=B.checked_sub(count)? {
+            let run = start..start + count;
+            if used[run.clone()].iter().any(|used| *used) {
+                continue;
+            }
+            let max_wear = wear[run].iter().copied().max().unwrap_or(0);
+            if best.is_none_or(|(_, best_wear)| max_wear < best_wear) {
+                best = Some((start, max_wear));
+            }
+            if lowest {
+                break;
+            }
+        }
+        best.map(|(start, _)| start)
+    }
+
+    /// Writes `value` to the blocks starting at `start`, and returns the
+    /// directory updated with the new writes count of these blocks.
+    fn write_blocks(&mut self, start: usize, value: &[u8]) -> Directory<E, B> {
+        let mut directory = *self.directory.get_ref();
+        for (i, chunk) in value.chunks(KV_BLOCK_SIZE).enumerate() {
+            let mut block = [0u8; KV_BLOCK_SIZE];
+            block[..chunk.len()].copy_from_slice(chunk);
+            self.blocks[start + i].update(&block);
+            directory.wear[start + i] = directory.wear[start + i].saturating_add(1);
+        }
+        directory
+    }
+
+    /// Inserts an entry, or replaces the value of the existing entry with the
+    /// same key.
+    ///
+    /// If the free blocks are too fragmented to hold `value`, the store is
+    /// compacted first.
+    ///
+    /// # Errors
+    ///
+    /// * [`KvError::InvalidKey`] if `key` is an empty or too long string,
+    /// * [`KvError::Full`] if there is no free entry or not enough free blocks.
+    ///   The store is left unchanged, except it may have been compacted.
+    pub fn insert<'k>(&mut self, key: impl Into<Key<'k>>, value: &[u8]) -> Result<(), KvError> {
+        let key = key.into();
+        if matches!(key, Key::Name(name) if name.is_empty() || name.len() > KV_MAX_KEY_LEN) {
+            return Err(KvError::InvalidKey);
+        }
+        if value.len() > u16::MAX as usize {
+            return Err(KvError::Full);
+        }
+        let index = match self.find(key) {
+            Some(index) => index,
+            None => self
+                .directory
+                .get_ref()
+                .entries
+                .iter()
+                .position(|e| e.is_free())
+                .ok_or(KvError::Full)?,
+        };
+
+        // The blocks of the current value are kept until the directory is updated.
+        let count = value.len().div_ceil(KV_BLOCK_SIZE);
+        let used = self.used_blocks();
+        if used.iter().filter(|used| !**used).count() < count {
+            return Err(KvError::Full);
+        }
+        let start = match self.find_free_blocks(&used, count, false) {
+            Some(start) => start,
+            None => {
+                self.compact();
+                self.find_free_blocks(&self.used_blocks(), count, false)
+                    .ok_or(KvError::Full)?
+            }
+        };
+
+        let mut directory = self.write_blocks(start, value);
+        directory.entries[index] = Entry::new(key, start, value.len());
+        self.directory.update(&directory);
+        Ok(())
+    }
+
+    /// Removes the entry with the given key.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`KvError::NotFound`] if there is no such entry.
+    pub fn remove<'k>(&mut self, key: impl Into<Key<'k>>) -> Result<(), KvError> {
+        let index = self.find(key.into()).ok_or(KvError::NotFound)?;
+        let mut directory = *self.directory.get_ref();
+        directory.entries[index] = Entry::FREE;
+        self.directory.update(&directory);
+        Ok(())
+    }
+
+    /// Removes all the entries.
+    /// This operation is atomic.
+    pub fn clear(&mut self) {
+        let mut directory = *self.directory.get_ref();
+        directory.entries = [Entry::FREE; E];
+        self.directory.update(&directory);
+    }
+
+    /// Moves values towards the beginning of the data area to merge free
+    /// blocks.
+    ///
+    /// Starting with the last one, values are moved to the first free blocks
+    /// which can hold them, as long as they do not overlap their current
+    /// blocks, so that each move is atomic.
+    pub fn compact(&mut self) {
+        loop {
+            // Entries by decreasing position, to find the last one which can be moved
+            let mut order = [0usize; E];
+            for (i, o) in order.iter_mut().enumerate() {
+                *o = i;
+            }
+            let entries = self.directory.get_ref().entries;
+            order.sort_unstable_by_key(|&i| core::cmp::Reverse(entries[i].start));
+
+            let mut moved = false;
+            for &index in order.iter() {
+                let entry = entries[index];
+                let count = entry.blocks().len();
+                if entry.is_free() || count == 0 {
+                    continue;
+                }
+                let used = self.used_blocks();
+                let Some(start) = self.find_free_blocks(&used, count, true) else {
+                    continue;
+                };
+                if start >= entry.start as usize {
+                    continue;
+                }
+                let mut value = [0u8; KV_BLOCK_SIZE];
+                let mut directory = *self.directory.get_ref();
+                for (i, block) in entry.blocks().enumerate() {
+                    value.copy_from_slice(self.blocks[block].get_ref());
+                    self.blocks[start + i].update(&value);
+                    directory.wear[start + i] = directory.wear[start + i].saturating_add(1);
+                }
+                directory.entries[index].start = start as u16;
+                self.directory.update(&directory);
+                moved = true;
+                break;
+            }
+            if !moved {
+                return;
+            }
+        }
+    }
+
+    /// Returns an iterator over the entries, as `(key, value)` pairs.
+    pub fn iter(&self) -> KvIterator<'_, E, B> {
+        KvIterator {
+            store: self,
+            next_index: 0,
+        }
+    }
+}
+
+impl<'a, const E: usize, const B: usize> IntoIterator for &'a KvStore<E, B> {
+    type Item = (Key<'a>, &'a [u8]);
+    type IntoIter = KvIterator<'a, E, B>;
+
+    fn into_iter(self) -> KvIterator<'a, E, B> {
+        self.iter()
+    }
+}
+
+pub struct KvIterator<'a, const E: usize, const B: usize> {
+    store: &'a KvStore<E, B>,
+    next_index: usize,
+}
+
+impl<'a, const E: usize, const B: usize> Iterator for KvIterator<'a, E, B> {
+    type Item = (Key<'a>, &'a [u8]);
+
+    fn next(&mut self) -> Option<(Key<'a>, &'a [u8])> {
+        let entries = &self.store.directory.get_ref().entries;
+        while let Some(entry) = entries.get(self.next_index) {
+            self.next_index += 1;
+            if !entry.is_free() {
+                return Some((entry.key(), self.store.value(entry)));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_eq_err as assert_eq;
+    use crate::nvm::{ALIGNED_STORAGE_ALIGN, sim};
+    use crate::testing::TestType;
+    use testmacro::test_item as test;
+
+    type Store = KvStore<4, 4>;
+
+    #[test]
+    fn kv_insert() {
+        let mut store = Store::new();
+        let long = [4u8; KV_BLOCK_SIZE + 1];
+        assert_eq!(store.insert(1u16, &[1, 2, 3]), Ok(()));
+        assert_eq!(store.insert("policy", &long), Ok(()));
+        assert_eq!(store.insert("empty", &[]), Ok(()));
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.get(1u16), Some(&[1, 2, 3][..]));
+        assert_eq!(store.get("policy"), Some(&long[..]));
+        assert_eq!(store.get("empty"), Some(&[][..]));
+        assert_eq!(store.get(2u16), None);
+        assert_eq!(store.contains_key("policy"), true);
+        assert_eq!(store.free_blocks(), 1);
+        assert_eq!(store.iter().count(), 3);
+
+        assert_eq!(store.insert("", &[1]), Err(KvError::InvalidKey));
+        let name = [b'k'; KV_MAX_KEY_LEN + 1];
+        let name = core::str::from_utf8(&name).unwrap();
+        assert_eq!(store.insert(name, &[1]), Err(KvError::InvalidKey));
+        assert_eq!(store.len(), 3);
+    }
+
+    #[test]
+    fn kv_update_remove() {
+        let mut store = Store::new();
+        assert_eq!(store.insert(1u16, &[1]), Ok(()));
+        assert_eq!(store.insert("name", &[2]), Ok(()));
+        assert_eq!(store.insert(1u16, &[3, 4]), Ok(()));
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get(1u16), Some(&[3, 4][..]));
+        // The blocks of the previous value are free again
+        assert_eq!(store.free_blocks(), 2);
+
+        assert_eq!(store.remove(1u16), Ok(()));
+        assert_eq!(store.remove(1u16), Err(KvError::NotFound));
+        assert_eq!(store.get(1u16), None);
+        assert_eq!(store.get("name"), Some(&[2][..]));
+        store.clear();
+        assert_eq!(store.is_empty(), true);
+        assert_eq!(store.free_blocks(), 4);
+    }
+
+    #[test]
+    fn kv_compaction() {
+        let mut store = Store::new();
+        assert_eq!(store.insert(1u16, &[1]), Ok(()));
+        assert_eq!(store.insert(2u16, &[2]), Ok(()));
+        assert_eq!(store.insert(3u16, &[3]), Ok(()));
+        assert_eq!(store.remove(1u16), Ok(()));
+        // The two free blocks are not consecutive: the store is compacted
+        let long = [4u8; KV_BLOCK_SIZE + 1];
+        assert_eq!(store.insert(4u16, &long), Ok(()));
+        assert_eq!(store.get(2u16), Some(&[2][..]));
+        assert_eq!(store.get(3u16), Some(&[3][..]));
+        assert_eq!(store.get(4u16), Some(&long[..]));
+        assert_eq!(store.free_blocks(), 0);
+
+        // Compacting a store without gaps does not move anything
+        assert_eq!(store.remove(4u16), Ok(()));
+        sim::reset();
+        store.compact();
+        assert_eq!(sim::bytes_written(), 0);
+        assert_eq!(store.get(3u16), Some(&[3][..]));
+    }
+
+    #[test]
+    fn kv_out_of_space() {
+        let mut store = Store::new();
+        let long = [5u8; KV_BLOCK_SIZE + 1];
+        assert_eq!(store.insert(1u16, &[1]), Ok(()));
+        assert_eq!(store.insert(2u16, &[2]), Ok(()));
+        assert_eq!(store.insert(3u16, &[3]), Ok(()));
+        // Not enough free blocks
+        assert_eq!(store.insert(4u16, &long), Err(KvError::Full));
+        assert_eq!(store.get(4u16), None);
+        assert_eq!(store.insert(4u16, &[4]), Ok(()));
+        // The new value of an entry is written before freeing the previous one
+        assert_eq!(store.insert(1u16, &[6]), Err(KvError::Full));
+        assert_eq!(store.get(1u16), Some(&[1][..]));
+        // No free entry
+        assert_eq!(store.insert(5u16, &[]), Err(KvError::Full));
+        assert_eq!(store.len(), 4);
+    }
+
+    #[test]
+    fn kv_tearing() {
+        let long = [4u8; KV_BLOCK_SIZE + 1];
+        let mut store = Store::new();
+        let setup = |store: &mut Store| {
+            *store = Store::new();
+            let _ = store.insert(1u16, &[1]);
+            let _ = store.insert(2u16, &[2]);
+            let _ = store.insert(3u16, &[3]);
+            let _ = store.remove(1u16);
+        };
+        setup(&mut store);
+        sim::reset();
+        // The storages assume pages of the size of their alignment
+        sim::set_page_size(ALIGNED_STORAGE_ALIGN);
+        // Moves the value of entry 3, then writes the new value
+        let _ = store.insert(4u16, &long);
+        let total = sim::bytes_written();
+        // Interrupting every few bytes reaches all the steps of each page write,
+        // in a reasonable time.
+        for n in (0..total).step_by(7) {
+            setup(&mut store);
+            sim::fail_after(n);
+            let _ = store.insert(4u16, &long);
+            sim::restore();
+            assert_eq!(store.get(2u16), Some(&[2][..]));
+            assert_eq!(store.get(3u16), Some(&[3][..]));
+            let new = store.get(4u16);
+            assert_eq!(new.is_none() || new == Some(&long[..]), true);
+        }
+        sim::reset();
+    }
+}
@@ -0,0 +1,311 @@
+//! Authenticated, and optionally encrypted, NVM storage.
+//!
+//! [`SecureStorage`] keeps a value along with a MAC computed with a key derived
+//! from the seed, so that values which have not been written by the application
+//! on this device, or which have been modified since, are detected and never
+//! returned:
+//!
+//! ```
+//! use ledger_device_sdk::NVMData;
+//! use ledger_device_sdk::nvm::{SecureStorage, StorageKey};
+//!
+//! #[link_section=".nvm_data"]
+//! static mut POLICY: NVMData<SecureStorage<[u8; 128]>> =
+//!     NVMData::new(SecureStorage::new(true));
+//!
+//! let key = StorageKey::derive(b"Wallet policy")?;
+//! let policy = unsafe { POLICY.get_mut() };
+//! policy.store(&key, &registered_policy)?;
+//! let registered_policy = policy.load(&key)?;
+//! ```
+//!
+//! The MAC key and the encryption key are derived from the node obtained with
+//! SLIP-21 for a label chosen by the application. Using a distinct label for
+//! each storage prevents values from being swapped between storages.
+//!
+//! Values are encrypted with a keystream made of `HMAC-SHA256(enc_key,
+//! counter || block)`, where the counter is incremented on every write. The
+//! MAC, `HMAC-SHA256(mac_key, flags || counter || size || value)`, covers the
+//! encrypted value.
+
+use super::{
+    ATOMIC_STORAGE_ALIGN, AtomicStorage, PlainData, SingleStorage, plain_bytes_mut, plain_zeroed,
+};
+use crate::cipher::apply_hmac_keystream;
+use crate::ecc::CxError;
+use crate::hmac::{HMACError, HMACInit, mac_eq, sha2::Sha2_256};
+use crate::kdf::{MAX_SLIP21_LABEL_LEN, slip21_derive};
+use zeroize::Zeroize;
+
+const KEY_LEN: usize = 32;
+const TAG_LEN: usize = 32;
+
+/// Maximum length of the label given to [`StorageKey::derive`].
+pub const MAX_STORAGE_LABEL_LEN: usize = MAX_SLIP21_LABEL_LEN;
+
+const SEALED_PRESENT: u8 = 0x01;
+const SEALED_ENCRYPTED: u8 = 0x02;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SecureStorageError {
+    /// No value has been stored.
+    Empty,
+    /// The stored value has not been written with this key, or has been modified.
+    Tampered,
+    /// Key derivation or MAC computation failed.
+    Crypto,
+    /// The write counter has reached its maximum: no more values can be
+    /// stored with this key.
+    Exhausted,
+}
+
+impl From<CxError> for SecureStorageError {
+    fn from(_: CxError) -> SecureStorageError {
+        SecureStorageError::Crypto
+    }
+}
+
+impl From<HMACError> for SecureStorageError {
+    fn from(_: HMACError) -> SecureStorageError {
+        SecureStorageError::Crypto
+    }
+}
+
+/// Keys protecting a [`SecureStorage`].
+pub struct StorageKey {
+    enc: [u8; KEY_LEN],
+    mac: [u8; KEY_LEN],
+}
+
+impl Drop for StorageKey {
+    fn drop(&mut self) {
+        self.enc.zeroize();
+        self.mac.zeroize();
+    }
+}
+
+impl StorageKey {
+    /// Derives the keys from the seed, through the SLIP-21 node for `label`.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`CxError::InvalidParameter`] if `label` is longer than
+    /// [`MAX_STORAGE_LABEL_LEN`].
+    pub fn derive(label: &[u8]) -> Result<StorageKey, CxError> {
+        let node = slip21_derive(&[label])?;
+        let mut key = StorageKey {
+            enc: [0; KEY_LEN],
+            mac: [0; KEY_LEN],
+        };
+        Sha2_256::new(node.as_ref())
+            .hmac(b"enc", &mut key.enc)
+            .map_err(|_| CxError::GenericError)?;
+        Sha2_256::new(node.as_ref())
+            .hmac(b"mac", &mut key.mac)
+            .map_err(|_| CxError::GenericError)?;
+        Ok(key)
+    }
+
+    fn apply_keystream(&self, counter: u32, data: &mut [u8]) -> Result<(), HMACError> {
+        apply_hmac_keystream(&self.enc, counter, 0, data)
+    }
+
+    fn tag<T: PlainData>(&self, sealed: &mut Sealed<T>) -> Result<[u8; TAG_LEN], HMACError> {
+        let mut mac = Sha2_256::new(&self.mac);
+        mac.update(&[sealed.flags])?;
+        mac.update(&sealed.counter.to_be_bytes())?;
+        mac.update(&(core::mem::size_of::<T>() as u32).to_be_bytes())?;
+        mac.update(sealed.value_bytes())?;
+        let mut tag = [0u8; TAG_LEN];
+        mac.finalize(&mut tag)?;
+        Ok(tag)
+    }
+}
+
+/// A value stored along with the data authenticating it.
+///
+/// This is the layout used by [`SecureStorage`] in NVM.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct Sealed<T> {
+    flags: u8,
+    counter: u32,
+    value: T,
+    tag: [u8; TAG_LEN],
+}
+
+impl<T: PlainData> Sealed<T> {
+    const fn empty() -> Sealed<T> {
+        Sealed {
+            flags: 0,
+            counter: 0,
+            value: plain_zeroed(),
+            tag: [0; TAG_LEN],
+        }
+    }
+
+    fn value_bytes(&mut self) -> &mut [u8] {
+        plain_bytes_mut(&mut self.value)
+    }
+}
+
+/// Non-Volatile data storage authenticated with a key derived from the seed,
+/// and optionally encrypted.
+///
+/// Updates are atomic. `T` must be [`PlainData`], as its bytes are encrypted
+/// in place.
+pub struct SecureStorage<T> {
+    storage: AtomicStorage<Sealed<T>>,
+    encrypted: bool,
+}
+
+impl<T> SecureStorage<T> {
+    /// Space used in NVM: the `AtomicStorage` of the [`Sealed`] value, followed
+    /// by the `encrypted` flag, which is padded to a page.
+    pub const NVM_SIZE: usize = AtomicStorage::<Sealed<T>>::NVM_SIZE + ATOMIC_STORAGE_ALIGN;
+}
+
+impl<T> SecureStorage<T>
+where
+    T: PlainData,
+{
+    /// Create an empty `SecureStorage<T>`. Values are encrypted if `encrypted`
+    /// is set, otherwise they are only authenticated.
+    pub const fn new(encrypted: bool) -> SecureStorage<T> {
+        SecureStorage {
+            storage: AtomicStorage::new(&Sealed::empty()),
+            encrypted,
+        }
+    }
+
+    /// Returns true if values are encrypted.
+    pub fn is_encrypted(&self) -> bool {
+        self.encrypted
+    }
+
+    /// Returns true if no value has been stored.
+    pub fn is_empty(&self) -> bool {
+        self.storage.get_ref().flags & SEALED_PRESENT == 0
+    }
+
+    /// Returns the stored value, after checking it has been written with `key`
+    /// and not modified since.
+    ///
+    /// # Errors
+    ///
+    /// * [`SecureStorageError::Empty`] if no value has been stored,
+    /// * [`SecureStorageError::Tampered`] if the value cannot be authenticated.
+    pub fn load(&self, key: &StorageKey) -> Result<T, SecureStorageError> {
+        let mut sealed = *self.storage.get_ref();
+        if sealed.flags & SEALED_PRESENT == 0 {
+            return Err(SecureStorageError::Empty);
+        }
+        let expected = key.tag(&mut sealed)?;
+        if !mac_eq(&expected, &sealed.tag) {
+            return Err(SecureStorageError::Tampered);
+        }
+        if sealed.flags & SEALED_ENCRYPTED != 0 {
+            key.apply_keystream(sealed.counter, sealed.value_bytes())?;
+        }
+        Ok(sealed.value)
+    }
+
+    /// Authenticates, encrypts if enabled, and stores `value`.
+    /// This operation is atomic.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`SecureStorageError::Exhausted`] once `u32::MAX` values
+    /// have been written.
+    pub fn store(&mut self, key: &StorageKey, value: &T) -> Result<(), SecureStorageError> {
+        // The counter is never reused, so that no keystream is used twice
+        let counter = self
+            .storage
+            .get_ref()
+            .counter
+            .checked_add(1)
+            .ok_or(SecureStorageError::Exhausted)?;
+        let mut sealed = Sealed {
+            flags: SEALED_PRESENT,
+            counter,
+            value: *value,
+            tag: [0; TAG_LEN],
+        };
+        if self.encrypted {
+            sealed.flags |= SEALED_ENCRYPTED;
+            key.apply_keystream(sealed.counter, sealed.value_bytes())?;
+        }
+        sealed.tag = key.tag(&mut sealed)?;
+        self.storage.update(&sealed);
+        Ok(())
+    }
+
+    /// Erases the stored value. The write counter is kept, so that the
+    /// following writes do not reuse it.
+    /// This operation is atomic.
+    pub fn clear(&mut self) {
+        let mut sealed = Sealed::empty();
+        sealed.counter = self.storage.get_ref().counter;
+        self.storage.update(&sealed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_eq_err as assert_eq;
+    use crate::testing::TestType;
+    use testmacro::test_item as test;
+
+    #[test]
+    fn secure_storage_roundtrip() {
+        let key = StorageKey::derive(b"Secure storage test").unwrap();
+        for encrypted in [false, true] {
+            let mut storage: SecureStorage<[u8; 40]> = SecureStorage::new(encrypted);
+            assert_eq!(storage.load(&key), Err(SecureStorageError::Empty));
+            assert_eq!(storage.store(&key, &[0x42; 40]), Ok(()));
+            assert_eq!(storage.load(&key), Ok([0x42; 40]));
+            assert_eq!(storage.storage.get_ref().value == [0x42; 40], !encrypted);
+            storage.clear();
+            assert_eq!(storage.is_empty(), true);
+        }
+    }
+
+    #[test]
+    fn secure_storage_counter_not_reused() {
+        let key = StorageKey::derive(b"Secure storage test").unwrap();
+        let mut storage: SecureStorage<[u8; 40]> = SecureStorage::new(true);
+        assert_eq!(storage.store(&key, &[0x42; 40]), Ok(()));
+        let first = *storage.storage.get_ref();
+        storage.clear();
+        assert_eq!(storage.storage.get_ref().counter, first.counter);
+        assert_eq!(storage.store(&key, &[0x42; 40]), Ok(()));
+        let second = *storage.storage.get_ref();
+        assert_eq!(second.counter, first.counter + 1);
+        // Same plaintext, different keystreams
+        assert_eq!(first.value != second.value, true);
+        assert_eq!(storage.load(&key), Ok([0x42; 40]));
+
+        let mut sealed = second;
+        sealed.counter = u32::MAX;
+        storage.storage.update(&sealed);
+        assert_eq!(
+            storage.store(&key, &[0x42; 40]),
+            Err(SecureStorageError::Exhausted)
+        );
+    }
+
+    #[test]
+    fn secure_storage_tampering() {
+        let key = StorageKey::derive(b"Secure storage test").unwrap();
+        let other_key = StorageKey::derive(b"Other storage").unwrap();
+        let mut storage: SecureStorage<[u8; 40]> = SecureStorage::new(true);
+        assert_eq!(storage.store(&key, &[0x42; 40]), Ok(()));
+        assert_eq!(storage.load(&other_key), Err(SecureStorageError::Tampered));
+
+        let mut sealed = *storage.storage.get_ref();
+        sealed.value[0] ^= 1;
+        storage.storage.update(&sealed);
+        assert_eq!(storage.load(&key), Err(SecureStorageError::Tampered));
+    }
+}
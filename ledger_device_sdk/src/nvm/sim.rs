@@ -0,0 +1,259 @@
+//! In-memory NVM backend with tearing injection, to test storage code.
+//!
+//! With the `nvm_sim` feature (and in the SDK unit tests), the storage types of
+//! the [`nvm`](super) module write through [`nvm_write`] instead of the
+//! `nvm_write` syscall, so they can be instantiated in RAM, e.g. as local
+//! variables of a test.
+//!
+//! Writes follow the semantics of the Flash memory: every page they touch is
+//! erased, then programmed again from its beginning with the updated content.
+//! A power loss is simulated with [`fail_after`]: once the given number of
+//! bytes have been programmed, the write is interrupted, leaving the rest of
+//! the page erased, and all the following writes are dropped until
+//! [`restore`] is called. Checking a storage against every possible
+//! interruption point looks like:
+//!
+//! ```
+//! use ledger_device_sdk::nvm::{AtomicStorage, SingleStorage, sim};
+//!
+//! sim::reset();
+//! sim::set_page_size(64);
+//! let mut storage = AtomicStorage::new(&[1u8; 16]);
+//! storage.update(&[2u8; 16]);
+//! for n in 0..sim::bytes_written() {
+//!     let mut storage = AtomicStorage::new(&[1u8; 16]);
+//!     sim::fail_after(n);
+//!     storage.update(&[2u8; 16]);
+//!     sim::restore();
+//!     assert!(matches!(storage.get_ref(), [1, ..] | [2, ..]));
+//! }
+//! ```
+//!
+//! As erasing a page also erases its bytes which are not part of the storage,
+//! storages must be aligned on the page size. This is the case of
+//! [`AtomicStorage`](super::AtomicStorage) and of the types containing one,
+//! with pages of up to [`PAGE_SIZE`] bytes.
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Size of the pages aligned with `AtomicStorage`, and default page size of
+/// the simulator.
+#[cfg(target_os = "nanox")]
+pub const PAGE_SIZE: usize = 256;
+#[cfg(not(target_os = "nanox"))]
+pub const PAGE_SIZE: usize = 512;
+
+/// Value of erased bytes.
+pub const ERASED: u8 = 0xff;
+
+const MAX_PAGE_SIZE: usize = 512;
+
+static PAGE: AtomicUsize = AtomicUsize::new(PAGE_SIZE);
+/// Number of bytes which can still be programmed before a power loss.
+static BUDGET: AtomicUsize = AtomicUsize::new(usize::MAX);
+static TORN: AtomicBool = AtomicBool::new(false);
+static WRITTEN: AtomicUsize = AtomicUsize::new(0);
+
+/// Restores the default page size, disables fault injection and resets the
+/// count of written bytes.
+pub fn reset() {
+    PAGE.store(PAGE_SIZE, Ordering::Relaxed);
+    BUDGET.store(usize::MAX, Ordering::Relaxed);
+    TORN.store(false, Ordering::Relaxed);
+    WRITTEN.store(0, Ordering::Relaxed);
+}
+
+/// Sets the size of the pages erased by writes.
+///
+/// # Panics
+///
+/// Panics if `size` is not a power of two, or is greater than 512.
+pub fn set_page_size(size: usize) {
+    assert!(size.is_power_of_two() && size <= MAX_PAGE_SIZE);
+    PAGE.store(size, Ordering::Relaxed);
+}
+
+/// Interrupts writes after `bytes` more bytes have been programmed.
+pub fn fail_after(bytes: usize) {
+    BUDGET.store(bytes, Ordering::Relaxed);
+    TORN.store(false, Ordering::Relaxed);
+}
+
+/// Ends the simulated power loss: writes are performed again, without fault
+/// injection.
+///
+/// Returns true if a write has been interrupted since [`fail_after`].
+pub fn restore() -> bool {
+    BUDGET.store(usize::MAX, Ordering::Relaxed);
+    // No read-modify-write: thumbv6m targets have no atomic compare-and-swap
+    let torn = TORN.load(Ordering::Relaxed);
+    TORN.store(false, Ordering::Relaxed);
+    torn
+}
+
+/// Returns the number of bytes programmed since the last [`reset`], including
+/// the bytes of the erased pages which have been programmed again.
+///
+/// This is the number of points at which a write can be interrupted.
+pub fn bytes_written() -> usize {
+    WRITTEN.load(Ordering::Relaxed)
+}
+
+/// Programs one byte, unless the power is lost.
+fn program(dst: *mut u8, value: u8) -> bool {
+    let budget = BUDGET.load(Ordering::Relaxed);
+    if budget == 0 {
+        TORN.store(true, Ordering::Relaxed);
+        return false;
+    }
+    if budget != usize::MAX {
+        BUDGET.store(budget - 1, Ordering::Relaxed);
+    }
+    WRITTEN.store(WRITTEN.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+    unsafe { dst.write_volatile(value) };
+    true
+}
+
+/// Replacement of the `nvm_write` syscall.
+///
+/// # Safety
+///
+/// `dst_adr` must be valid for writes of `src_len` bytes, as well as the
+/// pages containing them, and `src_adr` valid for reads of `src_len` bytes.
+pub unsafe fn nvm_write(
+    dst_adr: *mut core::ffi::c_void,
+    src_adr: *mut core::ffi::c_void,
+    src_len: u32,
+) {
+    if TORN.load(Ordering::Relaxed) {
+        return;
+    }
+    let page_size = PAGE.load(Ordering::Relaxed);
+    let start = dst_adr as usize;
+    let end = start + src_len as usize;
+    let src = unsafe { core::slice::from_raw_parts(src_adr as *const u8, src_len as usize) };
+    let mut content = [0u8; MAX_PAGE_SIZE];
+    let mut page = start & !(page_size - 1);
+    while page < end {
+        let ptr = page as *mut u8;
+        let content = &mut content[..page_size];
+        for (i, byte) in content.iter_mut().enumerate() {
+            let addr = page + i;
+            *byte = if (start..end).contains(&addr) {
+                src[addr - start]
+            } else {
+                unsafe { ptr.add(i).read_volatile() }
+            };
+        }
+        for i in 0..page_size {
+            unsafe { ptr.add(i).write_volatile(ERASED) };
+        }
+        for (i, byte) in content.iter().enumerate() {
+            if !program(unsafe { ptr.add(i) }, *byte) {
+                return;
+            }
+        }
+        page += page_size;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_eq_err as assert_eq;
+    use crate::nvm::{AtomicStorage, Collection, SafeStorage, SingleStorage};
+    use crate::testing::TestType;
+    use testmacro::test_item as test;
+
+    /// Page-aligned container, so that erasing pages does not touch other
+    /// variables.
+    #[repr(align(512))]
+    struct Page<T>(T);
+
+    // The storage types assume pages of 64 bytes, the alignment of `AlignedStorage`.
+    const STORAGE_PAGE_SIZE: usize = 64;
+
+    #[test]
+    fn sim_interrupted_write() {
+        reset();
+        set_page_size(STORAGE_PAGE_SIZE);
+        let mut page = Page([0u8; 128]);
+        fail_after(70);
+        unsafe {
+            nvm_write(
+                page.0[10..].as_mut_ptr() as *mut core::ffi::c_void,
+                [0x11u8; 100].as_ptr() as *mut core::ffi::c_void,
+                100,
+            );
+        }
+        assert_eq!(restore(), true);
+        assert_eq!(page.0[..10], [0; 10]);
+        assert_eq!(page.0[10..70], [0x11; 60]);
+        assert_eq!(page.0[70..], [ERASED; 58]);
+        assert_eq!(bytes_written(), 70);
+    }
+
+    #[test]
+    fn sim_safe_storage_tearing() {
+        reset();
+        set_page_size(STORAGE_PAGE_SIZE);
+        let mut storage = Page(SafeStorage::new([1u8; 16]));
+        storage.0.update(&[2u8; 16]);
+        let total = bytes_written();
+        for n in 0..total {
+            let mut storage = Page(SafeStorage::new([1u8; 16]));
+            fail_after(n);
+            storage.0.update(&[2u8; 16]);
+            assert_eq!(restore(), true);
+            // Either the interruption is detected, or the update was complete
+            assert_eq!(
+                !storage.0.is_valid() || *storage.0.get_ref() == [2; 16],
+                true
+            );
+        }
+    }
+
+    #[test]
+    fn sim_atomic_storage_tearing() {
+        reset();
+        set_page_size(STORAGE_PAGE_SIZE);
+        let mut storage = AtomicStorage::new(&[1u8; 16]);
+        storage.update(&[2u8; 16]);
+        let total = bytes_written();
+        for n in 0..total {
+            let mut storage = AtomicStorage::new(&[1u8; 16]);
+            fail_after(n);
+            storage.update(&[2u8; 16]);
+            restore();
+            let value = *storage.get_ref();
+            assert_eq!(value == [1; 16] || value == [2; 16], true);
+        }
+    }
+
+    #[test]
+    fn sim_collection_tearing() {
+        reset();
+        set_page_size(STORAGE_PAGE_SIZE);
+        let mut collection: Collection<u32, 4> = Collection::new(0);
+        let _ = collection.add(&1);
+        let _ = collection.add(&2);
+        reset();
+        set_page_size(STORAGE_PAGE_SIZE);
+        let _ = collection.update(0, &3);
+        collection.remove(1);
+        let total = bytes_written();
+        for n in 0..total {
+            let mut collection: Collection<u32, 4> = Collection::new(0);
+            let _ = collection.add(&1);
+            let _ = collection.add(&2);
+            fail_after(n);
+            let _ = collection.update(0, &3);
+            collection.remove(1);
+            restore();
+            let first = collection.get(0).copied();
+            assert_eq!(first == Some(1) || first == Some(3), true);
+            let second = collection.get(1).copied();
+            assert_eq!(second == Some(2) || second.is_none(), true);
+        }
+    }
+}
@@ -0,0 +1,447 @@
+//! Typed application settings persisted in NVM.
+//!
+//! Settings are declared once, as a table of [`Setting`]s, along with typed
+//! identifiers used to read and write their values:
+//!
+//! ```
+//! use ledger_device_sdk::NVMData;
+//! use ledger_device_sdk::settings::*;
+//!
+//! const BLIND_SIGNING: Switch = Switch(0);
+//! const ADDRESS_FORMAT: Choice = Choice(1);
+//! const LOCK_DELAY: Range = Range(2);
+//!
+//! static SETTINGS: [Setting; 3] = [
+//!     Setting::switch("Blind signing", "Enable blind signing", false),
+//!     Setting::choice("Address format", "Displayed addresses", &["Legacy", "Segwit"], 1),
+//!     Setting::range("Lock delay", "Minutes before locking", 1, 10, 1, 5),
+//! ];
+//!
+//! #[link_section=".nvm_data"]
+//! static mut DATA: NVMData<SettingsStorage<3>> =
+//!     NVMData::new(SettingsStorage::new(&SETTINGS));
+//!
+//! let storage = unsafe { DATA.get_mut() };
+//! // Values stored by a previous version may not be valid anymore
+//! storage.sanitize(&SETTINGS);
+//! if storage.get(BLIND_SIGNING) {
+//!     // ...
+//! }
+//! storage.set(&SETTINGS, LOCK_DELAY, 3).unwrap();
+//! ```
+//!
+//! The same table is used to display the settings, with
+//! `NbglHomeAndSettings::typed_settings` on NBGL devices, or with
+//! `ui::gadgets::SettingsMenu` on Nano S+ and Nano X.
+
+use crate::nvm::{AtomicStorage, SingleStorage};
+use numtoa::NumToA;
+
+/// Size of the buffer given to [`Setting::label`].
+pub const SETTING_LABEL_LEN: usize = 5;
+
+/// Type, and possible values, of a [`Setting`].
+#[derive(Copy, Clone)]
+pub enum SettingKind {
+    /// On/off switch, stored as 1 or 0.
+    Switch { default: bool },
+    /// One option among a list, stored as the index of the option.
+    Choice {
+        options: &'static [&'static str],
+        default: u8,
+    },
+    /// Number from `min` to `max`, by steps of `step`.
+    Range {
+        min: u16,
+        max: u16,
+        step: u16,
+        default: u16,
+    },
+}
+
+/// Declaration of a setting.
+#[derive(Copy, Clone)]
+pub struct Setting {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub kind: SettingKind,
+}
+
+impl Setting {
+    pub const fn switch(name: &'static str, description: &'static str, default: bool) -> Self {
+        Setting {
+            name,
+            description,
+            kind: SettingKind::Switch { default },
+        }
+    }
+
+    /// # Panics
+    ///
+    /// Panics if there are more than 255 options, or if `default` is not the
+    /// index of one of them.
+    pub const fn choice(
+        name: &'static str,
+        description: &'static str,
+        options: &'static [&'static str],
+        default: u8,
+    ) -> Self {
+        assert!(options.len() <= u8::MAX as usize && (default as usize) < options.len());
+        Setting {
+            name,
+            description,
+            kind: SettingKind::Choice { options, default },
+        }
+    }
+
+    /// # Panics
+    ///
+    /// Panics if `step` is zero, if the range has more than 255 values, or if
+    /// `default` is not one of them.
+    pub const fn range(
+        name: &'static str,
+        description: &'static str,
+        min: u16,
+        max: u16,
+        step: u16,
+        default: u16,
+    ) -> Self {
+        assert!(step != 0 && min <= max && (max - min) / step < u8::MAX as u16);
+        assert!(default >= min && default <= max && (default - min) % step == 0);
+        Setting {
+            name,
+            description,
+            kind: SettingKind::Range {
+                min,
+                max,
+                step,
+                default,
+            },
+        }
+    }
+
+    /// Returns the stored representation of the default value.
+    pub const fn default_value(&self) -> u16 {
+        match self.kind {
+            SettingKind::Switch { default } => default as u16,
+            SettingKind::Choice { default, .. } => default as u16,
+            SettingKind::Range { default, .. } => default,
+        }
+    }
+
+    /// Returns the number of possible values.
+    pub fn value_count(&self) -> usize {
+        match self.kind {
+            SettingKind::Switch { .. } => 2,
+            SettingKind::Choice { options, .. } => options.len(),
+            SettingKind::Range { min, max, step, .. } => ((max - min) / step) as usize + 1,
+        }
+    }
+
+    /// Returns the stored representation of the possible value at `position`,
+    /// in display order.
+    pub fn value_at(&self, position: usize) -> u16 {
+        match self.kind {
+            SettingKind::Switch { .. } | SettingKind::Choice { .. } => position as u16,
+            SettingKind::Range { min, step, .. } => min + position as u16 * step,
+        }
+    }
+
+    /// Returns the position of `value` among the possible values, or None if
+    /// it is not valid.
+    pub fn position_of(&self, value: u16) -> Option<usize> {
+        match self.kind {
+            SettingKind::Switch { .. } | SettingKind::Choice { .. } => {
+                Some(value as usize).filter(|&p| p < self.value_count())
+            }
+            SettingKind::Range { min, max, step, .. } => {
+                (value >= min && value <= max && (value - min) % step == 0)
+                    .then(|| ((value - min) / step) as usize)
+            }
+        }
+    }
+
+    pub fn is_valid(&self, value: u16) -> bool {
+        self.position_of(value).is_some()
+    }
+
+    /// Returns the text displayed for `value`.
+    pub fn label<'b>(&self, value: u16, buf: &'b mut [u8; SETTING_LABEL_LEN]) -> &'b str {
+        match self.kind {
+            SettingKind::Switch { .. } => match value {
+                0 => "Disabled",
+                _ => "Enabled",
+            },
+            SettingKind::Choice { options, .. } => options.get(value as usize).unwrap_or(&""),
+            SettingKind::Range { .. } => value.numtoa_str(10, buf),
+        }
+    }
+}
+
+/// Error returned when a setting value cannot be stored.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SettingError {
+    /// The index is out of the table of settings.
+    UnknownSetting,
+    /// The identifier does not match the kind of the setting.
+    KindMismatch,
+    /// The value is not one of the possible values of the setting.
+    InvalidValue,
+}
+
+/// Typed identifier of a setting, used to access its value.
+pub trait SettingId: Copy {
+    type Value;
+    /// Index of the setting in its table.
+    fn index(self) -> usize;
+    /// Returns true if this identifier can access settings of `kind`.
+    fn matches(kind: &SettingKind) -> bool;
+    fn decode(raw: u16) -> Self::Value;
+    fn encode(value: Self::Value) -> u16;
+}
+
+/// Identifier of a [`Setting::switch`].
+#[derive(Copy, Clone)]
+pub struct Switch(pub usize);
+
+/// Identifier of a [`Setting::choice`], whose value is the index of the option.
+#[derive(Copy, Clone)]
+pub struct Choice(pub usize);
+
+/// Identifier of a [`Setting::range`].
+#[derive(Copy, Clone)]
+pub struct Range(pub usize);
+
+impl SettingId for Switch {
+    type Value = bool;
+    fn index(self) -> usize {
+        self.0
+    }
+    fn matches(kind: &SettingKind) -> bool {
+        matches!(kind, SettingKind::Switch { .. })
+    }
+    fn decode(raw: u16) -> bool {
+        raw != 0
+    }
+    fn encode(value: bool) -> u16 {
+        value as u16
+    }
+}
+
+impl SettingId for Choice {
+    type Value = u8;
+    fn index(self) -> usize {
+        self.0
+    }
+    fn matches(kind: &SettingKind) -> bool {
+        matches!(kind, SettingKind::Choice { .. })
+    }
+    fn decode(raw: u16) -> u8 {
+        raw as u8
+    }
+    fn encode(value: u8) -> u16 {
+        value as u16
+    }
+}
+
+impl SettingId for Range {
+    type Value = u16;
+    fn index(self) -> usize {
+        self.0
+    }
+    fn matches(kind: &SettingKind) -> bool {
+        matches!(kind, SettingKind::Range { .. })
+    }
+    fn decode(raw: u16) -> u16 {
+        raw
+    }
+    fn encode(value: u16) -> u16 {
+        value
+    }
+}
+
+/// Access to the stored values of settings, by index.
+///
+/// This is used by the UI code, which does not depend on the number of settings.
+pub trait SettingValues {
+    fn value(&self, index: usize) -> u16;
+    /// Stores `value` for the setting at `index` in `settings`, if it is one
+    /// of its possible values.
+    fn set_value(
+        &mut self,
+        settings: &[Setting],
+        index: usize,
+        value: u16,
+    ) -> Result<(), SettingError>;
+}
+
+/// Values of `N` settings, stored in NVM with atomic updates.
+pub struct SettingsStorage<const N: usize> {
+    values: AtomicStorage<[u16; N]>,
+}
+
+impl<const N: usize> SettingsStorage<N> {
+    /// Space used in NVM: an `AtomicStorage` of `N` values.
+    pub const NVM_SIZE: usize = AtomicStorage::<[u16; N]>::NVM_SIZE;
+
+    /// Create a `SettingsStorage` initialized with the default values of `settings`.
+    pub const fn new(settings: &[Setting; N]) -> Self {
+        let mut values = [0u16; N];
+        let mut i = 0;
+        while i < N {
+            values[i] = settings[i].default_value();
+            i += 1;
+        }
+        SettingsStorage {
+            values: AtomicStorage::new(&values),
+        }
+    }
+
+    pub fn get<I: SettingId>(&self, id: I) -> I::Value {
+        I::decode(self.value(id.index()))
+    }
+
+    /// Stores the value of the setting `id` of `settings`.
+    ///
+    /// Returns an error, and leaves the stored value unchanged, if `id` does
+    /// not match the kind of the setting, or if `value` is not valid for it.
+    pub fn set<I: SettingId>(
+        &mut self,
+        settings: &[Setting; N],
+        id: I,
+        value: I::Value,
+    ) -> Result<(), SettingError> {
+        let setting = settings
+            .get(id.index())
+            .ok_or(SettingError::UnknownSetting)?;
+        if !I::matches(&setting.kind) {
+            return Err(SettingError::KindMismatch);
+        }
+        self.set_value(settings, id.index(), I::encode(value))
+    }
+
+    /// Resets the values which are not valid for `settings` to their default.
+    ///
+    /// Returns true if some values have been reset.
+    pub fn sanitize(&mut self, settings: &[Setting; N]) -> bool {
+        let mut values = *self.values.get_ref();
+        let mut changed = false;
+        for (value, setting) in values.iter_mut().zip(settings.iter()) {
+            if !setting.is_valid(*value) {
+                *value = setting.default_value();
+                changed = true;
+            }
+        }
+        if changed {
+            self.values.update(&values);
+        }
+        changed
+    }
+
+    /// Resets all the values to their default.
+    pub fn reset(&mut self, settings: &[Setting; N]) {
+        self.values.update(&settings.map(|s| s.default_value()));
+    }
+}
+
+impl<const N: usize> SettingValues for SettingsStorage<N> {
+    fn value(&self, index: usize) -> u16 {
+        self.values.get_ref()[index]
+    }
+
+    fn set_value(
+        &mut self,
+        settings: &[Setting],
+        index: usize,
+        value: u16,
+    ) -> Result<(), SettingError> {
+        let setting = settings
+            .get(index)
+            .filter(|_| index < N)
+            .ok_or(SettingError::UnknownSetting)?;
+        if !setting.is_valid(value) {
+            return Err(SettingError::InvalidValue);
+        }
+        let mut values = *self.values.get_ref();
+        if values[index] != value {
+            values[index] = value;
+            self.values.update(&values);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_eq_err as assert_eq;
+    use crate::testing::TestType;
+    use testmacro::test_item as test;
+
+    const BLIND_SIGNING: Switch = Switch(0);
+    const FORMAT: Choice = Choice(1);
+    const DELAY: Range = Range(2);
+
+    static SETTINGS: [Setting; 3] = [
+        Setting::switch("Blind signing", "", true),
+        Setting::choice("Format", "", &["A", "B", "C"], 2),
+        Setting::range("Delay", "", 5, 30, 5, 10),
+    ];
+
+    #[test]
+    fn settings_values() {
+        let mut storage = SettingsStorage::new(&SETTINGS);
+        assert_eq!(storage.get(BLIND_SIGNING), true);
+        assert_eq!(storage.get(FORMAT), 2);
+        assert_eq!(storage.get(DELAY), 10);
+
+        assert_eq!(storage.set(&SETTINGS, BLIND_SIGNING, false), Ok(()));
+        assert_eq!(storage.set(&SETTINGS, DELAY, 25), Ok(()));
+        assert_eq!(storage.get(BLIND_SIGNING), false);
+        assert_eq!(storage.get(DELAY), 25);
+        assert_eq!(storage.sanitize(&SETTINGS), false);
+
+        // Values stored by a previous version of the table
+        storage.values.update(&[0, 3, 12]);
+        assert_eq!(storage.sanitize(&SETTINGS), true);
+        assert_eq!(storage.get(FORMAT), 2);
+        assert_eq!(storage.get(DELAY), 10);
+        assert_eq!(storage.get(BLIND_SIGNING), false);
+    }
+
+    #[test]
+    fn settings_set_errors() {
+        let mut storage = SettingsStorage::new(&SETTINGS);
+        assert_eq!(
+            storage.set(&SETTINGS, FORMAT, 3),
+            Err(SettingError::InvalidValue)
+        );
+        assert_eq!(
+            storage.set(&SETTINGS, DELAY, 12),
+            Err(SettingError::InvalidValue)
+        );
+        assert_eq!(
+            storage.set(&SETTINGS, Switch(1), true),
+            Err(SettingError::KindMismatch)
+        );
+        assert_eq!(
+            storage.set(&SETTINGS, Range(3), 5),
+            Err(SettingError::UnknownSetting)
+        );
+        assert_eq!(storage.get(FORMAT), 2);
+        assert_eq!(storage.get(DELAY), 10);
+        assert_eq!(storage.get(BLIND_SIGNING), true);
+    }
+
+    #[test]
+    fn settings_positions() {
+        let delay = &SETTINGS[2];
+        assert_eq!(delay.value_count(), 6);
+        assert_eq!(delay.value_at(3), 20);
+        assert_eq!(delay.position_of(20), Some(3));
+        assert_eq!(delay.position_of(21), None);
+        let mut buf = [0u8; SETTING_LABEL_LEN];
+        assert_eq!(delay.label(20, &mut buf), "20");
+        assert_eq!(SETTINGS[1].label(1, &mut buf), "B");
+    }
+}
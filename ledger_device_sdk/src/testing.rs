@@ -131,10 +131,10 @@ pub(crate) fn handle_stack_consumption_apdu(p1: u8, p2: u8, com: &mut crate::io_
 
 /// Handle the stack consumption BOLOS APDU (INS=0x57) for io_new.
 #[cfg(all(feature = "stack_usage", feature = "io_new"))]
-pub(crate) fn handle_stack_consumption_apdu_new<const N: usize>(
+pub(crate) fn handle_stack_consumption_apdu_new<const N: usize, T: crate::io_new::Transport>(
     p1: u8,
     p2: u8,
-    comm: &mut crate::io_new::Comm<N>,
+    comm: &mut crate::io_new::Comm<N, T>,
 ) {
     use crate::io_new::StatusWords;
 
@@ -240,6 +240,17 @@ pub struct TestType {
     pub f: fn() -> Result<(), ()>,
 }
 
+/// Decodes the hexadecimal string `s` into an array, for test vectors.
+/// Panics if `s` is too short or not hexadecimal.
+#[cfg(feature = "unit_test")]
+pub fn hex<const N: usize>(s: &str) -> [u8; N] {
+    let mut out = [0u8; N];
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).unwrap();
+    }
+    out
+}
+
 /// Custom test runner that uses non-formatting print functions
 /// using semihosting. Only reports 'Ok' or 'fail'.
 #[cfg(feature = "unit_test")]
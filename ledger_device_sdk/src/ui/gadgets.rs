@@ -1,6 +1,7 @@
 use crate::{
     buttons::ButtonEvent::*,
     io::{self, ApduHeader, Comm, Event, Reply},
+    settings::{SETTING_LABEL_LEN, Setting, SettingValues, SettingsStorage},
     uxapp::{BOLOS_UX_OK, UxEvent},
 };
 use ledger_secure_sdk_sys::{
@@ -337,6 +338,81 @@ impl<'a> Menu<'a> {
     }
 }
 
+const MAX_SETTINGS_MENU: usize = 16;
+
+/// A menu listing typed settings, in which each setting can be selected
+/// and its value changed.
+/// The last entry, "Back", exits the menu.
+pub struct SettingsMenu<'a, const N: usize> {
+    settings: &'a [Setting; N],
+}
+
+impl<'a, const N: usize> SettingsMenu<'a, N> {
+    pub fn new(settings: &'a [Setting; N]) -> Self {
+        assert!(N < MAX_SETTINGS_MENU, "Too many settings.");
+        SettingsMenu { settings }
+    }
+
+    /// Display the menu until "Back" is selected. Changed values are
+    /// written to `storage`.
+    pub fn show(&self, storage: &mut SettingsStorage<N>) {
+        let mut panels = [""; MAX_SETTINGS_MENU];
+        for (panel, setting) in panels.iter_mut().zip(self.settings.iter()) {
+            *panel = setting.name;
+        }
+        panels[N] = "Back";
+        let menu = Menu::new(&panels[..=N]);
+
+        loop {
+            let index = menu.show();
+            if index == N {
+                return;
+            }
+            self.edit(index, storage);
+        }
+    }
+
+    /// Display the value of a setting, which is changed with the left and
+    /// right buttons and saved when both buttons are pressed.
+    fn edit(&self, index: usize, storage: &mut SettingsStorage<N>) {
+        let setting = &self.settings[index];
+        let count = setting.value_count();
+        let mut position = setting.position_of(storage.value(index)).unwrap_or(0);
+        let mut buttons = ButtonsState::new();
+
+        let draw = |position: usize| {
+            let mut buf = [0u8; SETTING_LABEL_LEN];
+            let label = setting.label(setting.value_at(position), &mut buf);
+            clear_screen();
+            Page::new(PageStyle::BoldNormal, [setting.name, label], None).place();
+            LEFT_ARROW.display();
+            RIGHT_ARROW.display();
+            crate::ui::screen_util::screen_update();
+        };
+
+        draw(position);
+
+        loop {
+            match get_event(&mut buttons) {
+                Some(ButtonEvent::LeftButtonRelease) => {
+                    position = (position + count - 1) % count;
+                    draw(position);
+                }
+                Some(ButtonEvent::RightButtonRelease) => {
+                    position = (position + 1) % count;
+                    draw(position);
+                }
+                Some(ButtonEvent::BothButtonsRelease) => {
+                    // `value_at` only returns valid values
+                    let _ = storage.set_value(self.settings, index, setting.value_at(position));
+                    return;
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
 #[derive(Copy, Clone, PartialEq, Default)]
 pub enum PageStyle {
     #[default]
@@ -565,6 +641,9 @@ impl<'a> MultiPageMenu<'a> {
                 io::Event::Ticker => {
                     if UxEvent::Event.request() != BOLOS_UX_OK {
                         // pin lock management
+                        // The APDU received while locked, if any, is consumed
+                        // rather than left pending for `next_event`.
+                        #[allow(deprecated)]
                         UxEvent::block_and_get_event::<Temp>(self.comm);
                         // notify Ticker event only when redisplay is required
                         return EventOrPageIndex::Event(io::Event::Ticker);
@@ -1,6 +1,7 @@
 use ledger_secure_sdk_sys::seph as sys_seph;
 use ledger_secure_sdk_sys::*;
 
+use crate::io::IoComm;
 #[cfg(not(feature = "io_new"))]
 use crate::io::Reply;
 
@@ -80,7 +81,28 @@ impl UxEvent {
         ret
     }
 
+    /// Blocks until the UX task is done, processing the events received meanwhile
+    /// through `comm`.
+    ///
+    /// Returns the last UX status, and whether the wait has been interrupted by an
+    /// application APDU, which is then kept pending in `comm`.
+    pub fn block_with_comm<C: IoComm + ?Sized>(comm: &mut C) -> (u32, bool) {
+        let mut ret = unsafe { os_sched_last_status(TASK_BOLOS_UX as u32) } as u32;
+        while ret == BOLOS_UX_IGNORE || ret == BOLOS_UX_CONTINUE {
+            let apdu = comm.poll_apdu();
+
+            UxEvent::Event.request();
+
+            if apdu {
+                return (ret, true);
+            }
+            ret = unsafe { os_sched_last_status(TASK_BOLOS_UX as u32) } as u32;
+        }
+        (ret, false)
+    }
+
     #[cfg(not(feature = "io_new"))]
+    #[deprecated(note = "use `UxEvent::block_with_comm`, which works with both io implementations")]
     pub fn block_and_get_event<T>(comm: &mut Comm) -> (u32, Option<Event<T>>)
     where
         T: TryFrom<ApduHeader>,